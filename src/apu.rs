@@ -0,0 +1,873 @@
+use serde::{Deserialize, Serialize};
+use common::*;
+
+// [APU (Audio Processing Unit)]
+// https://gbdev.io/pandocs/Audio.html
+// https://gbdev.io/pandocs/Audio_Registers.html
+//
+// CH1: 矩形波 + スイープ    (NR10~NR14)
+// CH2: 矩形波               (NR21~NR24)
+// CH3: 波形メモリ           (NR30~NR34, Wave RAM $FF30~$FF3F)
+// CH4: ノイズ (LFSR)        (NR41~NR44)
+// NR50: マスターボリューム、NR51: L/Rのパン、NR52: 電源 + 各チャンネルの状態
+//
+// 出力は4T-cycle(1M-cycle)毎に1サンプル、L/Rインターリーブのf32(-1.0~1.0)
+pub const SAMPLE_RATE: u32 = 1_048_576;
+// 取り出されずに溜まったサンプルの上限 (約1秒分、超えた分は捨てる)
+const MAX_SAMPLES: usize = SAMPLE_RATE as usize * 2;
+
+const NR10_ADDR: u16 = 0xFF10;
+const NR52_ADDR: u16 = 0xFF26;
+const WAVE_RAM_ADDR: u16 = 0xFF30;
+const WAVE_RAM_SIZE: usize = 16;
+
+// レジスタの読み出しマスク ($FF10~$FF2F、未使用ビットは1が読める)
+const READ_MASK: [u8; 0x20] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10~NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // ($FF15), NR21~NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30~NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // ($FF1F), NR41~NR44
+    0x00, 0x00, 0x70,             // NR50~NR52
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, // $FF27~$FF2F
+];
+
+// 矩形波のデューティ比 (12.5%, 25%, 50%, 75%)
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1],
+    [1, 0, 0, 0, 0, 0, 0, 1],
+    [1, 0, 0, 0, 0, 1, 1, 1],
+    [0, 1, 1, 1, 1, 1, 1, 0],
+];
+
+// ノイズの分周比 (NR43 Bit[2:0])
+const NOISE_DIVISOR: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+// ハイパスフィルタ(DCカット)のコンデンサの充電係数 (0.999958^4、4T-cycle毎)
+const HPF_CHARGE: f32 = 0.999_832;
+
+// 長さカウンタ
+#[derive(Serialize, Deserialize)]
+struct Length {
+    enabled: bool,
+    counter: u16,
+    max: u16,           // CH1,2,4: 64, CH3: 256
+}
+
+impl Length {
+    fn new(max: u16) -> Self {
+        Length {
+            enabled: false,
+            counter: 0,
+            max,
+        }
+    }
+
+    // NRx1 (長さデータの書き込み)
+    fn load(&mut self, val: u16) {
+        self.counter = self.max - val;
+    }
+
+    // Clocks the length counter. Returns true if the channel should be disabled.
+    fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            self.counter == 0
+        } else {
+            false
+        }
+    }
+
+    // NRx4 Bit6 (Length enable)の書き込み。チャンネルを止める場合はtrueを返す
+    // 次のフレームシーケンサのステップで長さカウンタが動かない時に有効にすると、1回余分にクロックされる
+    fn write_enable(&mut self, enabled: bool, fs_step: u8) -> bool {
+        let was_enabled = self.enabled;
+        self.enabled = enabled;
+
+        if !was_enabled && enabled && fs_step & 1 == 1 {
+            return self.clock();
+        }
+        false
+    }
+
+    fn trigger(&mut self, fs_step: u8) {
+        if self.counter == 0 {
+            self.counter = self.max;
+            if self.enabled && fs_step & 1 == 1 {
+                self.counter -= 1;
+            }
+        }
+    }
+}
+
+// 音量エンベロープ (NRx2)
+#[derive(Default, Serialize, Deserialize)]
+struct Envelope {
+    initial: u8,        // Bit[7:4] 初期音量
+    add: bool,          // Bit3 方向 (0=減少, 1=増加)
+    period: u8,         // Bit[2:0] スイープ間隔 (0=停止)
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    fn write(&mut self, val: u8) {
+        self.initial = val >> 4;
+        self.add = val & _BIT_3 != 0;
+        self.period = val & 0x07;
+    }
+
+    // DACはNRx2のBit[7:3]が全て0の時にOFF
+    fn dac_enabled(&self) -> bool {
+        self.initial != 0 || self.add
+    }
+
+    fn trigger(&mut self) {
+        self.volume = self.initial;
+        self.timer = self.period;
+    }
+
+    fn clock(&mut self) {
+        if self.period == 0 {
+            return;
+        }
+
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.add && self.volume < 15 {
+                self.volume += 1;
+            } else if !self.add && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+}
+
+// 周波数スイープ (CH1 Only, NR10)
+#[derive(Default, Serialize, Deserialize)]
+struct Sweep {
+    period: u8,         // Bit[6:4] スイープ間隔
+    negate: bool,       // Bit3 方向 (0=加算, 1=減算)
+    shift: u8,          // Bit[2:0] シフト量
+    timer: u8,
+    enabled: bool,
+    shadow: u16,        // シャドウ周波数
+    negate_used: bool,  // 減算モードで計算したか
+}
+
+impl Sweep {
+    // 新しい周波数を計算する
+    fn calc(&mut self) -> u16 {
+        let delta = self.shadow >> self.shift;
+        if self.negate {
+            self.negate_used = true;
+            self.shadow - delta
+        } else {
+            self.shadow + delta
+        }
+    }
+
+    fn reload_timer(&mut self) {
+        self.timer = if self.period == 0 { 8 } else { self.period };
+    }
+}
+
+// CH1, CH2: 矩形波
+#[derive(Serialize, Deserialize)]
+struct Square {
+    enabled: bool,
+    duty: u8,           // NRx1 Bit[7:6]
+    duty_pos: u8,
+    freq: u16,          // NRx3 + NRx4 Bit[2:0] (11bit)
+    timer: u32,
+    length: Length,
+    envelope: Envelope,
+    sweep: Sweep,       // CH1 Only
+}
+
+impl Square {
+    fn new() -> Self {
+        Square {
+            enabled: false,
+            duty: 0,
+            duty_pos: 0,
+            freq: 0,
+            timer: 0,
+            length: Length::new(64),
+            envelope: Envelope::default(),
+            sweep: Sweep::default(),
+        }
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - self.freq as u32) * 4
+    }
+
+    fn step(&mut self, mut cycles: u32) {
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.duty_pos = (self.duty_pos + 1) & 0x07;
+        }
+        self.timer -= cycles;
+    }
+
+    fn trigger(&mut self, fs_step: u8) {
+        self.enabled = self.envelope.dac_enabled();
+        self.length.trigger(fs_step);
+        self.timer = self.period();
+        self.envelope.trigger();
+
+        // (CH1 Only) スイープの初期化
+        self.sweep.shadow = self.freq;
+        self.sweep.reload_timer();
+        self.sweep.enabled = self.sweep.period != 0 || self.sweep.shift != 0;
+        self.sweep.negate_used = false;
+        if self.sweep.shift != 0 && self.sweep.calc() > 2047 {
+            self.enabled = false;
+        }
+    }
+
+    fn clock_sweep(&mut self) {
+        if self.sweep.timer > 0 {
+            self.sweep.timer -= 1;
+        }
+        if self.sweep.timer != 0 {
+            return;
+        }
+        self.sweep.reload_timer();
+
+        if !self.sweep.enabled || self.sweep.period == 0 {
+            return;
+        }
+
+        let freq = self.sweep.calc();
+        if freq > 2047 {
+            self.enabled = false;
+        } else if self.sweep.shift != 0 {
+            self.sweep.shadow = freq;
+            self.freq = freq;
+            // 新しい周波数でもう一度オーバーフローをチェックする
+            if self.sweep.calc() > 2047 {
+                self.enabled = false;
+            }
+        }
+    }
+
+    fn write_sweep(&mut self, val: u8) {
+        let negate = val & _BIT_3 != 0;
+        // 減算モードで計算した後に加算モードにするとチャンネルが止まる
+        if self.sweep.negate_used && !negate {
+            self.enabled = false;
+        }
+        self.sweep.period = (val >> 4) & 0x07;
+        self.sweep.negate = negate;
+        self.sweep.shift = val & 0x07;
+    }
+
+    // DACへの入力 (0~15)
+    fn output(&self) -> u8 {
+        if self.enabled && DUTY_TABLE[self.duty as usize][self.duty_pos as usize] != 0 {
+            self.envelope.volume
+        } else {
+            0
+        }
+    }
+}
+
+// CH3: 波形メモリ
+#[derive(Serialize, Deserialize)]
+struct Wave {
+    enabled: bool,
+    dac_enabled: bool,  // NR30 Bit7
+    volume_code: u8,    // NR32 Bit[6:5] (0=0%, 1=100%, 2=50%, 3=25%)
+    freq: u16,
+    timer: u32,
+    pos: u8,            // 再生位置 (0~31)
+    sample_buf: u8,
+    length: Length,
+}
+
+impl Wave {
+    fn new() -> Self {
+        Wave {
+            enabled: false,
+            dac_enabled: false,
+            volume_code: 0,
+            freq: 0,
+            timer: 0,
+            pos: 0,
+            sample_buf: 0,
+            length: Length::new(256),
+        }
+    }
+
+    fn period(&self) -> u32 {
+        (2048 - self.freq as u32) * 2
+    }
+
+    fn step(&mut self, mut cycles: u32, wave_ram: &[u8; WAVE_RAM_SIZE]) {
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.pos = (self.pos + 1) & 0x1F;
+            // 上位ニブルが先
+            let byte = wave_ram[(self.pos >> 1) as usize];
+            self.sample_buf = if self.pos & 1 == 0 { byte >> 4 } else { byte & 0x0F };
+        }
+        self.timer -= cycles;
+    }
+
+    fn trigger(&mut self, fs_step: u8) {
+        self.enabled = self.dac_enabled;
+        self.length.trigger(fs_step);
+        self.timer = self.period();
+        self.pos = 0;
+    }
+
+    fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        match self.volume_code {
+            0 => 0,
+            n => self.sample_buf >> (n - 1),
+        }
+    }
+}
+
+// CH4: ノイズ
+#[derive(Serialize, Deserialize)]
+struct Noise {
+    enabled: bool,
+    shift: u8,          // NR43 Bit[7:4] クロックシフト
+    width7: bool,       // NR43 Bit3 LFSRの幅 (0=15bit, 1=7bit)
+    divisor_code: u8,   // NR43 Bit[2:0] 分周比
+    lfsr: u16,
+    timer: u32,
+    length: Length,
+    envelope: Envelope,
+}
+
+impl Noise {
+    fn new() -> Self {
+        Noise {
+            enabled: false,
+            shift: 0,
+            width7: false,
+            divisor_code: 0,
+            lfsr: 0x7FFF,
+            timer: 0,
+            length: Length::new(64),
+            envelope: Envelope::default(),
+        }
+    }
+
+    fn period(&self) -> u32 {
+        NOISE_DIVISOR[self.divisor_code as usize] << self.shift
+    }
+
+    fn step(&mut self, mut cycles: u32) {
+        // シフト14, 15はクロックが来ない
+        if self.shift >= 14 {
+            return;
+        }
+
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+
+            let bit = (self.lfsr ^ (self.lfsr >> 1)) & 0x01;
+            self.lfsr = (self.lfsr >> 1) | (bit << 14);
+            if self.width7 {
+                self.lfsr = (self.lfsr & !0x40) | (bit << 6);
+            }
+        }
+        self.timer -= cycles;
+    }
+
+    fn trigger(&mut self, fs_step: u8) {
+        self.enabled = self.envelope.dac_enabled();
+        self.length.trigger(fs_step);
+        self.timer = self.period();
+        self.envelope.trigger();
+        self.lfsr = 0x7FFF;
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && self.lfsr & 0x01 == 0 {
+            self.envelope.volume
+        } else {
+            0
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct APU {
+    power: bool,                        // NR52 Bit7
+    regs: [u8; 0x20],                   // $FF10~$FF2F 書き込まれた値 (読み出し用)
+    wave_ram: [u8; WAVE_RAM_SIZE],      // $FF30~$FF3F
+    ch1: Square,
+    ch2: Square,
+    ch3: Wave,
+    ch4: Noise,
+    fs_step: u8,                        // 次に実行するフレームシーケンサのステップ (0~7)
+    hpf_l: f32,                         // ハイパスフィルタのコンデンサ (L)
+    hpf_r: f32,                         // ハイパスフィルタのコンデンサ (R)
+    cycles: u8,                         // 4T-cycleに満たない余り (倍速モードでは2T単位で進む)
+    #[serde(skip)]
+    samples: Vec<f32>,                  // 出力サンプル (L/Rインターリーブ)
+}
+
+impl Default for APU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl APU {
+    pub fn new() -> Self {
+        APU {
+            power: false,
+            regs: [0; 0x20],
+            wave_ram: [0; WAVE_RAM_SIZE],
+            ch1: Square::new(),
+            ch2: Square::new(),
+            ch3: Wave::new(),
+            ch4: Noise::new(),
+            fs_step: 0,
+            hpf_l: 0.0,
+            hpf_r: 0.0,
+            cycles: 0,
+            samples: Vec::new(),
+        }
+    }
+
+    // Moves the generated samples (interleaved L/R) into `out`.
+    pub fn drain_samples(&mut self, out: &mut Vec<f32>) {
+        out.append(&mut self.samples);
+    }
+
+    // Outputs silence without advancing the channels. (STOP中はAPUのクロックも止まる)
+    // フロントエンドはサンプル数で速度を合わせるので、出力は止めない
+    pub fn output_silence(&mut self, tick: u8) {
+        for _ in 0..(tick / 4) {
+            if self.samples.len() < MAX_SAMPLES {
+                self.samples.push(0.0);
+                self.samples.push(0.0);
+            }
+        }
+    }
+
+    // Advances the frame sequencer (512Hz, DIV-APU).
+    // Step  0: Length
+    // Step  2: Length, Sweep
+    // Step  4: Length
+    // Step  6: Length, Sweep
+    // Step  7: Envelope
+    pub fn step_frame_sequencer(&mut self) {
+        if !self.power {
+            return;
+        }
+
+        match self.fs_step {
+            0 | 4 => self.clock_length(),
+            2 | 6 => {
+                self.clock_length();
+                self.ch1.clock_sweep();
+            }
+            7 => {
+                self.ch1.envelope.clock();
+                self.ch2.envelope.clock();
+                self.ch4.envelope.clock();
+            }
+            _ => (),
+        }
+        self.fs_step = (self.fs_step + 1) & 0x07;
+    }
+
+    fn clock_length(&mut self) {
+        if self.ch1.length.clock() {
+            self.ch1.enabled = false;
+        }
+        if self.ch2.length.clock() {
+            self.ch2.enabled = false;
+        }
+        if self.ch3.length.clock() {
+            self.ch3.enabled = false;
+        }
+        if self.ch4.length.clock() {
+            self.ch4.enabled = false;
+        }
+    }
+
+    // NR52の電源OFFで$FF10~$FF25がクリアされる (Wave RAMはそのまま)
+    // (DMG) 長さカウンタは電源OFFの影響を受けない
+    fn power_off(&mut self) {
+        for addr in NR10_ADDR..NR52_ADDR {
+            self.write_reg(addr, 0);
+        }
+
+        let lengths = [
+            self.ch1.length.counter,
+            self.ch2.length.counter,
+            self.ch3.length.counter,
+            self.ch4.length.counter,
+        ];
+        self.ch1 = Square::new();
+        self.ch2 = Square::new();
+        self.ch3 = Wave::new();
+        self.ch4 = Noise::new();
+        self.ch1.length.counter = lengths[0];
+        self.ch2.length.counter = lengths[1];
+        self.ch3.length.counter = lengths[2];
+        self.ch4.length.counter = lengths[3];
+
+        self.power = false;
+    }
+
+    fn write_reg(&mut self, addr: u16, val: u8) {
+        self.regs[(addr - NR10_ADDR) as usize] = val;
+        let fs_step = self.fs_step;
+
+        match addr {
+            // CH1
+            0xFF10 => self.ch1.write_sweep(val),
+            0xFF11 => {
+                self.ch1.duty = val >> 6;
+                self.ch1.length.load((val & 0x3F) as u16);
+            }
+            0xFF12 => {
+                self.ch1.envelope.write(val);
+                if !self.ch1.envelope.dac_enabled() {
+                    self.ch1.enabled = false;
+                }
+            }
+            0xFF13 => self.ch1.freq = (self.ch1.freq & 0x700) | val as u16,
+            0xFF14 => {
+                self.ch1.freq = (self.ch1.freq & 0xFF) | ((val & 0x07) as u16) << 8;
+                if self.ch1.length.write_enable(val & _BIT_6 != 0, fs_step) {
+                    self.ch1.enabled = false;
+                }
+                if val & _BIT_7 != 0 {
+                    self.ch1.trigger(fs_step);
+                }
+            }
+
+            // CH2
+            0xFF16 => {
+                self.ch2.duty = val >> 6;
+                self.ch2.length.load((val & 0x3F) as u16);
+            }
+            0xFF17 => {
+                self.ch2.envelope.write(val);
+                if !self.ch2.envelope.dac_enabled() {
+                    self.ch2.enabled = false;
+                }
+            }
+            0xFF18 => self.ch2.freq = (self.ch2.freq & 0x700) | val as u16,
+            0xFF19 => {
+                self.ch2.freq = (self.ch2.freq & 0xFF) | ((val & 0x07) as u16) << 8;
+                if self.ch2.length.write_enable(val & _BIT_6 != 0, fs_step) {
+                    self.ch2.enabled = false;
+                }
+                if val & _BIT_7 != 0 {
+                    self.ch2.trigger(fs_step);
+                }
+            }
+
+            // CH3
+            0xFF1A => {
+                self.ch3.dac_enabled = val & _BIT_7 != 0;
+                if !self.ch3.dac_enabled {
+                    self.ch3.enabled = false;
+                }
+            }
+            0xFF1B => self.ch3.length.load(val as u16),
+            0xFF1C => self.ch3.volume_code = (val >> 5) & 0x03,
+            0xFF1D => self.ch3.freq = (self.ch3.freq & 0x700) | val as u16,
+            0xFF1E => {
+                self.ch3.freq = (self.ch3.freq & 0xFF) | ((val & 0x07) as u16) << 8;
+                if self.ch3.length.write_enable(val & _BIT_6 != 0, fs_step) {
+                    self.ch3.enabled = false;
+                }
+                if val & _BIT_7 != 0 {
+                    self.ch3.trigger(fs_step);
+                }
+            }
+
+            // CH4
+            0xFF20 => self.ch4.length.load((val & 0x3F) as u16),
+            0xFF21 => {
+                self.ch4.envelope.write(val);
+                if !self.ch4.envelope.dac_enabled() {
+                    self.ch4.enabled = false;
+                }
+            }
+            0xFF22 => {
+                self.ch4.shift = val >> 4;
+                self.ch4.width7 = val & _BIT_3 != 0;
+                self.ch4.divisor_code = val & 0x07;
+            }
+            0xFF23 => {
+                if self.ch4.length.write_enable(val & _BIT_6 != 0, fs_step) {
+                    self.ch4.enabled = false;
+                }
+                if val & _BIT_7 != 0 {
+                    self.ch4.trigger(fs_step);
+                }
+            }
+
+            // NR50, NR51はregsの値をそのまま使う
+            _ => (),
+        }
+    }
+
+    // Mixes the channels into one stereo sample.
+    fn mix(&mut self) -> (f32, f32) {
+        if !self.power {
+            return (0.0, 0.0);
+        }
+
+        // DAC: 0~15 → -1.0~1.0 (DAC OFFは0.0)
+        let dac = |input: u8, enabled: bool| -> f32 {
+            if enabled {
+                input as f32 / 7.5 - 1.0
+            } else {
+                0.0
+            }
+        };
+        let ch = [
+            dac(self.ch1.output(), self.ch1.envelope.dac_enabled()),
+            dac(self.ch2.output(), self.ch2.envelope.dac_enabled()),
+            dac(self.ch3.output(), self.ch3.dac_enabled),
+            dac(self.ch4.output(), self.ch4.envelope.dac_enabled()),
+        ];
+
+        // NR51: Bit[7:4] = CH4~1をLへ、Bit[3:0] = CH4~1をRへ
+        let nr51 = self.regs[0x15];
+        let mut left = 0.0;
+        let mut right = 0.0;
+        for (i, val) in ch.iter().enumerate() {
+            if nr51 & (0x10 << i) != 0 {
+                left += val;
+            }
+            if nr51 & (0x01 << i) != 0 {
+                right += val;
+            }
+        }
+
+        // NR50: Bit[6:4] = Lの音量、Bit[2:0] = Rの音量 (0~7 → 1/8~8/8)
+        let nr50 = self.regs[0x14];
+        let left = left / 4.0 * (((nr50 >> 4) & 0x07) + 1) as f32 / 8.0;
+        let right = right / 4.0 * ((nr50 & 0x07) + 1) as f32 / 8.0;
+
+        // ハイパスフィルタ
+        let out_l = left - self.hpf_l;
+        self.hpf_l = left - out_l * HPF_CHARGE;
+        let out_r = right - self.hpf_r;
+        self.hpf_r = right - out_r * HPF_CHARGE;
+
+        (out_l, out_r)
+    }
+}
+
+impl IO for APU {
+    fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            // NR52
+            NR52_ADDR => {
+                let power = val & _BIT_7 != 0;
+                if self.power && !power {
+                    self.power_off();
+                } else if !self.power && power {
+                    // 電源ONでフレームシーケンサはステップ0から
+                    self.power = true;
+                    self.fs_step = 0;
+                }
+            }
+            0xFF10..=0xFF25 => {
+                if self.power {
+                    self.write_reg(addr, val);
+                } else {
+                    // (DMG) 電源OFF中も長さカウンタは書き込める
+                    match addr {
+                        0xFF11 | 0xFF16 | 0xFF20 => self.write_reg(addr, val & 0x3F),
+                        0xFF1B => self.write_reg(addr, val),
+                        _ => (),
+                    }
+                }
+            }
+            // Unused
+            0xFF27..=0xFF2F => (),
+            // Wave RAM
+            0xFF30..=0xFF3F => self.wave_ram[(addr - WAVE_RAM_ADDR) as usize] = val,
+            _ => panic!("[ERR] APU Write, Addr: 0x{:04X}", addr),
+        }
+    }
+
+    fn read(&mut self, addr: u16) -> u8 {
+        match addr {
+            // NR52: Bit7 = 電源、Bit[3:0] = 各チャンネルが鳴っているか
+            NR52_ADDR => {
+                (self.power as u8) << 7
+                    | READ_MASK[(addr - NR10_ADDR) as usize]
+                    | (self.ch4.enabled as u8) << 3
+                    | (self.ch3.enabled as u8) << 2
+                    | (self.ch2.enabled as u8) << 1
+                    | self.ch1.enabled as u8
+            }
+            0xFF10..=0xFF2F => {
+                let i = (addr - NR10_ADDR) as usize;
+                self.regs[i] | READ_MASK[i]
+            }
+            // Wave RAM
+            0xFF30..=0xFF3F => self.wave_ram[(addr - WAVE_RAM_ADDR) as usize],
+            _ => panic!("[ERR] APU Read, Addr: 0x{:04X}", addr),
+        }
+    }
+
+    fn update(&mut self, tick: u8) {
+        // 4T-cycle毎にチャンネルを進めて1サンプル出力する
+        let cycles = self.cycles as u16 + tick as u16;
+        self.cycles = (cycles % 4) as u8;
+
+        for _ in 0..(cycles / 4) {
+            if self.power {
+                self.ch1.step(4);
+                self.ch2.step(4);
+                self.ch3.step(4, &self.wave_ram);
+                self.ch4.step(4);
+            }
+
+            let (left, right) = self.mix();
+            if self.samples.len() < MAX_SAMPLES {
+                self.samples.push(left);
+                self.samples.push(right);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn powered_apu() -> APU {
+        let mut apu = APU::new();
+        apu.write(NR52_ADDR, 0x80);
+        apu
+    }
+
+    #[test]
+    fn registers_read_back_with_mask() {
+        let mut apu = powered_apu();
+        for addr in NR10_ADDR..NR52_ADDR {
+            apu.write(addr, 0x00);
+        }
+        for addr in NR10_ADDR..NR52_ADDR {
+            assert_eq!(apu.read(addr), READ_MASK[(addr - NR10_ADDR) as usize], "${:04X}", addr);
+        }
+        for addr in NR52_ADDR + 1..WAVE_RAM_ADDR {
+            assert_eq!(apu.read(addr), 0xFF);
+        }
+        assert_eq!(apu.read(NR52_ADDR), 0xF0);
+
+        // 書き込み専用のビットは1が読める
+        apu.write(0xFF11, 0x85);
+        assert_eq!(apu.read(0xFF11), 0xBF);
+        apu.write(0xFF13, 0x12);
+        assert_eq!(apu.read(0xFF13), 0xFF);
+        apu.write(0xFF1A, 0x80);
+        assert_eq!(apu.read(0xFF1A), 0xFF);
+    }
+
+    #[test]
+    fn power_off_clears_registers() {
+        let mut apu = powered_apu();
+        apu.write(0xFF24, 0x77);
+        apu.write(0xFF30, 0x12);
+        apu.write(NR52_ADDR, 0x00);
+        assert_eq!(apu.read(NR52_ADDR), 0x70);
+        assert_eq!(apu.read(0xFF24), 0x00);
+        // Wave RAMはそのまま
+        assert_eq!(apu.read(0xFF30), 0x12);
+
+        // 電源OFF中は長さカウンタ以外書き込めない
+        apu.write(0xFF24, 0x77);
+        assert_eq!(apu.read(0xFF24), 0x00);
+        apu.write(0xFF11, 0xFF);
+        assert_eq!(apu.read(0xFF11), 0x3F);
+        assert_eq!(apu.ch1.length.counter, 64 - 0x3F);
+    }
+
+    #[test]
+    fn length_counter_disables_channel() {
+        let mut apu = powered_apu();
+        // 長さ 2、DAC ON、長さカウンタ有効でトリガー
+        apu.write(0xFF11, 62);
+        apu.write(0xFF12, 0xF0);
+        apu.write(0xFF14, 0xC0);
+        assert_eq!(apu.read(NR52_ADDR) & 0x01, 0x01);
+
+        apu.step_frame_sequencer();
+        apu.step_frame_sequencer();
+        assert_eq!(apu.read(NR52_ADDR) & 0x01, 0x01);
+        apu.step_frame_sequencer();
+        assert_eq!(apu.read(NR52_ADDR) & 0x01, 0x00);
+    }
+
+    #[test]
+    fn length_enable_on_odd_step_clocks_once() {
+        let mut apu = powered_apu();
+        apu.write(0xFF11, 62);
+        apu.write(0xFF12, 0xF0);
+        apu.write(0xFF14, 0x80);
+        // 次のステップ(1)で長さカウンタが動かない時に有効にすると、1回余分にクロックされる
+        apu.step_frame_sequencer();
+        apu.write(0xFF14, 0x40);
+        assert_eq!(apu.ch1.length.counter, 1);
+        assert_eq!(apu.read(NR52_ADDR) & 0x01, 0x01);
+    }
+
+    #[test]
+    fn sweep_updates_frequency_and_overflows() {
+        let mut apu = powered_apu();
+        // 間隔 1、加算、シフト 1、周波数 $400
+        apu.write(0xFF10, 0x11);
+        apu.write(0xFF12, 0xF0);
+        apu.write(0xFF13, 0x00);
+        apu.write(0xFF14, 0x84);
+        assert_eq!(apu.read(NR52_ADDR) & 0x01, 0x01);
+
+        // ステップ2: $400 + $200 = $600、次の計算($900)がオーバーフローして止まる
+        for _ in 0..3 {
+            apu.step_frame_sequencer();
+        }
+        assert_eq!(apu.ch1.freq, 0x600);
+        assert_eq!(apu.read(NR52_ADDR) & 0x01, 0x00);
+    }
+
+    #[test]
+    fn sweep_negate_then_add_disables_channel() {
+        let mut apu = powered_apu();
+        // 間隔 1、減算、シフト 1、周波数 $400
+        apu.write(0xFF10, 0x19);
+        apu.write(0xFF12, 0xF0);
+        apu.write(0xFF13, 0x00);
+        apu.write(0xFF14, 0x84);
+
+        for _ in 0..3 {
+            apu.step_frame_sequencer();
+        }
+        assert_eq!(apu.ch1.freq, 0x200);
+        assert_eq!(apu.read(NR52_ADDR) & 0x01, 0x01);
+
+        // 減算で計算した後に加算モードにすると止まる
+        apu.write(0xFF10, 0x11);
+        assert_eq!(apu.read(NR52_ADDR) & 0x01, 0x00);
+    }
+}
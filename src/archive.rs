@@ -0,0 +1,184 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use flate2::read::GzDecoder;
+use zip::ZipArchive;
+use error::LoadError;
+use header::ROM_EXTENSIONS;
+
+// [圧縮されたROMの読み込み]
+// .zip: 最初の.gb/.gbc/.sgb、または名前で指定したエントリ
+// .gz : 展開したデータ
+// それ以外はそのまま読む
+pub const ARCHIVE_EXTENSIONS: [&str; 2] = ["zip", "gz"];
+// 展開後のサイズの上限 (ヘッダーの最大ROMサイズ)
+pub const MAX_ROM_SIZE: u64 = 8 * 1024 * 1024;
+
+// Reads ROM data from a file, decompressing .zip/.gz archives.
+// `entry` selects the file in a zip archive by name (None: the first ROM).
+pub fn read_rom(path: &str, entry: Option<&str>) -> Result<Vec<u8>, LoadError> {
+    let io_err = |err| LoadError::Io { path: path.to_string(), err };
+    let file = File::open(path).map_err(io_err)?;
+
+    match extension(Path::new(path)).as_str() {
+        "zip" => read_zip(path, file, entry),
+        "gz" => read_limited(path, GzDecoder::new(file)),
+        _ => read_limited(path, file),
+    }
+}
+
+fn read_zip(path: &str, file: File, entry: Option<&str>) -> Result<Vec<u8>, LoadError> {
+    let archive_err = |e: zip::result::ZipError| LoadError::Archive { path: path.to_string(), reason: e.to_string() };
+    let mut zip = ZipArchive::new(file).map_err(archive_err)?;
+
+    // アーカイブ内の順番で探す
+    let mut names = Vec::with_capacity(zip.len());
+    for i in 0..zip.len() {
+        names.push(zip.by_index_raw(i).map_err(archive_err)?.name().to_string());
+    }
+    let index = names.iter().position(|name| match entry {
+        // フルパスかファイル名が一致するもの
+        Some(entry) => name == entry || Path::new(name).file_name().is_some_and(|f| f == entry),
+        None => is_rom_name(name),
+    });
+
+    let index = match index {
+        Some(index) => index,
+        None => {
+            return Err(LoadError::RomNotInArchive {
+                path: path.to_string(),
+                entry: entry.map(|entry| entry.to_string()),
+                names,
+            })
+        }
+    };
+
+    let file = zip.by_index(index).map_err(archive_err)?;
+    info!("Loading {} from {}", file.name(), path);
+    read_limited(path, file)
+}
+
+fn read_limited<R: Read>(path: &str, reader: R) -> Result<Vec<u8>, LoadError> {
+    let mut rom = Vec::new();
+    reader
+        .take(MAX_ROM_SIZE + 1)
+        .read_to_end(&mut rom)
+        .map_err(|err| LoadError::Io { path: path.to_string(), err })?;
+
+    if rom.len() as u64 > MAX_ROM_SIZE {
+        return Err(LoadError::Archive {
+            path: path.to_string(),
+            reason: format!("ROM is larger than {}MB", MAX_ROM_SIZE / 1024 / 1024),
+        });
+    }
+
+    Ok(rom)
+}
+
+// Returns the path of a file that belongs to the ROM (save file etc.) with the given extension.
+// game.gb → game.sav, game.zip → game.sav, game.gb.gz → game.sav
+pub fn rom_file_path(rom_path: &str, ext: &str) -> String {
+    let mut path_buf = PathBuf::from(rom_path);
+    if extension(&path_buf) == "gz" {
+        path_buf.set_extension("");
+    }
+    path_buf.set_extension(ext);
+    path_buf.to_string_lossy().into_owned()
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase()
+}
+
+fn is_rom_name(name: &str) -> bool {
+    ROM_EXTENSIONS.contains(&extension(Path::new(name)).as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Write;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use zip::write::{FileOptions, ZipWriter};
+
+    // テスト毎に別のファイルを使う
+    fn temp_path(name: &str) -> String {
+        let dir = ::std::env::temp_dir().join(format!("rsgb-archive-{}", ::std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.join(name).to_string_lossy().into_owned()
+    }
+
+    fn write_zip(path: &str, entries: &[(&str, &[u8])]) {
+        let mut zip = ZipWriter::new(File::create(path).unwrap());
+        for &(name, data) in entries {
+            zip.start_file(name, FileOptions::default()).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn zip_picks_first_rom_or_named_entry() {
+        let path = temp_path("select.zip");
+        write_zip(&path, &[("readme.txt", b"text"), ("dir/a.gbc", b"AAAA"), ("b.GB", b"BBBB")]);
+
+        assert_eq!(read_rom(&path, None).unwrap(), b"AAAA");
+        assert_eq!(read_rom(&path, Some("b.GB")).unwrap(), b"BBBB");
+        // ファイル名だけでも指定できる
+        assert_eq!(read_rom(&path, Some("a.gbc")).unwrap(), b"AAAA");
+        assert_eq!(read_rom(&path, Some("dir/a.gbc")).unwrap(), b"AAAA");
+
+        match read_rom(&path, Some("c.gb")) {
+            Err(LoadError::RomNotInArchive { entry, names, .. }) => {
+                assert_eq!(entry.as_deref(), Some("c.gb"));
+                assert_eq!(names, ["readme.txt", "dir/a.gbc", "b.GB"]);
+            }
+            other => panic!("unexpected result: {:?}", other.map(|rom| rom.len())),
+        }
+
+        let path = temp_path("no_rom.zip");
+        write_zip(&path, &[("readme.txt", b"text")]);
+        assert!(matches!(read_rom(&path, None), Err(LoadError::RomNotInArchive { entry: None, .. })));
+    }
+
+    #[test]
+    fn gzip_is_decompressed() {
+        let path = temp_path("game.gb.gz");
+        let mut gz = GzEncoder::new(File::create(&path).unwrap(), Compression::default());
+        gz.write_all(&[0x12; 1000]).unwrap();
+        gz.finish().unwrap();
+
+        assert_eq!(read_rom(&path, None).unwrap(), vec![0x12; 1000]);
+    }
+
+    #[test]
+    fn oversized_rom_is_rejected() {
+        // 展開後のサイズで判定する (圧縮すれば小さい)
+        let path = temp_path("big.gb.gz");
+        let mut gz = GzEncoder::new(File::create(&path).unwrap(), Compression::default());
+        gz.write_all(&vec![0; MAX_ROM_SIZE as usize + 1]).unwrap();
+        gz.finish().unwrap();
+        assert!(fs::metadata(&path).unwrap().len() < 1024 * 1024);
+
+        assert!(matches!(read_rom(&path, None), Err(LoadError::Archive { .. })));
+
+        // ちょうど上限のサイズは読める
+        let path = temp_path("max.gb");
+        fs::write(&path, vec![0; MAX_ROM_SIZE as usize]).unwrap();
+        assert_eq!(read_rom(&path, None).unwrap().len() as u64, MAX_ROM_SIZE);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn save_path_from_archive_name() {
+        assert_eq!(rom_file_path("roms/game.gb", "sav"), "roms/game.sav");
+        assert_eq!(rom_file_path("roms/game.zip", "sav"), "roms/game.sav");
+        assert_eq!(rom_file_path("roms/game.gb.gz", "sav"), "roms/game.sav");
+        assert_eq!(rom_file_path("roms/game.GZ", "sav"), "roms/game.sav");
+    }
+}
@@ -1,22 +1,233 @@
 use std::fs::File;
 // use std::io::{Read, Write};
 use std::io::Read;
+use serde::{Deserialize, Serialize};
 use common::*;
+use error::LoadError;
 
+// DMG BIOS: 256Byte ($0000-$00FF)
+// CGB BIOS: 2304Byte ($0000-$00FF, $0200-$08FF)
+//           ※ $0100-$01FFはカートリッジヘッダーが見える
+const DMG_BIOS_SIZE: usize = 0x100;
+const CGB_BIOS_SIZE: usize = 0x900;
+
+// ハードウェアモデル
+// https://gbdev.io/pandocs/Power_Up_Sequence.html
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Model {
+    DMG,    // ゲームボーイ
+    MGB,    // ゲームボーイポケット
+    SGB,    // スーパーゲームボーイ
+    CGB,    // ゲームボーイカラー
+    AGB,    // ゲームボーイアドバンス
+}
+
+impl Model {
+    // Selects a model from the cartridge CGB flag ($0143).
+    pub fn from_cgb_flag(cgb_flg: u8) -> Self {
+        match cgb_flg {
+            0x80 | 0xC0 => Model::CGB,
+            _ => Model::DMG,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "dmg" => Some(Model::DMG),
+            "mgb" => Some(Model::MGB),
+            "sgb" => Some(Model::SGB),
+            "cgb" => Some(Model::CGB),
+            "agb" => Some(Model::AGB),
+            _ => None,
+        }
+    }
+
+    // Returns true if the model has CGB functions.
+    pub fn is_cgb(self) -> bool {
+        matches!(self, Model::CGB | Model::AGB)
+    }
+}
+
+// BIOS終了時点のCPUレジスタ
+pub struct PostBootRegs {
+    pub a: u8, pub f: u8,
+    pub b: u8, pub c: u8,
+    pub d: u8, pub e: u8,
+    pub h: u8, pub l: u8,
+}
+
+// Returns CPU registers after the boot ROM has finished.
+// `cgb_mode` is false when a CGB/AGB runs a DMG cartridge (DMG compatibility mode).
+pub fn post_boot_regs(model: Model, cgb_mode: bool, rom: &[u8]) -> PostBootRegs {
+    // DMG/MGB: H, CフラグはヘッダーチェックサムNon-Zeroでセット
+    let dmg_f: u8 = if rom[0x014D] != 0 { 0xB0 } else { 0x80 };
+
+    match model {
+        Model::DMG => PostBootRegs { a: 0x01, f: dmg_f, b: 0x00, c: 0x13, d: 0x00, e: 0xD8, h: 0x01, l: 0x4D },
+        Model::MGB => PostBootRegs { a: 0xFF, f: dmg_f, b: 0x00, c: 0x13, d: 0x00, e: 0xD8, h: 0x01, l: 0x4D },
+        Model::SGB => PostBootRegs { a: 0x01, f: 0x00, b: 0x00, c: 0x14, d: 0x00, e: 0x00, h: 0xC0, l: 0x60 },
+        Model::CGB | Model::AGB => {
+            // AGBはBレジスタが+1される
+            let (f, b_ofs) = if model == Model::AGB { (0x00, 1) } else { (0x80, 0) };
+
+            if cgb_mode {
+                PostBootRegs { a: 0x11, f, b: b_ofs, c: 0x00, d: 0xFF, e: 0x56, h: 0x00, l: 0x0D }
+            } else {
+                // DMG互換モード: 任天堂ライセンスのROMはBにタイトルのチェックサムが入る
+                let nintendo = rom[0x014B] == 0x01
+                    || (rom[0x014B] == 0x33 && rom[0x0144] == b'0' && rom[0x0145] == b'1');
+                let b = if nintendo {
+                    rom[0x0134..=0x0143].iter().fold(0u8, |sum, &c| sum.wrapping_add(c))
+                } else {
+                    0x00
+                };
+                let (h, l) = if b == 0x43 || b == 0x58 { (0x99, 0x1A) } else { (0x00, 0x7C) };
+
+                PostBootRegs { a: 0x11, f, b: b.wrapping_add(b_ofs), c: 0x00, d: 0x00, e: 0x08, h, l }
+            }
+        }
+    }
+}
+
+// Returns I/O registers after the boot ROM has finished. (Addr, Value)
+// DIV ($FF04) is set separately via the timer counter.
+// `cgb_mode` is false when a CGB/AGB runs a DMG cartridge (DMG compatibility mode).
+pub fn post_boot_io(model: Model, cgb_mode: bool) -> Vec<(u16, u8)> {
+    let sc: u8 = if model.is_cgb() { 0x7F } else { 0x7E };
+    let nr52: u8 = if model == Model::SGB { 0xF0 } else { 0xF1 };
+
+    let mut io = vec![
+        (0xFF00, 0xCF), // P1
+        (0xFF01, 0x00), // SB
+        (0xFF02, sc),   // SC
+        (0xFF05, 0x00), // TIMA
+        (0xFF06, 0x00), // TMA
+        (0xFF07, 0xF8), // TAC
+        (0xFF0F, 0xE1), // IF
+        (0xFF26, nr52), // NR52 (電源OFF中はNR10~NR51に書き込めないので先に設定)
+        (0xFF10, 0x80), // NR10
+        (0xFF11, 0xBF), // NR11
+        (0xFF12, 0xF3), // NR12
+        (0xFF13, 0xFF), // NR13
+        (0xFF14, 0xBF), // NR14
+        (0xFF16, 0x3F), // NR21
+        (0xFF17, 0x00), // NR22
+        (0xFF18, 0xFF), // NR23
+        (0xFF19, 0xBF), // NR24
+        (0xFF1A, 0x7F), // NR30
+        (0xFF1B, 0xFF), // NR31
+        (0xFF1C, 0x9F), // NR32
+        (0xFF1D, 0xFF), // NR33
+        (0xFF1E, 0xBF), // NR34
+        (0xFF20, 0xFF), // NR41
+        (0xFF21, 0x00), // NR42
+        (0xFF22, 0x00), // NR43
+        (0xFF23, 0xBF), // NR44
+        (0xFF24, 0x77), // NR50
+        (0xFF25, 0xF3), // NR51
+        (0xFF40, 0x91), // LCDC
+        (0xFF41, 0x85), // STAT
+        (0xFF42, 0x00), // SCY
+        (0xFF43, 0x00), // SCX
+        (0xFF45, 0x00), // LYC
+        (0xFF47, 0xFC), // BGP
+        (0xFF48, 0xFF), // OBP0 (不定)
+        (0xFF49, 0xFF), // OBP1 (不定)
+        (0xFF4A, 0x00), // WY
+        (0xFF4B, 0x00), // WX
+        (0xFFFF, 0x00), // IE
+    ];
+
+    if model.is_cgb() {
+        // KEY0: CGBモードはヘッダーのCGBフラグ、DMG互換モードはBit2
+        // OPRI: CGBモードはOAM順、DMG互換モードはX座標順
+        io.push((0xFF4C, if cgb_mode { 0x80 } else { 0x04 }));
+        io.push((0xFF6C, if cgb_mode { 0x00 } else { 0x01 }));
+        if !cgb_mode {
+            io.extend(compat_palette_io());
+        }
+    }
+
+    io
+}
+
+// DMG互換モードのパレット (ブートROMがタイトルで選ばない時の既定値, RGB555)
+// BG: 白, 黄緑, 青, 黒    OBJ0/OBJ1: 白, ピンク, 赤茶, 黒
+const COMPAT_BG_PALETTE: [u16; 4] = [0x7FFF, 0x1BEF, 0x6180, 0x0000];
+const COMPAT_OBJ_PALETTE: [u16; 4] = [0x7FFF, 0x421F, 0x1CF2, 0x0000];
+
+// Writes the DMG compatibility palettes to BG palette 0 and OBJ palettes 0, 1.
+fn compat_palette_io() -> Vec<(u16, u8)> {
+    // BCPS/OCPS: インデックス0からオートインクリメント
+    let mut io = vec![(0xFF68, 0x80), (0xFF6A, 0x80)];
+    for &col in COMPAT_BG_PALETTE.iter() {
+        io.push((0xFF69, col as u8));
+        io.push((0xFF69, (col >> 8) as u8));
+    }
+    for &col in COMPAT_OBJ_PALETTE.iter().chain(COMPAT_OBJ_PALETTE.iter()) {
+        io.push((0xFF6B, col as u8));
+        io.push((0xFF6B, (col >> 8) as u8));
+    }
+    io
+}
+
+// Returns the internal 16bit divider counter after the boot ROM has finished.
+// (DMG/MGBはDIV=$AB、それ以外のモデルは起動時間で変わるので代表値)
+pub fn post_boot_div(model: Model) -> u16 {
+    match model {
+        Model::DMG | Model::MGB => 0xABCC,
+        Model::SGB => 0x0000,
+        Model::CGB | Model::AGB => 0x1EA0,
+    }
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct BIOS {
     pub bios: Vec<u8>,
     pub is_boot: bool
 }
 
 impl BIOS {
-    pub fn new(path: &str) -> Self {
+    pub fn new(path: &str) -> Result<Self, LoadError> {
+        let io_err = |err| LoadError::Io { path: path.to_string(), err };
+
         let mut bios = Vec::new();
-        let mut file = File::open(path).unwrap();
-        file.read_to_end(&mut bios).unwrap();
+        let mut file = File::open(path).map_err(io_err)?;
+        file.read_to_end(&mut bios).map_err(io_err)?;
 
-        BIOS {
-            bios: bios,
+        Self::from_bytes(bios)
+    }
+
+    // Creates a BIOS from boot ROM data (DMG: 256Byte, CGB: 2304Byte).
+    pub fn from_bytes(bios: Vec<u8>) -> Result<Self, LoadError> {
+        if bios.len() != DMG_BIOS_SIZE && bios.len() != CGB_BIOS_SIZE {
+            return Err(LoadError::InvalidBiosSize(bios.len()));
+        }
+
+        Ok(BIOS {
+            bios,
             is_boot: true,
+        })
+    }
+
+    // No boot ROM (starts from the post-boot state).
+    pub fn none() -> Self {
+        BIOS {
+            bios: Vec::new(),
+            is_boot: false,
+        }
+    }
+
+    // Returns true if the address is mapped to the boot ROM.
+    pub fn is_mapped(&self, addr: u16) -> bool {
+        if !self.is_boot {
+            return false;
+        }
+
+        match addr as usize {
+            0x0000..=0x00FF => self.bios.len() >= DMG_BIOS_SIZE,
+            0x0200..=0x08FF => self.bios.len() >= CGB_BIOS_SIZE,
+            _ => false,
         }
     }
 }
@@ -24,14 +235,12 @@ impl BIOS {
 #[allow(dead_code)]
 impl IO for BIOS {
     fn write(&mut self, addr: u16, _val: u8) {
-        match addr {
-            _ => panic!("[ERR] BIOS Write Only! (Addr: ${:#04X})", addr),
-        }
+        panic!("[ERR] BIOS Write Only! (Addr: ${:#04X})", addr)
     }
 
     fn read(&mut self, addr: u16) -> u8 {
         match addr {
-            0x0000..=0x00FF => {
+            0x0000..=0x00FF | 0x0200..=0x08FF => {
                 self.bios[addr as usize]
             },
             _ => panic!("[ERR] BIOS Read Addr ${:#04X}", addr),
@@ -0,0 +1,186 @@
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use error::LoadError;
+
+// [ポケットカメラのイメージセンサー入力]
+// 実機のセンサー(M64282FP)の代わりに画像ファイルかテストパターンを使う
+// 出力は CAMERA_W × CAMERA_H のグレースケール (0 = 黒, 255 = 白)
+pub const CAMERA_W: usize = 128;
+pub const CAMERA_H: usize = 112;
+// PGMの幅・高さの上限
+const MAX_PGM_SIZE: usize = 4096;
+
+// Returns the built-in test pattern.
+// 上半分はグレーの8段階の縦縞、下半分は横方向のグラデーション
+pub fn test_pattern() -> Vec<u8> {
+    let mut image = vec![0; CAMERA_W * CAMERA_H];
+
+    for y in 0..CAMERA_H {
+        for x in 0..CAMERA_W {
+            image[y * CAMERA_W + x] = if y < CAMERA_H / 2 {
+                (x / (CAMERA_W / 8) * 255 / 7) as u8
+            } else {
+                (x * 255 / (CAMERA_W - 1)) as u8
+            };
+        }
+    }
+
+    image
+}
+
+// Loads a PNG or PGM file as the camera image.
+// 縦横比を合わせて中央を切り出し、CAMERA_W × CAMERA_H に縮小する
+pub fn load_image(path: &str) -> Result<Vec<u8>, LoadError> {
+    let io_err = |err| LoadError::Io { path: path.to_string(), err };
+    let invalid = |reason: String| LoadError::InvalidImage { path: path.to_string(), reason };
+
+    let ext = Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let file = File::open(path).map_err(io_err)?;
+
+    let (w, h, gray) = match ext.as_str() {
+        "png" => decode_png(file).map_err(invalid)?,
+        "pgm" | "pnm" => {
+            let mut data = Vec::new();
+            BufReader::new(file).read_to_end(&mut data).map_err(io_err)?;
+            decode_pgm(&data).map_err(invalid)?
+        }
+        _ => return Err(invalid("unsupported format (PNG or PGM)".to_string())),
+    };
+
+    Ok(scale(w, h, &gray))
+}
+
+// PNG → (幅, 高さ, グレースケール)
+fn decode_png(file: File) -> Result<(usize, usize, Vec<u8>), String> {
+    let mut decoder = png::Decoder::new(BufReader::new(file));
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).map_err(|e| e.to_string())?;
+
+    let channels = info.color_type.samples();
+    let gray = buf[..info.buffer_size()]
+        .chunks_exact(channels)
+        .map(|px| match channels {
+            1 | 2 => px[0],
+            // ITU-R BT.601 の輝度
+            _ => ((px[0] as u32 * 299 + px[1] as u32 * 587 + px[2] as u32 * 114) / 1000) as u8,
+        })
+        .collect();
+
+    Ok((info.width as usize, info.height as usize, gray))
+}
+
+// PGM (P2: テキスト, P5: バイナリ) → (幅, 高さ, グレースケール)
+fn decode_pgm(data: &[u8]) -> Result<(usize, usize, Vec<u8>), String> {
+    let mut pos = 0;
+
+    // ヘッダーのトークン (空白区切り、#から行末まではコメント)
+    let mut token = || -> Option<String> {
+        loop {
+            while pos < data.len() && data[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if pos < data.len() && data[pos] == b'#' {
+                while pos < data.len() && data[pos] != b'\n' {
+                    pos += 1;
+                }
+            } else {
+                break;
+            }
+        }
+        let start = pos;
+        while pos < data.len() && !data[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        (start < pos).then(|| String::from_utf8_lossy(&data[start..pos]).into_owned())
+    };
+    let bad_header = || "broken PGM header".to_string();
+
+    let magic = token().ok_or_else(bad_header)?;
+    let mut num = || token().and_then(|t| t.parse::<usize>().ok()).ok_or_else(bad_header);
+    let (w, h, maxval) = (num()?, num()?, num()?);
+    if w == 0 || h == 0 || maxval == 0 || maxval > 0xFFFF {
+        return Err(bad_header());
+    }
+    let size = w.checked_mul(h).filter(|_| w <= MAX_PGM_SIZE && h <= MAX_PGM_SIZE).ok_or_else(bad_header)?;
+
+    let samples: Vec<usize> = match magic.as_str() {
+        "P2" => (0..size).map(|_| num()).collect::<Result<_, _>>()?,
+        "P5" => {
+            // ヘッダーの後は空白1文字だけ
+            let body = data.get(pos + 1..).unwrap_or_default();
+            if maxval < 0x100 {
+                body.iter().take(size).map(|&v| v as usize).collect()
+            } else {
+                body.chunks_exact(2).take(size).map(|v| (v[0] as usize) << 8 | v[1] as usize).collect()
+            }
+        }
+        _ => return Err("unsupported PGM format (P2 or P5)".to_string()),
+    };
+    if samples.len() < size {
+        return Err("truncated PGM data".to_string());
+    }
+
+    let gray = samples.iter().map(|&v| (v.min(maxval) * 255 / maxval) as u8).collect();
+    Ok((w, h, gray))
+}
+
+// 中央を CAMERA_W:CAMERA_H で切り出して縮小する (面積平均)
+fn scale(w: usize, h: usize, gray: &[u8]) -> Vec<u8> {
+    let (crop_w, crop_h) = if w * CAMERA_H > h * CAMERA_W {
+        (h * CAMERA_W / CAMERA_H, h)
+    } else {
+        (w, w * CAMERA_H / CAMERA_W)
+    };
+    let (crop_w, crop_h) = (crop_w.max(1), crop_h.max(1));
+    let (left, top) = ((w - crop_w) / 2, (h - crop_h) / 2);
+
+    let mut image = vec![0; CAMERA_W * CAMERA_H];
+    for y in 0..CAMERA_H {
+        let y0 = top + y * crop_h / CAMERA_H;
+        let y1 = (top + (y + 1) * crop_h / CAMERA_H).max(y0 + 1);
+        for x in 0..CAMERA_W {
+            let x0 = left + x * crop_w / CAMERA_W;
+            let x1 = (left + (x + 1) * crop_w / CAMERA_W).max(x0 + 1);
+
+            let mut sum = 0;
+            for sy in y0..y1 {
+                sum += gray[sy * w + x0..sy * w + x1].iter().map(|&v| v as usize).sum::<usize>();
+            }
+            image[y * CAMERA_W + x] = (sum / ((y1 - y0) * (x1 - x0))) as u8;
+        }
+    }
+
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_ascii_and_binary_pgm() {
+        let (w, h, gray) = decode_pgm(b"P2\n# comment\n2 2\n4\n0 1\n2 4\n").unwrap();
+        assert_eq!((w, h), (2, 2));
+        assert_eq!(gray, [0, 63, 127, 255]);
+
+        let (w, h, gray) = decode_pgm(b"P5 3 1 255\n\x00\x80\xFF").unwrap();
+        assert_eq!((w, h), (3, 1));
+        assert_eq!(gray, [0, 128, 255]);
+    }
+
+    #[test]
+    fn oversized_pgm_is_rejected() {
+        // w × h がオーバーフローする
+        let data = format!("P5 {} {} 255\n", usize::MAX / 2, 3);
+        assert_eq!(decode_pgm(data.as_bytes()).unwrap_err(), "broken PGM header");
+        assert_eq!(decode_pgm(b"P5 4097 1 255\n").unwrap_err(), "broken PGM header");
+        assert_eq!(decode_pgm(b"P5 4 4 255\n\x00").unwrap_err(), "truncated PGM data");
+    }
+}
@@ -1,176 +1,289 @@
+use std::fs;
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io;
+use std::io::Write;
+use std::mem;
+use serde::{Deserialize, Serialize};
+use archive;
 use common::*;
+use error;
+use error::LoadError;
+use header::CartridgeHeader;
+use mbc::Mapper;
+use patch;
+use mbc::mbc2::MBC2_RAM_SIZE;
+use mbc::mbc7::MBC7_EEPROM_SIZE;
+use mbc::tama5::TAMA5_EEPROM_SIZE;
+
+// 任天堂ロゴ ($0104-$0133)
+pub const NINTENDO_LOGO: [u8; 0x30] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83,
+    0x00, 0x0C, 0x00, 0x0D, 0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E,
+    0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99, 0xBB, 0xBB, 0x67, 0x63,
+    0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
 
-const ROM_BANK_SIZE: usize = 16 * 1024;
-const RAM_BANK_SIZE: usize = 8 * 1024;
 #[allow(dead_code)]
+#[derive(Serialize, Deserialize)]
 pub struct Cartridge {
-    rom: Vec<u8>,
+    #[serde(skip)]
+    rom: Vec<u8>,           // ROMはセーブステートに含めない
+    #[serde(skip)]
+    header: CartridgeHeader,
     ram: Vec<u8>,
     mbc_type: u8,
-    ram_enable: bool,
-    bank_no_upper: u8,
-    bank_no_lower: u8,
-    num_rom_banks: u8,
-    mode: bool,
+    mapper: Mapper,         // MBC ($0147で選択)
     cgb_flg: u8,
+    #[serde(skip)]
+    dirty: bool,            // 前回のセーブからRAMが書き換えられた
 }
 
 // https://gbdev.io/pandocs/The_Cartridge_Header.html
 impl Cartridge {
-    pub fn new(fname: &str) -> Self {
-        let mut rom = Vec::new();
-        let mut file = File::open(fname).unwrap();
-        file.read_to_end(&mut rom).unwrap();
+    // Loads a ROM file. (.zip/.gzは展開する)
+    // In lenient mode, recoverable header errors are logged as warnings instead of failing.
+    pub fn new(fname: &str, lenient: bool) -> Result<Self, LoadError> {
+        Self::open(fname, None, None, lenient)
+    }
+
+    // Loads a ROM file, selecting the zip entry by name. (None: the first ROM in the archive)
+    // The patch (IPS/UPS/BPS) is applied in memory. (None: ROMと同じ名前のパッチがあれば当てる)
+    pub fn open(fname: &str, entry: Option<&str>, patch: Option<&str>, lenient: bool) -> Result<Self, LoadError> {
+        let mut rom = archive::read_rom(fname, entry)?;
 
-        // CGBフラグ(0x143バイト目)
-        let cgb_flg: u8 = rom[0x0143];
+        let patch = patch.map(|patch| patch.to_string()).or_else(|| patch::find_patch(fname));
+        if let Some(ref patch) = patch {
+            rom = patch::apply_patch_file(patch, rom, lenient)?;
+        }
+
+        Self::from_bytes(rom, lenient)
+    }
+
+    // Creates a cartridge from ROM data.
+    pub fn from_bytes(mut rom: Vec<u8>, lenient: bool) -> Result<Self, LoadError> {
+        let header = CartridgeHeader::parse(&rom)?;
+
+        let cgb_flg = header.cgb_flag;
+        info!("Title: {}", header.title);
         info!("CGB Flag: {:#02X}", cgb_flg);
 
-        let mbc_type = rom[0x0147];
-        let mbc_name = match mbc_type {
-            0x00 => "ROM ONLY",
-            0x01 => "MBC1",
-            0x02 => "MBC1+RAM",
-            0x03 => "MBC1+RAM+BATTERY",
-            0x05 => "MBC2",
-            0x06 => "MBC2+BATTERY",
-            0x08 => "ROM+RAM",
-            0x09 => "ROM+RAM+BATTERY",
-            0x0B => "MMM01",
-            0x0C => "MMM01+RAM",
-            0x0D => "MMM01+RAM+BATTERY",
-            0x0F => "MBC3+TIMER+BATTERY",
-            0x10 => "MBC3+TIMER+RAM+BATTERY",
-            0x11 => "MBC3",
-            0x12 => "MBC3+RAM",
-            0x13 => "MBC3+RAM+BATTERY",
-            0x19 => "MBC5",
-            0x1A => "MBC5+RAM",
-            0x1B => "MBC5+RAM+BATTERY",
-            0x1C => "MBC5+RUMBLE",
-            0x1D => "MBC5+RUMBLE+RAM",
-            0x1E => "MBC5+RUMBLE+RAM+BATTERY",
-            0x20 => "MBC6",
-            0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
-            0xFC => "POCKET CAMERA",
-            0xFD => "BANDAI TAMA5",
-            0xFE => "HuC3",
-            0xFF => "HuC1+RAM+BATTERY",
-            _ => "Unknown",
-        };
+        let mbc_type = header.cartridge_type;
+        if header.cartridge_name.is_none() {
+            // 不明なMBCはMBC1として扱う
+            error::check(lenient, LoadError::UnsupportedMapper(mbc_type))?;
+        }
 
-        let rom_size: usize = match rom[0x0148] {
-            0 => 32 * 1024,
-            n => 32 * 1024 << (n as usize),
+        let rom_size = match header.rom_size {
+            Some(size) => size,
+            None => {
+                // 不明なROMサイズはファイルサイズから決める
+                error::check(lenient, LoadError::InvalidRomSize(header.rom_size_code))?;
+                rom.len().next_power_of_two().max(32 * 1024)
+            }
         };
 
-        let num_rom_banks = 2 << rom[0x0148];
-
-        let ram_size: usize = match rom[0x0149] {
-            0 => 0,
-            1 => 2 * 1024,
-            2 => 8 * 1024,
-            3 => 32 * 1024,
-            4 => 128 * 1024,
-            5 => 64 * 1024,
-            _ => panic!("RAM size invalid"),
+        let ram_size = match mbc_type {
+            // MBC2はRAM内蔵 (512×4bit)
+            0x05 | 0x06 => MBC2_RAM_SIZE,
+            // MBC7はEEPROM (256Byte)
+            0x22 => MBC7_EEPROM_SIZE,
+            // TAMA5はEEPROM (32Byte)
+            0xFD => TAMA5_EEPROM_SIZE,
+            _ => match header.ram_size {
+                Some(size) => size,
+                None => {
+                    // 不明なRAMサイズは最大(128KB)として扱う
+                    error::check(lenient, LoadError::InvalidRamSize(header.ram_size_code))?;
+                    128 * 1024
+                }
+            },
         };
 
-        // チェックサム$014D (= $0134~$014C)
-        let mut chksum: u8 = 0;
-        for i in 0x0134..0x014D {
-            chksum = chksum.wrapping_sub(rom[i]).wrapping_sub(1);
-        }
-        if chksum != rom[0x014D] {
-            panic!("ROM header checksum is incorrect");
+        if !header.header_checksum_ok() {
+            error::check(
+                lenient,
+                LoadError::HeaderChecksum { header: header.header_checksum, calc: header.header_checksum_calc },
+            )?;
         }
 
         if rom_size != rom.len() {
-            panic!("ROM file invalid");
+            error::check(lenient, LoadError::RomSizeMismatch { header: rom_size, file: rom.len() })?;
+            // 足りない部分は0xFFで埋める
+            let size = rom_size.max(rom.len().next_power_of_two());
+            rom.resize(size, 0xFF);
         }
 
+        let mapper = Mapper::new(mbc_type, &rom);
+
         info!("ROM size {}KB", rom_size / 1024);
         info!("RAM size {}KB", ram_size / 1024);
-        info!("MBC type {}", mbc_name);
+        info!("MBC type {}", header.cartridge_name.unwrap_or("Unknown"));
 
-        Cartridge {
-            rom: rom,
+        Ok(Cartridge {
+            rom,
+            header,
             ram: vec![0; ram_size],
-            mbc_type: mbc_type,
-            ram_enable: false,
-            bank_no_upper: 0,
-            bank_no_lower: 0,
-            num_rom_banks: num_rom_banks,
-            mode: false,
-            cgb_flg: cgb_flg,
-        }
+            mbc_type,
+            mapper,
+            cgb_flg,
+            dirty: false,
+        })
     }
 
-    fn rom_bank_no(&self) -> u8 {
-        let bank_no = if self.mode {
-            self.bank_no_lower
-        } else {
-            self.bank_no_upper << 5 | self.bank_no_lower
-        };
-
-        let bank_no = match bank_no {
-            0 | 0x20 | 0x40 | 0x60 => bank_no + 1,
-            _ => bank_no,
-        };
-
-        bank_no & (self.num_rom_banks - 1)
+    // Returns true if the cartridge keeps its save data with a battery (or EEPROM/flash).
+    pub fn has_battery(&self) -> bool {
+        self.header.battery
     }
 
-    fn ram_bank_no(&self) -> u8 {
-        if self.mode {
-            self.bank_no_upper
-        } else {
-            0
+    // Returns true if the save data was modified since the last write.
+    pub fn save_dirty(&mut self) -> bool {
+        if self.mapper.mbc_mut().save_modified() {
+            self.dirty = true;
         }
+        self.dirty
     }
 
-    pub fn read_save_file(&mut self, fname: &str) {
+    // Loads the save file. (ファイルがなければ何もしない)
+    // The file must be at least as large as the RAM given by the header.
+    pub fn read_save_file(&mut self, fname: &str) -> Result<(), LoadError> {
+        if !self.has_battery() {
+            return Ok(());
+        }
+
+        let data = match fs::read(fname) {
+            Ok(data) => data,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(LoadError::Io { path: fname.to_string(), err }),
+        };
         info!("Reading save file from: {}", fname);
 
-        if let Ok(mut file) = File::open(fname) {
-            self.ram = Vec::new();
-            file.read_to_end(&mut self.ram).unwrap();
+        // | RAM | 追加データ (RTCなど) |
+        // 追加データのないMBCはRAMと同じサイズでなければならない
+        let extra_size = self.mapper.mbc().extra_size();
+        if data.len() < self.ram.len() || (extra_size == 0 && data.len() != self.ram.len()) {
+            return Err(LoadError::SaveSizeMismatch {
+                path: fname.to_string(),
+                expected: self.ram.len() + extra_size,
+                actual: data.len(),
+            });
         }
+
+        let len = self.ram.len();
+        self.ram.copy_from_slice(&data[..len]);
+        self.mapper.mbc_mut().load_extra(&data[len..]);
+        self.mapper.mbc_mut().save_modified();
+        self.dirty = false;
+
+        Ok(())
     }
 
-    pub fn write_save_file(&mut self, fname: &str) {
+    // Writes the save file. (電池のないカートリッジは何もしない)
+    // 書き込み中に落ちても前のセーブが壊れないように、一時ファイルに書いてから置き換える
+    pub fn write_save_file(&mut self, fname: &str) -> io::Result<()> {
+        if !self.has_battery() {
+            return Ok(());
+        }
         info!("Writing save file to: {}", fname);
 
-        if let Ok(mut file) = File::create(fname) {
-            file.write_all(&mut self.ram).unwrap();
+        let tmp_fname = format!("{}.tmp", fname);
+        {
+            let mut file = File::create(&tmp_fname)?;
+            file.write_all(&self.ram)?;
+            file.write_all(&self.mapper.mbc_mut().save_extra())?;
+            file.sync_all()?;
         }
+        fs::rename(&tmp_fname, fname)?;
+
+        self.mapper.mbc_mut().save_modified();
+        self.dirty = false;
+
+        Ok(())
+    }
+
+    // Writes the save file if the save data was modified. Returns true if written.
+    pub fn flush_save_file(&mut self, fname: &str) -> io::Result<bool> {
+        if !self.has_battery() || !self.save_dirty() {
+            return Ok(false);
+        }
+
+        self.write_save_file(fname)?;
+        Ok(true)
+    }
+
+    // Returns true if the rumble motor is on. (MBC5+RUMBLE)
+    pub fn rumble(&self) -> bool {
+        self.mapper.mbc().rumble()
+    }
+
+    // Sets the accelerometer input in G. (MBC7)
+    pub fn set_accelerometer(&mut self, x: f32, y: f32) {
+        self.mapper.mbc_mut().set_accelerometer(x, y);
+    }
+
+    // Sets the image seen by the camera sensor. (POCKET CAMERA)
+    pub fn set_camera_image(&mut self, image: &[u8]) {
+        self.mapper.mbc_mut().set_camera_image(image);
+    }
+
+    // Returns true if the IR LED is on. (HuC1/HuC3)
+    pub fn ir_led(&self) -> bool {
+        self.mapper.mbc().ir_led()
+    }
+
+    // Sets whether the IR receiver sees light. (HuC1/HuC3)
+    pub fn set_ir_input(&mut self, light: bool) {
+        self.mapper.mbc_mut().set_ir_input(light);
     }
 
     pub fn get_cgb_mode(&self) -> u8 {
         self.cgb_flg
     }
+
+    // Returns ROM data.
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    // Returns the decoded cartridge header.
+    pub fn header(&self) -> &CartridgeHeader {
+        &self.header
+    }
+
+    // Returns title in the cartridge header ($0134-$0143).
+    pub fn title(&self) -> String {
+        self.header.title.clone()
+    }
+
+    // Returns header checksum ($014D).
+    pub fn header_checksum(&self) -> u8 {
+        self.header.header_checksum
+    }
+
+    // Returns global checksum ($014E-$014F, big endian).
+    pub fn global_checksum(&self) -> u16 {
+        self.header.global_checksum
+    }
+
+    // Moves ROM data (and its header, MBC6 flash) from another cartridge (used after loading a save state).
+    // セーブステートのRAMが読み込み前と違う時だけ、次のflushで書き出す
+    pub fn take_rom(&mut self, other: &mut Cartridge) {
+        self.rom = mem::take(&mut other.rom);
+        self.header = mem::take(&mut other.header);
+        self.mapper.take_extra(&mut other.mapper);
+        self.dirty = other.dirty || self.ram != other.ram;
+    }
 }
 
 impl IO for Cartridge {
     fn write(&mut self, addr: u16, val: u8) {
         match addr {
-            // RAM enable
-            0x0000..=0x1FFF => self.ram_enable = val & 0x0F == 0x0A,
-            // ROM bank number (lower 5 bits)
-            0x2000..=0x3FFF => self.bank_no_lower = val & 0x1F,
-            // RAM bank number or ROM bank number (upper 2 bits)
-            0x4000..=0x5FFF => self.bank_no_upper = val & 0x03,
-            // ROM/RAM mode select
-            0x6000..=0x7FFF => self.mode = val & 0x01 > 0,
-            // RAM Bank 01~nn
+            // MBC registers
+            0x0000..=0x7FFF => self.mapper.mbc_mut().write_reg(addr, val),
+            // External RAM
             0xA000..=0xBFFF => {
-                if !self.ram_enable {
-                    return;
+                if self.mapper.mbc_mut().write_ram(&mut self.ram, addr, val) {
+                    self.dirty = true;
                 }
-                let offset = RAM_BANK_SIZE * self.ram_bank_no() as usize;
-                self.ram[(addr & 0x1FFF) as usize + offset] = val
             }
             _ => unreachable!("Unexpected address: 0x{:04X}", addr),
         }
@@ -178,26 +291,153 @@ impl IO for Cartridge {
 
     fn read(&mut self, addr: u16) -> u8 {
         match addr {
-            // ROM Bank 00
-            0x0000..=0x3FFF => self.rom[addr as usize],
-            // ROM bank 01-7F
-            0x4000..=0x7FFF => {
-                let offset = ROM_BANK_SIZE * self.rom_bank_no() as usize;
-                self.rom[(addr & 0x3FFF) as usize + offset]
-            }
-            // RAM Bank 01~nn
-            0xa000..=0xBFFF => {
-                if !self.ram_enable {
-                    return 0xFF;
-                }
-                let offset = RAM_BANK_SIZE * self.ram_bank_no() as usize;
-                self.ram[(addr & 0x1FFF) as usize + offset]
-            }
+            // ROM
+            0x0000..=0x7FFF => self.mapper.mbc().read_rom(&self.rom, addr),
+            // External RAM
+            0xA000..=0xBFFF => self.mapper.mbc_mut().read_ram(&self.ram, addr),
             _ => unreachable!("Unexpected address: 0x{:04X}", addr),
         }
     }
 
-    fn update(&mut self, _tick: u8) {
-        // NOP
+    fn update(&mut self, tick: u8) {
+        self.mapper.mbc_mut().update(tick);
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    // テスト用のROMを作る
+    // 各バンクはバンク番号(下位8bit)で埋め、$0150からプログラムを置く
+    pub fn rom(cartridge_type: u8, rom_size_code: u8, ram_size_code: u8, code: &[u8]) -> Vec<u8> {
+        let mut rom = vec![0; (32 * 1024) << rom_size_code];
+        for (bank, data) in rom.chunks_mut(0x4000).enumerate() {
+            for val in data.iter_mut() {
+                *val = bank as u8;
+            }
+        }
+
+        // エントリーポイント: JP $0150
+        rom[0x0100..0x0104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        rom[0x0104..0x0134].copy_from_slice(&NINTENDO_LOGO);
+        for val in rom[0x0134..0x0150].iter_mut() {
+            *val = 0;
+        }
+        rom[0x0134..0x0138].copy_from_slice(b"TEST");
+        rom[0x0147] = cartridge_type;
+        rom[0x0148] = rom_size_code;
+        rom[0x0149] = ram_size_code;
+        rom[0x014D] = rom[0x0134..0x014D].iter().fold(0u8, |sum, &val| sum.wrapping_sub(val).wrapping_sub(1));
+
+        rom[0x0150..0x0150 + code.len()].copy_from_slice(code);
+        rom
+    }
+
+    pub fn cartridge(cartridge_type: u8, rom_size_code: u8, ram_size_code: u8, code: &[u8]) -> Cartridge {
+        Cartridge::from_bytes(rom(cartridge_type, rom_size_code, ram_size_code, code), false).unwrap()
+    }
+
+    #[test]
+    fn take_rom_keeps_dirty_flag() {
+        // RAMが同じなら書き出さない
+        let mut old = cartridge(0x03, 1, 0x02, &[]);
+        let mut state = cartridge(0x03, 1, 0x02, &[]);
+        state.rom.clear();
+        state.take_rom(&mut old);
+        assert!(!state.save_dirty());
+        assert_eq!(state.read(0x4000), 0x01);
+
+        // RAMが変わった時
+        let mut old = cartridge(0x03, 1, 0x02, &[]);
+        let mut state = cartridge(0x03, 1, 0x02, &[]);
+        state.write(0x0000, 0x0A);
+        state.write(0xA000, 0x55);
+        assert!(state.save_dirty());
+        state.take_rom(&mut old);
+        assert!(state.save_dirty());
+
+        // 読み込み前の変更が書き出されていない時
+        let mut old = cartridge(0x03, 1, 0x02, &[]);
+        let mut state = cartridge(0x03, 1, 0x02, &[]);
+        old.write(0x0000, 0x0A);
+        old.write(0xA000, 0x55);
+        state.ram[0] = 0x55;
+        state.take_rom(&mut old);
+        assert!(state.save_dirty());
+    }
+
+    fn temp_path(name: &str) -> String {
+        let dir = ::std::env::temp_dir().join(format!("rsgb-cartridge-{}", ::std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.join(name).to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn save_file_is_replaced_atomically() {
+        let path = temp_path("atomic.sav");
+        fs::write(&path, vec![0x11; 8 * 1024]).unwrap();
+
+        let mut cart = cartridge(0x03, 0, 0x02, &[]);
+        cart.read_save_file(&path).unwrap();
+        cart.write(0x0000, 0x0A);
+        cart.write(0xA000, 0x22);
+
+        // 一時ファイルが作れなければ、前のセーブはそのまま
+        let tmp_path = format!("{}.tmp", path);
+        fs::create_dir_all(&tmp_path).unwrap();
+        assert!(cart.flush_save_file(&path).is_err());
+        assert_eq!(fs::read(&path).unwrap(), vec![0x11; 8 * 1024]);
+        assert!(cart.save_dirty());
+
+        fs::remove_dir(&tmp_path).unwrap();
+        assert!(cart.flush_save_file(&path).unwrap());
+        let data = fs::read(&path).unwrap();
+        assert_eq!(data[0], 0x22);
+        assert_eq!(data[1..], vec![0x11; 8 * 1024 - 1][..]);
+        assert!(fs::metadata(&tmp_path).is_err());
+    }
+
+    #[test]
+    fn save_file_is_flushed_only_when_modified() {
+        let path = temp_path("flush.sav");
+        let _ = fs::remove_file(&path);
+
+        let mut cart = cartridge(0x03, 0, 0x02, &[]);
+        assert!(!cart.flush_save_file(&path).unwrap());
+        assert!(fs::metadata(&path).is_err());
+
+        cart.write(0x0000, 0x0A);
+        cart.write(0xA000, 0x00);
+        assert!(!cart.flush_save_file(&path).unwrap());
+        cart.write(0xA000, 0x01);
+        assert!(cart.flush_save_file(&path).unwrap());
+        assert!(!cart.flush_save_file(&path).unwrap());
+
+        // 電池のないカートリッジは書き出さない
+        let path = temp_path("no_battery.sav");
+        let mut cart = cartridge(0x02, 0, 0x02, &[]);
+        cart.write(0x0000, 0x0A);
+        cart.write(0xA000, 0x01);
+        assert!(!cart.flush_save_file(&path).unwrap());
+        cart.write_save_file(&path).unwrap();
+        assert!(fs::metadata(&path).is_err());
+    }
+
+    #[test]
+    fn save_file_size_is_checked() {
+        let path = temp_path("size.sav");
+        let mut cart = cartridge(0x03, 0, 0x02, &[]);
+        fs::write(&path, vec![0; 4 * 1024]).unwrap();
+        assert!(matches!(
+            cart.read_save_file(&path),
+            Err(LoadError::SaveSizeMismatch { expected: 8192, actual: 4096, .. })
+        ));
+        fs::write(&path, vec![0; 8 * 1024 + 1]).unwrap();
+        assert!(cart.read_save_file(&path).is_err());
+
+        // ファイルがなければ何もしない
+        fs::remove_file(&path).unwrap();
+        assert!(cart.read_save_file(&path).is_ok());
     }
 }
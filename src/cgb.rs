@@ -1,3 +1,4 @@
+use serde::{Deserialize, Serialize};
 use common::*;
 
 pub const CGB_MODE_DMG_COMPATI: u8 = 0x80;  // CGB機能とDMGコンパチ動作（GB/GBC共通カートリッジ）
@@ -7,28 +8,6 @@ pub const CGB_MODE_NONE: u8 = 0xFF;
 pub const _CGB_GP_DMA: u8 = 0;
 pub const _CGB_H_BLANK_DMA: u8 = 1;
 
-// リファレンスを翻訳＆図解すると
-// https://gbdev.io/pandocs/Palettes.html#palettes
-// BG/OBJの各カラーパレットは[8パレット×4色/パレット×2バイト/色= 64Byte]
-//
-// [カラーパレットの色番号]
-// |    0x00    |    0x01    |    0x02    |    0x03    |
-// |BGP0 色番号0|BGP0 色番号1|BGP0 色番号2|BGP0 色番号3|
-// ↓ =
-// [カラーパレットのデータ]
-// |    0x00    |    0x01    |    0x02    |    0x03    |
-// |BGP0 0の上位|BGP0 0の下位|BGP0 1の上位|BGP0 1の下位|
-//
-// 例）データ0x03はBGP0の色番号1の上位バイト(青と緑)
-//
-// [RGB555]
-// Bit 0-4   Red Intensity   ($00-1F)
-// Bit 5-9   Green Intensity ($00-1F)
-// Bit 10-14 Blue Intensity  ($00-1F)
-// |   Bit[15:12]   |  Bit[11:8]   |    Bit[7:4]    |    Bit[3:0]    |
-// | N/A |     青色 5bit     |     緑色 5bit     |     赤色 5bit     |
-pub const _COLOR_PALETTE_SIZE: usize = 64;
-
 // [CGB対応]
 // TODO :MBC1（GB/GBC共通） ... テリーのワンダーランド
 // TODO :MBC3（GB/GBC共通） ... ポケモン（金、銀）
@@ -42,8 +21,10 @@ pub const _COLOR_PALETTE_SIZE: usize = 64;
 // ↓レジスタ一覧
 // https://gbdev.io/pandocs/Hardware_Reg_List.html?highlight=bcps#hardware-registers
 #[allow(dead_code)]
+#[derive(Serialize, Deserialize)]
 pub struct CGB {
-    pub key_1: u8,          // (Addr $FF4D R/W) スピードスイッチの準備
+    pub key_0: u8,          // (Addr $FF4C W) CPUモード (ブートROMの実行中のみ書き込める、Bit2 = DMG互換モード)
+    pub key_1: u8,          // (Addr $FF4D R/W) スピードスイッチの準備 (Bit0)
     pub vbk: u8,            // (Addr $FF4F R/W) VRAM バンク
     pub hdma1: u8,          // (Addr $FF51 W) VRAM DMA ソース（上位）
     pub hdma2: u8,          // (Addr $FF52 W) VRAM DMA ソース（下位）
@@ -51,12 +32,7 @@ pub struct CGB {
     pub hdma4: u8,          // (Addr $FF54 W) VRAM DMA 宛先（下位）
     pub hdma5: u8,          // (Addr $FF55 W) VRAM DMA 長さ/モード/開始
     pub rp: u8,             // (Addr $FF56 R/W) 赤外線通信ポート
-    pub bgpi: u8,           // (Addr $FF68 R/W) 背景パレット インデックス
-    pub bcps: u8,           // (Addr $FF68 R/W) 背景色パレット仕様
-    pub bg_col_plt: Vec<u8>, // (Addr $FF69 R/W) BCPD/BGPD
-    pub ocps: u8,           // (Addr $FF6A R/W) OBJ カラーパレット仕様
-    pub obpi: u8,           // (Addr $FF6A R/W) OBJ パレットインデックス
-    pub obj_col_plt: Vec<u8>, // (Addr $FF6B R/W) OCPD/OBPD
+    // $FF68~$FF6B (BCPS/BCPD, OCPS/OCPD)はPPUが持つ
     pub opri: u8,           // (Addr $FF6C R/W) オブジェクト優先モード
     pub svbk: u8,           // (Addr $FF70 R/W) WRAM バンク
     pub pcm12: u8,          // (Addr $FF76 R) Audio digital outputs 1 & 2
@@ -65,11 +41,22 @@ pub struct CGB {
 
     pub unlock_flg: bool,   // アンロックフラグ
     pub cgb_mode: u8,       // CGBモード
+    pub double_speed: bool, // 倍速モード (KEY1 Bit7, STOPで切り替わる)
+    pub hdma_active: bool,  // H-Blank DMAの転送中
+    pub hdma_src: u16,      // H-Blank DMAの次の転送元
+    pub hdma_dst: u16,      // H-Blank DMAの次の転送先
+}
+
+impl Default for CGB {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl CGB {
     pub fn new() -> Self {
         CGB {
+            key_0: 0,
             key_1: 0,
             vbk: 0,
             hdma1: 0,
@@ -78,12 +65,6 @@ impl CGB {
             hdma4: 0,
             hdma5: 0,
             rp: 0,
-            bcps: 0,
-            bgpi: 0,
-            bg_col_plt: vec![0; _COLOR_PALETTE_SIZE],
-            ocps: 0,
-            obpi: 0,
-            obj_col_plt: vec![0; _COLOR_PALETTE_SIZE],
             opri: 0,
             svbk: 0,
             pcm12: 0,
@@ -91,6 +72,10 @@ impl CGB {
 
             unlock_flg: false,
             cgb_mode: CGB_MODE_NONE,
+            double_speed: false,
+            hdma_active: false,
+            hdma_src: 0,
+            hdma_dst: 0,
         }
     }
 
@@ -123,66 +108,39 @@ impl CGB {
 impl IO for CGB {
     fn write(&mut self, addr: u16, val: u8) {
         match addr {
+            0xFF4C => {
+                self.key_0 = val;
+                if val & 0x04 != 0 {
+                    self.cgb_mode = CGB_MODE_NON_CGB;
+                }
+            }
             0xFF4D => self.key_1 = val & 0x01, // Bit7はRO,Bit0はR/W
-            0xFF4F => self.vbk = val,
+            0xFF4F => self.vbk = val & 0x01, // Bit0のみ有効
             0xFF51 => self.hdma1 = val,
             0xFF52 => self.hdma2 = val,
             0xFF53 => self.hdma3 = val,
             0xFF54 => self.hdma4 = val,
             0xFF55 => self.hdma5 = val,
             0xFF56 => self.rp = val,
-            0xFF68 => {
-                // BGPI(Bit7) = インクリメント方法
-                self.bgpi = (val & _BIT_7) >> 7;
-                // BCPS(Bit[5:0]) = パレットのインデックス
-                self.bcps = val & 0x3F;
-            },
-            0xFF69 => {
-                // BCPD/BGPD
-                self.bg_col_plt[self.bcps as usize] = val & 0xFF;
-                if self.bgpi != 0 {
-                    self.bcps = (self.bcps + 1) & 0x3F;
-                }
-            },
-            0xFF6A => {
-                // OBPI(Bit7) = インクリメント方法
-                self.obpi = (val & _BIT_7) >> 7;
-                // OCPS(Bit[5:0]) = パレットのインデックス
-                self.ocps = val & 0x3F;
-            },
-            0xFF6B => {
-                // OCPD/OBPD
-                self.obj_col_plt[self.ocps as usize] = val & 0xFF;
-                if self.obpi != 0 {
-                    self.ocps = (self.ocps + 1) & 0x3F;
-                }
-            },
             0xFF6C => self.opri = val,
             0xFF70 => self.svbk = val,
-            _ => panic!("[ERR] CGB Reg Invalid Addr (Write to: ${:#04X})", addr),
+            _ => (),
         }
     }
 
     fn read(&mut self, addr: u16) -> u8 {
         match addr {
-            0xFF4D => self.key_1,
-            0xFF4F => self.vbk,
+            0xFF4C => 0xFF, // 読めない
+            0xFF4D => 0x7E | (u8::from(self.double_speed) << 7) | self.key_1,
+            0xFF4F => 0xFE | self.vbk,
+            0xFF51..=0xFF54 => 0xFF, // 書き込み専用
+            0xFF55 => self.hdma5,
             0xFF56 => self.rp,
-            0xFF68 => {
-                // BGPI(Bit7) | BCPS(Bit[5:0])
-                self.bgpi << 7 | self.bcps
-            },
-            0xFF69 => self.bg_col_plt[self.bcps as usize],
-            0xFF6A => {
-                // OBPI(Bit7) | OCPS(Bit[5:0])
-                self.obpi << 7 | self.ocps
-            },
-            0xFF6B => self.obj_col_plt[self.ocps as usize],
             0xFF6C => self.opri,
             0xFF70 => self.svbk,
             0xFF76 => self.pcm12,
             0xFF77 => self.pcm34,
-            _ => panic!("[ERR] CGB Reg Invalid Addr (Read to: ${:#04X})", addr),
+            _ => 0xFF, // 未使用
         }
     }
 
@@ -34,7 +34,7 @@ pub const _BIT_29: u32 = 0x20000000;
 pub const _BIT_30: u32 = 0x40000000;
 pub const _BIT_31: u32 = 0x80000000;
 
-pub const _MEM_SIZE_1K:   u16 =   1 * 1024;
+pub const _MEM_SIZE_1K:   u16 =   1024;
 pub const _MEM_SIZE_2K:   u16 =   2 * 1024;
 pub const _MEM_SIZE_4K:   u16 =   4 * 1024;
 pub const _MEM_SIZE_8K:   u16 =   8 * 1024;
@@ -1,5 +1,12 @@
+use serde::{Deserialize, Serialize};
+use bios;
+use bios::Model;
+use cgb::CGB_MODE_NON_CGB;
+use bios::BIOS;
+use cartridge::Cartridge;
 use mmu::MMU;
 
+#[derive(Serialize, Deserialize)]
 pub struct CPU {
     pub mmu: MMU,
     reg_a: u8, reg_f: u8, // AF
@@ -10,27 +17,59 @@ pub struct CPU {
     sp: u16,
     ime: bool,
     halted: bool,
+    halt_bug: bool, // HALTバグ (次のオペコードの読み出しでPCが進まない)
+    ei_delay: bool, // EIの次の命令の後でIMEをセットする
+    stopped: bool,  // STOPモード (ボタンが押されるまでシステムクロックが止まる)
+    lockup: Option<(u16, u8)>,  // 不正なオペコードで固まった (PC, オペコード)
     // DMG 4.194304 MHz, CGB 8.388608 MHz
     tick: u8,
+    #[serde(skip)]
+    elapsed: u8,    // 現在のステップで経過したクロック (通常速度)
 }
 
 impl CPU {
-    pub fn new(bios_path: &str, rom_path: &str) -> Self {
+    pub fn new(bios: BIOS, cartridge: Cartridge) -> Self {
         CPU {
-            mmu: MMU::new(bios_path, rom_path),
+            mmu: MMU::new(bios, cartridge),
             reg_a: 0, reg_f: 0,
             reg_b: 0, reg_c: 0,
             reg_d: 0, reg_e: 0,
             reg_h: 0, reg_l: 0,
             ime: false,
             halted: false,
-            // pc: 0, // BIOS
-            pc: 0x0100, // BIOS Skip
+            halt_bug: false,
+            ei_delay: false,
+            stopped: false,
+            lockup: None,
+            pc: 0,
             sp: 0,
             tick: 0,
+            elapsed: 0,
         }
     }
 
+    // Starts the boot ROM from $0000, or sets the post-boot state if no boot ROM is loaded.
+    pub fn boot(&mut self, model: Model) {
+        self.mmu.model = model;
+
+        if self.mmu.bios.is_boot {
+            self.pc = 0x0000;
+            return;
+        }
+
+        // BIOS Skip
+        let cgb_mode = self.mmu.cgb.unlock_flg && self.mmu.cgb.cgb_mode != CGB_MODE_NON_CGB;
+        let regs = bios::post_boot_regs(model, cgb_mode, self.mmu.cartridge.rom());
+        self.reg_a = regs.a; self.reg_f = regs.f;
+        self.reg_b = regs.b; self.reg_c = regs.c;
+        self.reg_d = regs.d; self.reg_e = regs.e;
+        self.reg_h = regs.h; self.reg_l = regs.l;
+        self.sp = 0xFFFE;
+        self.pc = 0x0100;
+
+        self.mmu.post_boot();
+    }
+
     // Reads AF register
     fn af(&self) -> u16 {
         (self.reg_a as u16) << 8 | self.reg_f as u16
@@ -239,20 +278,38 @@ impl CPU {
         }
     }
 
-    // Writes 8-bit value to memory
+    // Advances the rest of the system by one M-cycle (4 clocks).
+    // メモリアクセスと内部処理の1 M-cycleごとに呼ぶ
+    fn cycle(&mut self) {
+        self.tick += 4;
+        self.elapsed += self.mmu.update(4);
+    }
+
+    // Writes 8-bit value to memory (1 M-cycle, アクセスはM-cycleの最後)
     fn write_mem8(&mut self, addr: u16, val: u8) {
-        self.mmu.write(addr, val);
+        self.cycle();
 
-        self.tick += 4;
+        self.mmu.write(addr, val);
     }
 
-    // Reads 8-bit value from memory
+    // Reads 8-bit value from memory (1 M-cycle, アクセスはM-cycleの最後)
     fn read_mem8(&mut self, addr: u16) -> u8 {
-        let ret = self.mmu.read(addr);
+        self.cycle();
 
-        self.tick += 4;
+        self.mmu.read(addr)
+    }
+
+    // Pushes 16-bit value to the stack (internal delay, upper byte, lower byte)
+    fn push16(&mut self, val: u16) {
+        self.cycle();
+
+        self.sp = self.sp.wrapping_sub(1);
+        let sp = self.sp;
+        self.write_mem8(sp, (val >> 8) as u8);
 
-        ret
+        self.sp = self.sp.wrapping_sub(1);
+        let sp = self.sp;
+        self.write_mem8(sp, (val & 0xFF) as u8);
     }
 
     // Writes 16-bit value to memory
@@ -297,7 +354,7 @@ impl CPU {
     fn ld_sp_hl(&mut self) {
         trace!("LD SP, HL");
 
-        self.tick += 4;
+        self.cycle();
 
         self.sp = self.hl();
     }
@@ -313,7 +370,7 @@ impl CPU {
         let (res, carry) = hl.overflowing_add(val);
         self.set_hl(res);
 
-        self.tick += 4;
+        self.cycle();
 
         self.set_f_n(false);
         self.set_f_h(half_carry);
@@ -342,7 +399,8 @@ impl CPU {
 
         self.sp = self._add_sp(val);
 
-        self.tick += 8;
+        self.cycle();
+        self.cycle();
     }
 
     // LD HL, SP+d8
@@ -351,7 +409,7 @@ impl CPU {
 
         trace!("LD HL, SP{:+}", offset);
 
-        self.tick += 4;
+        self.cycle();
 
         let res = self._add_sp(offset);
         self.set_hl(res);
@@ -885,7 +943,7 @@ impl CPU {
     fn _jp(&mut self, addr: u16) {
         self.pc = addr;
 
-        self.tick += 4;
+        self.cycle();
     }
 
     fn jp_cc_d8(&mut self, cci: u8) {
@@ -928,7 +986,7 @@ impl CPU {
     fn _jr(&mut self, offset: i8) {
         self.pc = self.pc.wrapping_add(offset as u16);
 
-        self.tick += 4;
+        self.cycle();
     }
 
     // Jump to pc+d8
@@ -1024,13 +1082,9 @@ impl CPU {
     }
 
     fn _call(&mut self, addr: u16) {
-        self.sp = self.sp.wrapping_sub(2);
-        let sp = self.sp;
         let pc = self.pc;
 
-        self.tick += 4;
-
-        self.write_mem16(sp, pc);
+        self.push16(pc);
         self.pc = addr;
     }
 
@@ -1065,7 +1119,7 @@ impl CPU {
         self.pc = self.read_mem16(sp);
         self.sp = self.sp.wrapping_add(2);
 
-        self.tick += 4;
+        self.cycle();
     }
 
     // RET
@@ -1079,7 +1133,7 @@ impl CPU {
     fn ret_cc(&mut self, cci: u8) {
         trace!("RET {}", Self::cc_to_string(cci));
 
-        self.tick += 4;
+        self.cycle();
 
         if self.cc(cci) {
             self._ret();
@@ -1090,52 +1144,36 @@ impl CPU {
     fn push_bc(&mut self) {
         trace!("PUSH BC");
 
-        self.sp = self.sp.wrapping_sub(2);
         let val = self.bc();
-        let sp = self.sp;
 
-        self.tick += 4;
-
-        self.write_mem16(sp, val);
+        self.push16(val);
     }
 
     // PUSH DE
     fn push_de(&mut self) {
         trace!("PUSH DE");
 
-        self.sp = self.sp.wrapping_sub(2);
         let val = self.de();
-        let sp = self.sp;
 
-        self.tick += 4;
-
-        self.write_mem16(sp, val);
+        self.push16(val);
     }
 
     // PUSH HL
     fn push_hl(&mut self) {
         trace!("PUSH HL");
 
-        self.sp = self.sp.wrapping_sub(2);
         let val = self.hl();
-        let sp = self.sp;
-
-        self.tick += 4;
 
-        self.write_mem16(sp, val);
+        self.push16(val);
     }
 
     // PUSH AF
     fn push_af(&mut self) {
         trace!("PUSH AF");
 
-        self.sp = self.sp.wrapping_sub(2);
         let val = self.af();
-        let sp = self.sp;
-
-        self.tick += 4;
 
-        self.write_mem16(sp, val);
+        self.push16(val);
     }
 
     // POP BC
@@ -1213,7 +1251,7 @@ impl CPU {
         let val = self.read_r16(reg);
         self.write_r16(reg, val.wrapping_add(1));
 
-        self.tick += 4;
+        self.cycle();
     }
 
     fn dec_r16(&mut self, reg: u8) {
@@ -1222,7 +1260,7 @@ impl CPU {
         let val = self.read_r16(reg);
         self.write_r16(reg, val.wrapping_sub(1));
 
-        self.tick += 4;
+        self.cycle();
     }
 
     fn ld_ind_d16_a(&mut self) {
@@ -1247,13 +1285,14 @@ impl CPU {
         trace!("DI");
 
         self.ime = false;
+        self.ei_delay = false;
     }
 
-    // Enable interrupt
+    // Enable interrupt (次の命令の後から有効)
     fn ei(&mut self) {
         trace!("EI");
 
-        self.ime = true;
+        self.ei_delay = true;
     }
 
     // Enable interrupt and return
@@ -1286,83 +1325,152 @@ impl CPU {
         }
     }
 
+    // STOP
+    // https://gbdev.io/pandocs/Reducing_Power_Consumption.html#using-the-stop-instruction
+    // 2Byte命令 (0x10 0x00) だが、割り込みが保留されていると1Byte命令になる
+    fn stop(&mut self) {
+        trace!("STOP");
+
+        let pending = self.irq_pending();
+
+        // ボタンが押されていたらSTOPモードに入らない (DIVもリセットしない)
+        if self.mmu.gamepad.selected_pressed() {
+            if !pending {
+                self.pc = self.pc.wrapping_add(1);
+                self.halt();
+            }
+            return;
+        }
+
+        if !pending {
+            self.pc = self.pc.wrapping_add(1);
+        }
+        self.mmu.reset_div();
+
+        if self.mmu.speed_switch_requested() {
+            // KEY1で準備されていれば速度を切り替える
+            self.mmu.switch_speed();
+        } else {
+            self.stopped = true;
+        }
+    }
+
     // HALT
+    // https://gbdev.io/pandocs/halt.html
+    // IE & IFが立つまで (IMEに関係なく) CPUを止める
     fn halt(&mut self) {
         trace!("HALT");
 
-        if self.ime {
+        if !self.irq_pending() {
             self.halted = true;
+        } else if !self.ime {
+            // HALTバグ: IME=0で割り込みが保留されているとHALTせず、次のバイトを2回読む
+            self.halt_bug = true;
         }
+        // IME=1で割り込みが保留されていれば、HALTせずにそのまま割り込む
+    }
+
+    // Returns true if an enabled interrupt is requested. (IE & IF)
+    fn irq_pending(&self) -> bool {
+        self.mmu.interrupt.pending() != 0
     }
 
+    // Returns the address and opcode if the CPU is locked up by an illegal opcode.
+    pub fn lockup(&self) -> Option<(u16, u8)> {
+        self.lockup
+    }
+
+    // Illegal opcode ($D3, $DB, $DD, $E3, $E4, $EB, $EC, $ED, $F4, $FC, $FD)
+    // 実機はCPUが固まる (割り込みも受け付けない)。PPUなどは動き続ける
+    fn illegal(&mut self, opcode: u8) {
+        let pc = self.pc.wrapping_sub(1);
+        warn!("Illegal opcode 0x{:02X} at 0x{:04X}, CPU locked up", opcode, pc);
+
+        self.lockup = Some((pc, opcode));
+    }
+
+    // Executes a single instruction (or ISR call).
+    // Returns the elapsed clocks at normal speed. (倍速モードではCPUクロックの半分)
     pub fn step(&mut self) -> u8 {
-        let mut total_tick = 0;
+        if self.stopped {
+            // ボタンが押されたらSTOPモードから復帰する
+            if self.mmu.gamepad.selected_pressed() {
+                self.stopped = false;
+            } else {
+                return self.mmu.update_stopped(4);
+            }
+        }
 
         self.tick = 0;
-
-        if self.halted {
-            self.tick += 4;
+        self.elapsed = 0;
+
+        if self.lockup.is_some() {
+            // 固まったCPUはリセットまで何もしない
+            self.cycle();
+        } else if self.mmu.cpu_stall > 0 {
+            // 速度切り替え中, VRAM DMA転送中
+            self.cycle();
+            self.mmu.cpu_stall = self.mmu.cpu_stall.saturating_sub(4);
+        } else if self.ime && self.irq_pending() {
+            // 割り込みは命令の境界で受け付ける
+            self.dispatch_irq();
+        } else if self.halted {
+            self.cycle();
         } else {
+            // EIの効果は次の命令の後から (EIの直後の命令は割り込まれない)
+            if self.ei_delay {
+                self.ei_delay = false;
+                self.ime = true;
+            }
             self.fetch_and_exec();
         }
 
-        total_tick += self.tick;
-
-        self.mmu.update(self.tick);
-
-        if self.ime {
-            self.tick = 0;
-            self.check_irqs();
-            self.mmu.update(self.tick);
-
-            total_tick += self.tick;
+        // 割り込みが要求されたらHALTから復帰する (IME=0なら割り込まずに次の命令へ)
+        if self.halted && self.irq_pending() {
+            self.halted = false;
         }
 
-        total_tick
+        self.elapsed
     }
 
-    // Checks IRQs and execute ISRs if requested.
-    fn check_irqs(&mut self) {
-        // Bit 0 has the highest priority
-        for i in 0..5 {
-            let irq = self.mmu.int_flag & (1 << i) > 0;
-            let ie = self.mmu.int_enable & (1 << i) > 0;
-
-            // If interrupt is requested and enabled
-            if irq && ie {
-                self.call_isr(i);
-                break;
-            }
-        }
-    }
-
-    // Calls requested interrupt service routine.
-    fn call_isr(&mut self, id: u8) {
-        // Reset corresponding bit in IF
-        self.mmu.int_flag &= !(1 << id);
-        // Clear IME (disable any further interrupts)
+    // Dispatches the highest priority interrupt. (5 M-cycle)
+    // https://gbdev.io/pandocs/Interrupts.html#interrupt-handling
+    // M1~M2: 待ち, M3: PC上位をプッシュ, M4: PC下位をプッシュ, M5: ベクタへジャンプ
+    fn dispatch_irq(&mut self) {
         self.ime = false;
         self.halted = false;
+        self.cycle();
+        self.cycle();
 
-        let isr: u16 = match id {
-            0 => 0x40,
-            1 => 0x48,
-            2 => 0x50,
-            3 => 0x80,
-            4 => 0x70,
-            _ => panic!("Invalid IRQ id {}", id),
-        };
+        let pc = self.pc;
+        self.sp = self.sp.wrapping_sub(1);
+        let sp = self.sp;
+        self.write_mem8(sp, (pc >> 8) as u8);
+
+        // どの割り込みかは上位バイトをプッシュした後で決まる
+        // SP=$0000だとプッシュでIE ($FFFF) が書き換わり、割り込みがなくなると$0000へジャンプする
+        let vector = self.mmu.interrupt.acknowledge();
 
-        self.tick += 8;
+        self.sp = self.sp.wrapping_sub(1);
+        let sp = self.sp;
+        self.write_mem8(sp, pc as u8);
 
-        debug!("Calling ISR 0x{:02x}", isr);
+        self.pc = vector.unwrap_or(0x0000);
+        self.cycle();
 
-        self._call(isr);
+        debug!("Calling ISR 0x{:02x}", self.pc);
     }
 
     // Fetches and executes reg_a single instructions.
     fn fetch_and_exec(&mut self) {
-        let opcode = self.read_d8();
+        let opcode = if self.halt_bug {
+            // HALTバグ: PCを進めずに読む
+            self.halt_bug = false;
+            let pc = self.pc;
+            self.read_mem8(pc)
+        } else {
+            self.read_d8()
+        };
         let reg = opcode & 7;
         let reg2 = opcode >> 3 & 7;
 
@@ -1513,7 +1621,11 @@ impl CPU {
             // HALT
             0x76 => self.halt(),
 
-            _ => panic!("Unimplemented opcode 0x{:x}", opcode),
+            // STOP
+            0x10 => self.stop(),
+
+            // Illegal
+            0xD3 | 0xDB | 0xDD | 0xE3 | 0xE4 | 0xEB | 0xEC | 0xED | 0xF4 | 0xFC | 0xFD => self.illegal(opcode),
         }
     }
 
@@ -1527,3 +1639,289 @@ impl CPU {
         println!("T:  {}", self.tick);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cartridge::tests::cartridge;
+    use gamepad::Key;
+    use interrupt::{INT_SERIAL, INT_TIMER, INT_VBLANK};
+
+    // $0150からプログラムを実行するCPU (ブートROMなし)
+    fn cpu(model: Model, code: &[u8]) -> CPU {
+        let mut cpu = CPU::new(BIOS::none(), cartridge(0x00, 0, 0, code));
+        if model.is_cgb() {
+            cpu.mmu.cgb.cgb_unlock(0xC0);
+        }
+        cpu.boot(model);
+        cpu.pc = 0x0150;
+        // 割り込みはテストで要求したシリアル割り込みだけにする
+        cpu.mmu.write(0xFF0F, 0x00);
+        cpu.mmu.write(0xFFFF, INT_SERIAL);
+        cpu
+    }
+
+    fn request_serial(cpu: &mut CPU) {
+        cpu.mmu.interrupt.request(INT_SERIAL);
+    }
+
+    #[test]
+    fn halt_waits_for_interrupt_without_ime() {
+        // HALT, INC A
+        let mut cpu = cpu(Model::DMG, &[0x76, 0x3C]);
+        let a = cpu.reg_a;
+
+        assert_eq!(cpu.step(), 4);
+        assert!(cpu.halted);
+        for _ in 0..100 {
+            assert_eq!(cpu.step(), 4);
+            assert_eq!(cpu.pc, 0x0151);
+        }
+
+        // IME=0なら割り込まずに次の命令から再開する
+        request_serial(&mut cpu);
+        cpu.step();
+        assert!(!cpu.halted);
+        cpu.step();
+        assert_eq!(cpu.pc, 0x0152);
+        assert_eq!(cpu.reg_a, a.wrapping_add(1));
+        assert_eq!(cpu.mmu.read(0xFF0F) & INT_SERIAL, INT_SERIAL);
+    }
+
+    #[test]
+    fn halt_bug_reads_next_byte_twice() {
+        // HALT, INC A, NOP
+        let mut cpu = cpu(Model::DMG, &[0x76, 0x3C, 0x00]);
+        let a = cpu.reg_a;
+        request_serial(&mut cpu);
+
+        // IME=0で割り込みが保留されているとHALTしない
+        cpu.step();
+        assert!(!cpu.halted);
+        assert_eq!(cpu.pc, 0x0151);
+
+        // INC A が2回実行される
+        cpu.step();
+        assert_eq!(cpu.pc, 0x0151);
+        cpu.step();
+        assert_eq!(cpu.pc, 0x0152);
+        assert_eq!(cpu.reg_a, a.wrapping_add(2));
+    }
+
+    #[test]
+    fn halt_with_ime_wakes_into_isr() {
+        // HALT
+        let mut cpu = cpu(Model::DMG, &[0x76]);
+        cpu.ime = true;
+
+        cpu.step();
+        assert!(cpu.halted);
+        cpu.step();
+        assert!(cpu.halted);
+
+        request_serial(&mut cpu);
+        cpu.step();
+        assert!(!cpu.halted);
+        assert_eq!(cpu.pc, 0x0058);
+        // 戻り先はHALTの次
+        assert_eq!(cpu.mmu.read(cpu.sp), 0x51);
+    }
+
+    #[test]
+    fn ei_takes_effect_after_next_instruction() {
+        // EI, NOP, NOP
+        let mut cpu = cpu(Model::DMG, &[0xFB, 0x00, 0x00]);
+        request_serial(&mut cpu);
+
+        cpu.step();
+        assert_eq!(cpu.pc, 0x0151);
+        // EIの直後の命令は割り込まれない
+        cpu.step();
+        assert_eq!(cpu.pc, 0x0152);
+
+        assert_eq!(cpu.step(), 20);
+        assert_eq!(cpu.pc, 0x0058);
+        assert!(!cpu.ime);
+        assert_eq!(cpu.mmu.read(0xFF0F) & INT_SERIAL, 0);
+        assert_eq!(cpu.mmu.read(cpu.sp), 0x52);
+        assert_eq!(cpu.mmu.read(cpu.sp.wrapping_add(1)), 0x01);
+    }
+
+    #[test]
+    fn di_cancels_pending_ei() {
+        // EI, DI, NOP
+        let mut cpu = cpu(Model::DMG, &[0xFB, 0xF3, 0x00]);
+        request_serial(&mut cpu);
+
+        cpu.step();
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.pc, 0x0153);
+        assert!(!cpu.ime);
+    }
+
+    #[test]
+    fn dispatch_picks_vector_after_high_byte_push() {
+        let mut cpu = cpu(Model::DMG, &[0x00]);
+        cpu.ime = true;
+        // SP=$0000: PC上位($01)のプッシュでIE($FFFF)が書き換わる
+        cpu.sp = 0x0000;
+        cpu.mmu.write(0xFFFF, INT_VBLANK | INT_SERIAL);
+        request_serial(&mut cpu);
+
+        // IE = $01 になり、シリアル割り込みはキャンセルされて$0000へジャンプする
+        assert_eq!(cpu.step(), 20);
+        assert_eq!(cpu.pc, 0x0000);
+        assert_eq!(cpu.mmu.read(0xFFFF), 0x01);
+        assert_eq!(cpu.mmu.read(0xFF0F) & INT_SERIAL, INT_SERIAL);
+    }
+
+    #[test]
+    fn dispatch_takes_highest_priority() {
+        let mut cpu = cpu(Model::DMG, &[0x00]);
+        cpu.ime = true;
+        cpu.mmu.write(0xFFFF, INT_TIMER | INT_SERIAL);
+        cpu.mmu.interrupt.request(INT_TIMER | INT_SERIAL);
+
+        cpu.step();
+        assert_eq!(cpu.pc, 0x0050);
+        assert_eq!(cpu.mmu.read(0xFF0F) & 0x1F, INT_SERIAL);
+    }
+
+    #[test]
+    fn instruction_cycles() {
+        let cases: [(&[u8], u8); 16] = [
+            (&[0x00], 4),               // NOP
+            (&[0x01, 0x34, 0x12], 12),  // LD BC, d16
+            (&[0x08, 0x00, 0xC1], 20),  // LD (a16), SP
+            (&[0x34], 12),              // INC (HL)
+            (&[0xC5], 16),              // PUSH BC
+            (&[0xC1], 12),              // POP BC
+            (&[0xCD, 0x00, 0x02], 24),  // CALL a16
+            (&[0xC9], 16),              // RET
+            (&[0x28, 0x10], 12),        // JR Z, e (分岐する)
+            (&[0x20, 0x10], 8),         // JR NZ, e (分岐しない)
+            (&[0xE9], 4),               // JP (HL)
+            (&[0xFF], 16),              // RST $38
+            (&[0xE8, 0x01], 16),        // ADD SP, e
+            (&[0xF8, 0x01], 12),        // LD HL, SP+e
+            (&[0xCB, 0x46], 12),        // BIT 0, (HL)
+            (&[0xCB, 0xC6], 16),        // SET 0, (HL)
+        ];
+
+        for &(code, clocks) in &cases {
+            let mut cpu = cpu(Model::DMG, code);
+            cpu.reg_f = 0x80;
+            cpu.set_hl(0xC000);
+            assert_eq!(cpu.step(), clocks, "{:02X?}", code);
+        }
+    }
+
+    #[test]
+    fn memory_access_sees_mid_instruction_time() {
+        // TIMAを16クロックごとに進める
+        fn start_timer(cpu: &mut CPU) {
+            cpu.mmu.write(0xFF07, 0x05);
+            cpu.mmu.write(0xFF05, 0x00);
+            cpu.mmu.write(0xFF04, 0x00);
+        }
+
+        // LD A, ($FF05) は4 M-cycle目 (16クロック後) に読む
+        let mut ld = cpu(Model::DMG, &[0xFA, 0x05, 0xFF]);
+        start_timer(&mut ld);
+        ld.step();
+        assert_eq!(ld.reg_a, 0x01);
+
+        // LDH A, ($05) は3 M-cycle目 (12クロック後) に読む
+        let mut ldh = cpu(Model::DMG, &[0xF0, 0x05]);
+        start_timer(&mut ldh);
+        ldh.step();
+        assert_eq!(ldh.reg_a, 0x00);
+        assert_eq!(ldh.mmu.read(0xFF05), 0x00);
+        ldh.mmu.update(4);
+        assert_eq!(ldh.mmu.read(0xFF05), 0x01);
+    }
+
+    #[test]
+    fn double_speed_halves_elapsed_clocks() {
+        let mut cpu = cpu(Model::CGB, &[0x00, 0xCD, 0x00, 0x02]);
+        cpu.mmu.cgb.double_speed = true;
+        assert_eq!(cpu.step(), 2);
+        assert_eq!(cpu.step(), 12);
+    }
+
+    #[test]
+    fn illegal_opcode_locks_up() {
+        for &opcode in &[0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD] {
+            let mut cpu = cpu(Model::DMG, &[opcode]);
+            cpu.step();
+            assert_eq!(cpu.lockup(), Some((0x0150, opcode)));
+        }
+
+        let mut cpu = cpu(Model::DMG, &[0xD3]);
+        cpu.step();
+
+        // 割り込みも受け付けない
+        cpu.ime = true;
+        request_serial(&mut cpu);
+        let ly = cpu.mmu.read(0xFF44);
+        for _ in 0..1000 {
+            assert_eq!(cpu.step(), 4);
+            assert_eq!(cpu.pc, 0x0151);
+        }
+        assert_eq!(cpu.mmu.read(0xFF0F) & INT_SERIAL, INT_SERIAL);
+        // PPUは動き続ける
+        assert_ne!(cpu.mmu.read(0xFF44), ly);
+    }
+
+    #[test]
+    fn stop_switches_speed() {
+        // LD A, $01; LDH ($4D), A; STOP; INC A
+        let mut cpu = cpu(Model::CGB, &[0x3E, 0x01, 0xE0, 0x4D, 0x10, 0x00, 0x3C]);
+        cpu.step();
+        cpu.step();
+        assert_eq!(cpu.mmu.read(0xFF4D), 0x7F);
+
+        cpu.step();
+        assert!(cpu.mmu.double_speed());
+        assert_eq!(cpu.mmu.read(0xFF4D), 0xFE);
+        assert_eq!(cpu.mmu.read(0xFF04), 0x00);
+        assert_eq!(cpu.pc, 0x0156);
+
+        // 切り替え中 (2050 M-cycle) はCPUが止まる
+        let mut stalled = 0;
+        while cpu.mmu.cpu_stall > 0 {
+            assert_eq!(cpu.step(), 2);
+            assert_eq!(cpu.pc, 0x0156);
+            stalled += 1;
+        }
+        assert_eq!(stalled, 2050);
+        assert_eq!(cpu.step(), 2);
+        assert_eq!(cpu.reg_a, 0x02);
+    }
+
+    #[test]
+    fn stop_waits_for_button() {
+        // STOP; INC A
+        let mut cpu = cpu(Model::DMG, &[0x10, 0x00, 0x3C]);
+        let a = cpu.reg_a;
+        cpu.mmu.write(0xFF00, 0x10);
+
+        cpu.step();
+        assert!(cpu.stopped);
+        let ly = cpu.mmu.read(0xFF44);
+        for _ in 0..1000 {
+            assert_eq!(cpu.step(), 4);
+        }
+        // システムクロックが止まるのでPPUも止まる
+        assert_eq!(cpu.mmu.read(0xFF44), ly);
+        assert_eq!(cpu.reg_a, a);
+
+        // ボタンで復帰する
+        cpu.mmu.gamepad.keydown(Key::A);
+        cpu.step();
+        assert!(!cpu.stopped);
+        assert_eq!(cpu.pc, 0x0153);
+        assert_eq!(cpu.reg_a, a.wrapping_add(1));
+    }
+}
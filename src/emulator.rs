@@ -0,0 +1,403 @@
+use std::collections::VecDeque;
+use std::io;
+
+use bios::{BIOS, Model};
+use camera;
+use cartridge::Cartridge;
+use cpu::CPU;
+use error::LoadError;
+use gamepad::Key;
+use savestate;
+use savestate::StateError;
+
+// 1フレーム = 456クロック × 154ライン (144ライン + V-Blank 10ライン)
+pub const FRAME_TICKS: u32 = 456 * (144 + 10);
+// 取り出されずに溜まったイベントの上限 (超えたら古いものから捨てる)
+const MAX_EVENTS: usize = 256;
+
+// Emulator settings.
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    pub bios_path: Option<String>,      // Boot ROM (None: start from the post-boot state)
+    pub model: Option<Model>,           // Hardware model (None: selected from the cartridge header)
+    pub lenient: bool,                  // Warn instead of failing on broken ROM headers
+    pub camera_image: Option<String>,   // PNG/PGM image for the Pocket Camera (None: test pattern)
+    pub rom_entry: Option<String>,      // ROM file name in a zip archive (None: the first .gb/.gbc)
+    pub patch: Option<String>,          // IPS/UPS/BPS patch (None: <ROM>.ips/.ups/.bps if it exists)
+}
+
+// Events reported to the frontend. (Emulator::poll_event)
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    Rumble(bool),   // 振動モーターのON/OFF (MBC5+RUMBLE)
+    Lockup { pc: u16, opcode: u8 },    // 不正なオペコードでCPUが固まった
+}
+
+// Headless emulator facade.
+// フロントエンドはこのAPIだけでエミュレータを操作できる
+pub struct Emulator {
+    cpu: CPU,
+    events: VecDeque<Event>,
+    rumble: bool,
+    lockup: Option<(u16, u8)>,
+}
+
+impl Emulator {
+    // Loads the ROM with the default settings.
+    pub fn new(rom_path: &str) -> Result<Self, LoadError> {
+        Self::with_config(rom_path, &Config::default())
+    }
+
+    // Loads the ROM (and the boot ROM if given) with the given settings.
+    pub fn with_config(rom_path: &str, config: &Config) -> Result<Self, LoadError> {
+        let bios = match config.bios_path {
+            Some(ref path) => BIOS::new(path)?,
+            None => BIOS::none(),
+        };
+        let mut cartridge = Cartridge::open(
+            rom_path,
+            config.rom_entry.as_deref(),
+            config.patch.as_deref(),
+            config.lenient,
+        )?;
+        if let Some(ref path) = config.camera_image {
+            cartridge.set_camera_image(&camera::load_image(path)?);
+        }
+
+        Ok(Self::from_parts(bios, cartridge, config.model))
+    }
+
+    // Boots as the given model (None: selected from the cartridge header).
+    // Without a boot ROM, registers are set to the post-boot state of the model.
+    pub fn from_parts(bios: BIOS, cartridge: Cartridge, model: Option<Model>) -> Self {
+        let mut cpu = CPU::new(bios, cartridge);
+
+        let cgb_flg: u8 = cpu.mmu.cartridge.get_cgb_mode();
+        let model = model.unwrap_or_else(|| Model::from_cgb_flag(cgb_flg));
+        info!("Model: {:?}", model);
+
+        // CGB Unlock
+        if model.is_cgb() {
+            cpu.mmu.cgb.cgb_unlock(cgb_flg);
+        }
+
+        cpu.boot(model);
+
+        Emulator {
+            cpu,
+            events: VecDeque::new(),
+            rumble: false,
+            lockup: None,
+        }
+    }
+
+    // Emulates one frame. Returns elapsed clocks.
+    pub fn run_frame(&mut self) -> u32 {
+        let mut elapsed_tick: u32 = 0;
+
+        while elapsed_tick < FRAME_TICKS {
+            elapsed_tick += self.step() as u32;
+        }
+
+        elapsed_tick
+    }
+
+    // Executes a single instruction (or ISR call).
+    // Returns elapsed clocks at normal speed (CGB double speed: half the CPU clocks).
+    pub fn step(&mut self) -> u8 {
+        let tick = self.cpu.step();
+        self.check_events();
+
+        tick
+    }
+
+    // Checks state changes that are reported as events.
+    fn check_events(&mut self) {
+        let rumble = self.cpu.mmu.cartridge.rumble();
+        if rumble != self.rumble {
+            self.rumble = rumble;
+            self.push_event(Event::Rumble(rumble));
+        }
+
+        let lockup = self.cpu.lockup();
+        if lockup != self.lockup {
+            self.lockup = lockup;
+            if let Some((pc, opcode)) = lockup {
+                self.push_event(Event::Lockup { pc, opcode });
+            }
+        }
+    }
+
+    fn push_event(&mut self, event: Event) {
+        if self.events.len() >= MAX_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    // Returns the next pending event.
+    pub fn poll_event(&mut self) -> Option<Event> {
+        self.events.pop_front()
+    }
+
+    // Returns true if the rumble motor is on.
+    pub fn rumble(&self) -> bool {
+        self.rumble
+    }
+
+    // Returns the address and opcode if the CPU is locked up by an illegal opcode.
+    pub fn lockup(&self) -> Option<(u16, u8)> {
+        self.cpu.lockup()
+    }
+
+    // Sets the tilt of the cartridge in G (MBC7 accelerometer).
+    // x: positive = tilted right, y: positive = tilted toward the player (down)
+    pub fn set_accelerometer(&mut self, x: f32, y: f32) {
+        self.cpu.mmu.cartridge.set_accelerometer(x, y);
+    }
+
+    // Sets the image seen by the Pocket Camera sensor. (CAMERA_W x CAMERA_H, 0 = black, 255 = white)
+    pub fn set_camera_image(&mut self, image: &[u8]) {
+        self.cpu.mmu.cartridge.set_camera_image(image);
+    }
+
+    // Returns true if the cartridge IR LED is on. (HuC1/HuC3)
+    pub fn ir_output(&self) -> bool {
+        self.cpu.mmu.cartridge.ir_led()
+    }
+
+    // Sets whether the cartridge IR receiver sees light. (HuC1/HuC3)
+    pub fn set_ir_input(&mut self, light: bool) {
+        self.cpu.mmu.cartridge.set_ir_input(light);
+    }
+
+    // Connects the IR ports of two emulators (each receives the other's LED).
+    // 赤外線通信はパルスの長さで送るので、step()ごとに呼ぶ
+    pub fn exchange_ir(&mut self, other: &mut Emulator) {
+        let (a, b) = (self.ir_output(), other.ir_output());
+        self.set_ir_input(b);
+        other.set_ir_input(a);
+    }
+
+    // Presses a button.
+    pub fn keydown(&mut self, key: Key) {
+        self.cpu.mmu.gamepad.keydown(key);
+    }
+
+    // Releases a button.
+    pub fn keyup(&mut self, key: Key) {
+        self.cpu.mmu.gamepad.keyup(key);
+    }
+
+    // Presses or releases a button.
+    pub fn set_button(&mut self, key: Key, pressed: bool) {
+        if pressed {
+            self.keydown(key);
+        } else {
+            self.keyup(key);
+        }
+    }
+
+    // Returns the current frame buffer (SCREEN_W x SCREEN_H, 1 byte per pixel).
+    pub fn frame_buffer(&self) -> &[u8] {
+        self.cpu.mmu.ppu.frame_buffer()
+    }
+
+    // Returns the current color frame buffer (SCREEN_W x SCREEN_H, RGB24 3 bytes per pixel).
+    // DMG games are rendered as gray.
+    pub fn frame_buffer_rgb(&self) -> &[u8] {
+        self.cpu.mmu.ppu.frame_buffer_rgb()
+    }
+
+    // Moves the audio samples generated so far into `out`.
+    // (Interleaved L/R f32 samples at SAMPLE_RATE Hz)
+    pub fn drain_samples(&mut self, out: &mut Vec<f32>) {
+        self.cpu.mmu.apu.drain_samples(out);
+    }
+
+    // Reads a byte from the CPU address space.
+    pub fn read_mem(&mut self, addr: u16) -> u8 {
+        self.cpu.mmu.read(addr)
+    }
+
+    // Writes a byte to the CPU address space.
+    pub fn write_mem(&mut self, addr: u16, val: u8) {
+        self.cpu.mmu.write(addr, val);
+    }
+
+    // Returns true if the cartridge has battery backed save data.
+    pub fn has_battery(&self) -> bool {
+        self.cpu.mmu.cartridge.has_battery()
+    }
+
+    // Loads battery backed RAM from a save file. (Does nothing if the file does not exist)
+    pub fn read_save_file(&mut self, fname: &str) -> Result<(), LoadError> {
+        self.cpu.mmu.cartridge.read_save_file(fname)
+    }
+
+    // Writes battery backed RAM to a save file.
+    pub fn write_save_file(&mut self, fname: &str) -> io::Result<()> {
+        self.cpu.mmu.cartridge.write_save_file(fname)
+    }
+
+    // Writes battery backed RAM to a save file if it was modified since the last write.
+    // Returns true if written. (フロントエンドから定期的に呼ぶ)
+    pub fn flush_save_file(&mut self, fname: &str) -> io::Result<bool> {
+        self.cpu.mmu.cartridge.flush_save_file(fname)
+    }
+
+    // Serializes the whole machine state.
+    pub fn save_state(&self) -> Result<Vec<u8>, StateError> {
+        savestate::save_state(&self.cpu)
+    }
+
+    // Restores the whole machine state.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), StateError> {
+        savestate::load_state(&mut self.cpu, data)?;
+        // 振動・ロックアップの状態も読み込んだ状態に合わせる
+        self.check_events();
+        Ok(())
+    }
+
+    // Writes the whole machine state to a file.
+    pub fn save_state_file(&self, fname: &str) -> Result<(), StateError> {
+        savestate::save_state_file(&self.cpu, fname)
+    }
+
+    // Restores the whole machine state from a file.
+    pub fn load_state_file(&mut self, fname: &str) -> Result<(), StateError> {
+        savestate::load_state_file(&mut self.cpu, fname)?;
+        self.check_events();
+        Ok(())
+    }
+
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut CPU {
+        &mut self.cpu
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use cartridge::tests::cartridge;
+
+    // デバッグビルドではステートの読み込みにスタックを多く使うので、別スレッドで実行する
+    pub fn with_large_stack<F: FnOnce() + Send + 'static>(f: F) {
+        ::std::thread::Builder::new().stack_size(32 * 1024 * 1024).spawn(f).unwrap().join().unwrap();
+    }
+
+    // 未使用領域・書き込み専用レジスタのアドレス
+    const UNMAPPED: [(u16, u16); 8] = [
+        (0xFEA0, 0xFEFF),
+        (0xFF03, 0xFF03),
+        (0xFF08, 0xFF0E),
+        (0xFF4E, 0xFF4E),
+        (0xFF50, 0xFF54),
+        (0xFF57, 0xFF67),
+        (0xFF71, 0xFF75),
+        (0xFF78, 0xFF7F),
+    ];
+
+    #[test]
+    fn unmapped_addresses_are_open_bus() {
+        for &model in &[Model::DMG, Model::CGB] {
+            let mut emu = Emulator::from_parts(BIOS::none(), cartridge(0x00, 0, 0, &[]), Some(model));
+
+            // 全アドレスを読んでもパニックしない
+            for addr in 0x0000..=0xFFFF {
+                emu.read_mem(addr);
+            }
+
+            for &(start, end) in &UNMAPPED {
+                for addr in start..=end {
+                    emu.write_mem(addr, 0x00);
+                    assert_eq!(emu.read_mem(addr), 0xFF, "{:?} ${:04X}", model, addr);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn oam_dma_from_any_page() {
+        let mut emu = Emulator::from_parts(BIOS::none(), cartridge(0x00, 0, 0, &[]), Some(Model::DMG));
+        // LCD OFF (OAMを読めるようにする)
+        emu.write_mem(0xFF40, 0x00);
+        emu.write_mem(0xC000, 0x12);
+
+        emu.write_mem(0xFF46, 0x40);
+        assert_eq!(emu.read_mem(0xFF46), 0x40);
+        assert_eq!(emu.read_mem(0xFE00), 0x01);
+
+        // $E0以降はWRAMのミラーから転送される
+        emu.write_mem(0xFF46, 0xE0);
+        assert_eq!(emu.read_mem(0xFF46), 0xE0);
+        assert_eq!(emu.read_mem(0xFE00), 0x12);
+    }
+
+    #[test]
+    fn rumble_changes_are_reported_once() {
+        let mut emu = Emulator::from_parts(BIOS::none(), cartridge(0x1C, 0, 0, &[0x18, 0xFE]), None);
+        emu.step();
+        assert_eq!(emu.poll_event(), None);
+
+        emu.write_mem(0x4000, 0x08);
+        emu.step();
+        emu.step();
+        assert_eq!(emu.poll_event(), Some(Event::Rumble(true)));
+        assert_eq!(emu.poll_event(), None);
+
+        emu.write_mem(0x4000, 0x00);
+        emu.step();
+        assert_eq!(emu.poll_event(), Some(Event::Rumble(false)));
+    }
+
+    #[test]
+    fn load_state_updates_rumble() {
+        with_large_stack(|| {
+            let mut emu = Emulator::from_parts(BIOS::none(), cartridge(0x1C, 0, 0, &[0x18, 0xFE]), None);
+            let state = emu.save_state().unwrap();
+
+            emu.write_mem(0x4000, 0x08);
+            emu.step();
+            assert_eq!(emu.poll_event(), Some(Event::Rumble(true)));
+
+            // 振動OFFの状態に戻る
+            emu.load_state(&state).unwrap();
+            assert_eq!(emu.poll_event(), Some(Event::Rumble(false)));
+            emu.step();
+            assert_eq!(emu.poll_event(), None);
+        });
+    }
+
+    #[test]
+    fn load_state_keeps_mbc6_flash() {
+        with_large_stack(|| {
+            let mut emu = Emulator::from_parts(BIOS::none(), cartridge(0x20, 0, 0x03, &[0x18, 0xFE]), None);
+            let state = emu.save_state().unwrap();
+            // フラッシュ(1MB)はセーブステートに含めない
+            assert!(state.len() < 1024 * 1024);
+
+            // フラッシュ Enable、書き込み Enable、バンクAをフラッシュにする
+            emu.write_mem(0x0C00, 0x01);
+            emu.write_mem(0x1000, 0x01);
+            emu.write_mem(0x2800, 0x08);
+            // $5555 ← $AA, $2AAA ← $55, $5555 ← $A0 の後に1バイト書き込む
+            for &(bank, addr, val) in &[(2, 0x5555, 0xAA), (1, 0x4AAA, 0x55), (2, 0x5555, 0xA0), (0, 0x4000, 0x12)] {
+                emu.write_mem(0x2000, bank);
+                emu.write_mem(addr, val);
+            }
+            assert_eq!(emu.read_mem(0x4000), 0x12);
+
+            // ステートを読み込んでもフラッシュの内容は残る
+            emu.load_state(&state).unwrap();
+            emu.write_mem(0x0C00, 0x01);
+            emu.write_mem(0x2800, 0x08);
+            emu.write_mem(0x2000, 0x00);
+            assert_eq!(emu.read_mem(0x4000), 0x12);
+        });
+    }
+}
@@ -0,0 +1,116 @@
+use std::fmt;
+use std::io;
+
+// ROM/BIOSロード時のエラー
+#[derive(Debug)]
+pub enum LoadError {
+    Io { path: String, err: io::Error },
+    TruncatedRom { size: usize },
+    RomSizeMismatch { header: usize, file: usize },
+    InvalidRomSize(u8),
+    InvalidRamSize(u8),
+    HeaderChecksum { header: u8, calc: u8 },
+    UnsupportedMapper(u8),
+    InvalidBiosSize(usize),
+    InvalidImage { path: String, reason: String },
+    Archive { path: String, reason: String },
+    RomNotInArchive { path: String, entry: Option<String>, names: Vec<String> },
+    Patch { path: String, reason: String },
+    SaveSizeMismatch { path: String, expected: usize, actual: usize },
+    PatchSizeMismatch { path: String, expected: usize, actual: usize },
+    PatchChecksum { path: String, what: &'static str, expected: u32, actual: u32 },
+}
+
+impl LoadError {
+    // Returns true if the error can be ignored in lenient mode.
+    pub fn is_recoverable(&self) -> bool {
+        matches!(
+            self,
+            LoadError::RomSizeMismatch { .. }
+                | LoadError::InvalidRomSize(_)
+                | LoadError::InvalidRamSize(_)
+                | LoadError::HeaderChecksum { .. }
+                | LoadError::UnsupportedMapper(_)
+                | LoadError::PatchSizeMismatch { .. }
+                | LoadError::PatchChecksum { .. }
+        )
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io { path, err } => write!(f, "Cannot read {}: {}", path, err),
+            LoadError::TruncatedRom { size } => write!(
+                f,
+                "ROM is too small to have a cartridge header ({} bytes)",
+                size
+            ),
+            LoadError::RomSizeMismatch { header, file } => write!(
+                f,
+                "ROM size mismatch (header: {}KB, file: {}KB)",
+                header / 1024,
+                file / 1024
+            ),
+            LoadError::InvalidRomSize(code) => write!(f, "Invalid ROM size code ${:02X}", code),
+            LoadError::InvalidRamSize(code) => write!(f, "Invalid RAM size code ${:02X}", code),
+            LoadError::HeaderChecksum { header, calc } => write!(
+                f,
+                "ROM header checksum is incorrect (header: ${:02X}, calculated: ${:02X})",
+                header, calc
+            ),
+            LoadError::UnsupportedMapper(mbc_type) => {
+                write!(f, "Unsupported cartridge type ${:02X}", mbc_type)
+            }
+            LoadError::InvalidBiosSize(size) => write!(
+                f,
+                "Invalid boot ROM size ({} bytes, expected 256 or 2304 bytes)",
+                size
+            ),
+            LoadError::InvalidImage { path, reason } => write!(f, "Cannot load image {}: {}", path, reason),
+            LoadError::Archive { path, reason } => write!(f, "Cannot extract {}: {}", path, reason),
+            LoadError::RomNotInArchive { path, entry, names } => {
+                match entry {
+                    Some(entry) => write!(f, "{} is not found in {}", entry, path)?,
+                    None => write!(f, "No ROM (.gb/.gbc/.sgb) is found in {}", path)?,
+                }
+                write!(f, " (entries: {})", names.join(", "))
+            }
+            LoadError::Patch { path, reason } => write!(f, "Cannot apply patch {}: {}", path, reason),
+            LoadError::SaveSizeMismatch { path, expected, actual } => write!(
+                f,
+                "Save file {} does not match the cartridge RAM size (expected {} bytes, file is {} bytes)",
+                path, expected, actual
+            ),
+            LoadError::PatchSizeMismatch { path, expected, actual } => write!(
+                f,
+                "Patch {} is for a different ROM (expected {} bytes, ROM is {} bytes)",
+                path, expected, actual
+            ),
+            LoadError::PatchChecksum { path, what, expected, actual } => write!(
+                f,
+                "Patch {}: CRC32 of the {} does not match (expected {:08X}, calculated {:08X})",
+                path, what, expected, actual
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadError::Io { err, .. } => Some(err),
+            _ => None,
+        }
+    }
+}
+
+// Ignores a recoverable error with a warning in lenient mode.
+pub fn check(lenient: bool, err: LoadError) -> Result<(), LoadError> {
+    if lenient && err.is_recoverable() {
+        warn!("{} (ignored)", err);
+        Ok(())
+    } else {
+        Err(err)
+    }
+}
@@ -0,0 +1,393 @@
+use std::process;
+use std::thread;
+use std::time;
+
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
+use sdl2::event::Event;
+use sdl2::haptic::Haptic;
+use sdl2::keyboard::{Keycode, Mod, Scancode};
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+
+
+use rsgb::common::*;
+use rsgb::archive;
+use rsgb::gamepad;
+use rsgb::Emulator;
+use rsgb::Event as EmuEvent;
+use rsgb::Rewind;
+use rsgb::{Resampler, SAMPLE_RATE};
+
+use Args;
+
+// オーディオ出力
+const AUDIO_FREQ: i32 = 48000;
+const AUDIO_LATENCY: f64 = 0.06;        // 目標バッファ量 (秒)
+const AUDIO_MAX_ADJUST: f64 = 0.005;    // 速度調整の最大幅 (±0.5%)
+
+// 振動 (MBC5+RUMBLE)
+const RUMBLE_STRENGTH: f32 = 0.75;
+const RUMBLE_DURATION: u32 = 100;       // 毎フレーム更新するので短めでいい (ms)
+
+// 傾き (MBC7の加速度センサー)
+const TILT_KEY: f32 = 1.0;              // I/J/K/Lキーで傾ける量 (G)
+
+// セーブファイル (SRAMが書き換えられていたら書き出す間隔)
+const SAVE_FLUSH_FRAMES: u32 = 60;
+
+fn translate_keycode(key: Keycode) -> Option<gamepad::Key> {
+    match key {
+        Keycode::Down => Some(gamepad::Key::Down),
+        Keycode::Up => Some(gamepad::Key::Up),
+        Keycode::Left => Some(gamepad::Key::Left),
+        Keycode::Right => Some(gamepad::Key::Right),
+        Keycode::Return => Some(gamepad::Key::Start),
+        Keycode::RShift => Some(gamepad::Key::Select),
+        Keycode::X => Some(gamepad::Key::A),
+        Keycode::Z => Some(gamepad::Key::B),
+        _ => None,
+    }
+}
+
+// Handles key down event.
+fn handle_keydown(emu: &mut Emulator, rewind: &mut Rewind, rom: &str, key: Keycode, keymod: Mod) {
+    if handle_state_key(emu, rewind, rom, key, keymod) {
+        return;
+    }
+
+    if let Some(k) = translate_keycode(key) {
+        emu.keydown(k);
+    }
+}
+
+// Handles key up event.
+fn handle_keyup(emu: &mut Emulator, key: Keycode) {
+    if let Some(k) = translate_keycode(key) {
+        emu.keyup(k);
+    }
+}
+
+// Returns save state slot number for function keys (F1~F9).
+fn state_slot(key: Keycode) -> Option<u8> {
+    match key {
+        Keycode::F1 => Some(1),
+        Keycode::F2 => Some(2),
+        Keycode::F3 => Some(3),
+        Keycode::F4 => Some(4),
+        Keycode::F5 => Some(5),
+        Keycode::F6 => Some(6),
+        Keycode::F7 => Some(7),
+        Keycode::F8 => Some(8),
+        Keycode::F9 => Some(9),
+        _ => None,
+    }
+}
+
+// Handles save state hotkeys. (Shift+F1~F9: Save, F1~F9: Load)
+// Loading a state clears the rewind buffer (巻き戻しでロード前に戻らないように)
+fn handle_state_key(emu: &mut Emulator, rewind: &mut Rewind, rom: &str, key: Keycode, keymod: Mod) -> bool {
+    let slot = match state_slot(key) {
+        Some(slot) => slot,
+        None => return false,
+    };
+    let fname = state_fname(rom, slot);
+
+    if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+        match emu.save_state_file(&fname) {
+            Ok(()) => info!("Saved state to slot {}", slot),
+            Err(e) => error!("Failed to save state to slot {}: {}", slot, e),
+        }
+    } else {
+        match emu.load_state_file(&fname) {
+            Ok(()) => {
+                rewind.clear();
+                info!("Loaded state from slot {}", slot);
+            }
+            Err(e) => error!("Failed to load state from slot {}: {}", slot, e),
+        }
+    }
+
+    true
+}
+
+// Opens the audio device. Returns None if audio is not available.
+fn open_audio(sdl_context: &sdl2::Sdl) -> Option<AudioQueue<f32>> {
+    let desired = AudioSpecDesired {
+        freq: Some(AUDIO_FREQ),
+        channels: Some(2),
+        samples: Some(1024),
+    };
+
+    let queue = sdl_context
+        .audio()
+        .and_then(|audio| audio.open_queue::<f32, _>(None, &desired));
+    match queue {
+        Ok(queue) => {
+            info!("Audio: {}Hz", queue.spec().freq);
+            queue.resume();
+            Some(queue)
+        }
+        Err(e) => {
+            warn!("Audio disabled: {}", e);
+            None
+        }
+    }
+}
+
+// Returns the target fill level of the audio queue in bytes.
+fn audio_target_size(queue: &AudioQueue<f32>) -> u32 {
+    (queue.spec().freq as f64 * AUDIO_LATENCY) as u32 * 2 * 4
+}
+
+// Resamples the emulated audio and feeds it to the audio queue.
+// バッファの残量に応じて速度を少しだけ調整し、バッファ量を目標値付近に保つ
+fn queue_audio(queue: &AudioQueue<f32>, resampler: &mut Resampler, samples: &[f32], buf: &mut Vec<f32>) {
+    let target = audio_target_size(queue) as f64;
+    let error = ((target - queue.size() as f64) / target).clamp(-1.0, 1.0);
+    resampler.set_adjust(1.0 + error * AUDIO_MAX_ADJUST);
+
+    buf.clear();
+    resampler.process(samples, buf);
+    if let Err(e) = queue.queue_audio(buf) {
+        warn!("Audio queue failed: {}", e);
+    }
+}
+
+// Waits until the audio queue drains to the target level. (オーディオ基準でフレームを進める)
+fn wait_audio(queue: &AudioQueue<f32>) {
+    let target = audio_target_size(queue);
+    while queue.size() > target {
+        thread::sleep(time::Duration::from_millis(1));
+    }
+}
+
+// Opens the rumble device of the first joystick. Returns None if not available.
+fn open_haptic(sdl_context: &sdl2::Sdl) -> Option<Haptic> {
+    let joystick = sdl_context.joystick().ok()?;
+    if joystick.num_joysticks().ok()? == 0 {
+        return None;
+    }
+
+    match sdl_context.haptic().map(|haptic| haptic.open_from_joystick_id(0)) {
+        Ok(Ok(haptic)) => {
+            info!("Rumble: {}", joystick.name_for_index(0).unwrap_or_default());
+            Some(haptic)
+        }
+        _ => None,
+    }
+}
+
+// Handles emulator events. Returns true if the rumble motor was on during the last frame.
+fn poll_events(emu: &mut Emulator) -> bool {
+    let mut rumble = emu.rumble();
+    while let Some(event) = emu.poll_event() {
+        match event {
+            EmuEvent::Rumble(on) => rumble |= on,
+            EmuEvent::Lockup { pc, opcode } => {
+                eprintln!("CPU locked up: illegal opcode ${:02X} at ${:04X}", opcode, pc);
+            }
+        }
+    }
+    rumble
+}
+
+// Updates the accelerometer input. (MBC7)
+// I/J/K/L keys tilt by TILT_KEY, or drag with the left mouse button (window center = level, edge = 1G).
+fn update_tilt(emu: &mut Emulator, event_pump: &sdl2::EventPump, window_size: (u32, u32)) {
+    let keys = event_pump.keyboard_state();
+    let axis = |neg: Scancode, pos: Scancode| {
+        (keys.is_scancode_pressed(pos) as i32 - keys.is_scancode_pressed(neg) as i32) as f32 * TILT_KEY
+    };
+    let mut x = axis(Scancode::J, Scancode::L);
+    let mut y = axis(Scancode::I, Scancode::K);
+
+    let mouse = event_pump.mouse_state();
+    if x == 0.0 && y == 0.0 && mouse.left() {
+        let (w, h) = (window_size.0 as f32 / 2.0, window_size.1 as f32 / 2.0);
+        x = ((mouse.x() as f32 - w) / w).clamp(-1.0, 1.0);
+        y = ((mouse.y() as f32 - h) / h).clamp(-1.0, 1.0);
+    }
+
+    emu.set_accelerometer(x, y);
+}
+
+// Returns save filename for the ROM. (アーカイブの場合はアーカイブ名から)
+fn save_fname(rom: &str) -> String {
+    archive::rom_file_path(rom, "sav")
+}
+
+// Returns save state filename for the ROM and slot.
+fn state_fname(rom: &str, slot: u8) -> String {
+    archive::rom_file_path(rom, &format!("ss{}", slot))
+}
+
+// Runs the emulator with the SDL2 frontend.
+pub fn run(args: Args) {
+    let mut emu = match Emulator::with_config(&args.rom, &args.config) {
+        Ok(emu) => emu,
+        Err(e) => {
+            eprintln!("Failed to load {}: {}", args.rom, e);
+            if e.is_recoverable() {
+                eprintln!("(Use --lenient to run it anyway)");
+            }
+            process::exit(1);
+        }
+    };
+
+    // セーブファイルが読めなかった時は、上書きしないようにセーブを止める
+    let save_path = save_fname(&args.rom);
+    let save_enabled = match emu.read_save_file(&save_path) {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("Failed to load {}: {}", save_path, e);
+            eprintln!("(The save file is left as is, and this session will not be saved)");
+            false
+        }
+    };
+    let mut save_frames: u32 = 0;
+
+    // ============================================================================
+    // SDL2 Init
+    // ============================================================================
+    let sdl_context = sdl2::init().unwrap();
+    let video_subsystem = sdl_context.video().unwrap();
+    let window = video_subsystem
+        .window("RSGB -Rust GB Emu-", SCREEN_W as u32 * 2, SCREEN_H as u32 * 2)
+        .position_centered()
+        .build()
+        .unwrap();
+    let mut canvas = window.into_canvas().build().unwrap();
+    let texture_creator = canvas.texture_creator();
+    let mut texture = texture_creator
+        .create_texture_streaming(PixelFormatEnum::RGB24, 160, 144)
+        .unwrap();
+    let mut event_pump = sdl_context.event_pump().unwrap();
+
+    let audio = open_audio(&sdl_context);
+    let mut resampler = audio
+        .as_ref()
+        .map(|queue| Resampler::new(SAMPLE_RATE, queue.spec().freq as u32));
+    let mut samples = Vec::new();
+    let mut audio_buf = Vec::new();
+
+    // 振動 (ジョイスティックの接続はSDLの初期化時のみ確認する)
+    let _joystick = sdl_context.joystick();
+    let mut haptic = open_haptic(&sdl_context);
+    let mut rumble = false;
+
+    // 巻き戻し (Backspace長押し)
+    let mut rewind = Rewind::default();
+    let mut rewinding = false;
+
+    // ============================================================================
+
+    'running: loop {
+        let now = time::Instant::now();
+
+        update_tilt(&mut emu, &event_pump, canvas.window().size());
+
+        if rewinding {
+            // Step back at the same pace as recording
+            rewind.rewind(&mut emu);
+        } else {
+            // Emulate one frame
+            emu.run_frame();
+            rewind.record(&emu);
+        }
+
+        save_frames += 1;
+        if save_enabled && save_frames >= SAVE_FLUSH_FRAMES {
+            save_frames = 0;
+            if let Err(e) = emu.flush_save_file(&save_path) {
+                warn!("Failed to write {}: {}", save_path, e);
+            }
+        }
+
+        samples.clear();
+        emu.drain_samples(&mut samples);
+        if let (Some(queue), Some(resampler)) = (&audio, &mut resampler) {
+            if !rewinding {
+                queue_audio(queue, resampler, &samples, &mut audio_buf);
+            }
+        }
+
+        texture
+            .with_lock(None, |buf: &mut [u8], pitch: usize| {
+                let fb = emu.frame_buffer_rgb();
+
+                for y in 0..144 {
+                    let offset = y * pitch;
+                    buf[offset..offset + 160 * 3].copy_from_slice(&fb[y * 160 * 3..(y + 1) * 160 * 3]);
+                }
+            })
+            .unwrap();
+
+        let rumble_prev = rumble;
+        rumble = poll_events(&mut emu);
+        if let Some(ref mut haptic) = haptic {
+            if rumble {
+                haptic.rumble_play(RUMBLE_STRENGTH, RUMBLE_DURATION);
+            } else if rumble_prev {
+                haptic.rumble_stop();
+            }
+        }
+
+        canvas.clear();
+        canvas.copy(&texture, None, None).unwrap();
+        if rumble {
+            // 振動中は右上に赤いインジケーターを表示する
+            let (w, _) = canvas.output_size().unwrap();
+            canvas.set_draw_color(Color::RGB(255, 0, 0));
+            canvas.fill_rect(Rect::new(w as i32 - 16, 8, 8, 8)).unwrap();
+            canvas.set_draw_color(Color::RGB(0, 0, 0));
+        }
+        canvas.present();
+
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => break 'running,
+                Event::KeyDown {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => rewinding = true,
+                Event::KeyUp {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => rewinding = false,
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    keymod,
+                    ..
+                } => handle_keydown(&mut emu, &mut rewind, &args.rom, keycode, keymod),
+                Event::KeyUp {
+                    keycode: Some(keycode),
+                    ..
+                } => handle_keyup(&mut emu, keycode),
+                _ => (),
+            }
+        }
+
+        match audio {
+            // オーディオの再生速度に合わせる
+            Some(ref queue) if !rewinding => wait_audio(queue),
+            _ => {
+                let wait = time::Duration::from_micros(1000000 / 60);
+                let elapsed = now.elapsed();
+
+                if wait > elapsed {
+                    thread::sleep(wait - elapsed);
+                }
+            }
+        }
+    }
+
+    if save_enabled {
+        if let Err(e) = emu.write_save_file(&save_path) {
+            eprintln!("Failed to write {}: {}", save_path, e);
+        }
+    }
+}
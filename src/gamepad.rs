@@ -1,12 +1,14 @@
+use serde::{Deserialize, Serialize};
 use common::IO;
 
+#[derive(Serialize, Deserialize)]
 pub struct GamePad {
     key: u8,
     key_state: u8,
     pub irq: bool,
 }
 
-#[derive(Hash, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
 pub enum Key {
     A,
     B,
@@ -18,6 +20,12 @@ pub enum Key {
     Select,
 }
 
+impl Default for GamePad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl GamePad {
     pub fn new() -> Self {
         GamePad {
@@ -42,6 +50,19 @@ impl GamePad {
         self.irq = true;
     }
 
+    // Returns true if a button in the selected row (P14/P15) is pressed.
+    // (P10~P13のどれかがLow = STOPから復帰する)
+    pub fn selected_pressed(&self) -> bool {
+        let mut state = 0x0F;
+        if self.key & 0x10 == 0 {
+            state &= self.key_state >> 4;
+        }
+        if self.key & 0x20 == 0 {
+            state &= self.key_state & 0x0F;
+        }
+        state != 0x0F
+    }
+
     pub fn keyup(&mut self, key: Key) {
         match key {
             Key::Down => self.key_state |= 0x80,
@@ -0,0 +1,615 @@
+use std::fmt;
+use std::path::Path;
+use serde::Serialize;
+use archive;
+use archive::ARCHIVE_EXTENSIONS;
+use cartridge::NINTENDO_LOGO;
+use error::LoadError;
+use mbc::mmm01;
+
+// [カートリッジヘッダー ($0100-$014F)]
+// https://gbdev.io/pandocs/The_Cartridge_Header.html
+// $0104-$0133: 任天堂ロゴ
+// $0134-$0143: タイトル (CGB対応ソフトは $013F-$0142 がメーカーコード, $0143 がCGBフラグ)
+// $0144-$0145: 新ライセンシーコード (旧ライセンシーコードが$33の時に使う)
+// $0146: SGBフラグ ($03 = SGB対応)
+// $0147: カートリッジタイプ    $0148: ROMサイズ    $0149: RAMサイズ
+// $014A: 仕向地 ($00 = 日本, $01 = 海外)    $014B: 旧ライセンシーコード    $014C: バージョン
+// $014D: ヘッダーチェックサム    $014E-$014F: グローバルチェックサム (ビッグエンディアン)
+pub const HEADER_END: usize = 0x0150;
+// ROMファイルの拡張子
+pub const ROM_EXTENSIONS: [&str; 3] = ["gb", "gbc", "sgb"];
+
+// CGBフラグ ($0143)
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub enum CgbSupport {
+    #[default]
+    None,           // DMG専用
+    Compatible,     // $80: DMG/CGB共通
+    Only,           // $C0: CGB専用
+}
+
+// 仕向地 ($014A)
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub enum Destination {
+    #[default]
+    Japan,
+    Overseas,
+    Unknown(u8),
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct CartridgeHeader {
+    pub offset: usize,                  // ヘッダーの位置 (MMM01のマルチカートはROMの最後の32KB)
+    pub title: String,
+    pub manufacturer: Option<String>,   // メーカーコード (CGB)
+    pub cgb_flag: u8,
+    pub cgb: CgbSupport,
+    pub sgb: bool,
+    pub licensee: String,               // ライセンシーコード (旧コードは2桁の16進数, 新コードは2文字)
+    pub publisher: Option<&'static str>,
+    pub cartridge_type: u8,
+    pub cartridge_name: Option<&'static str>,
+    pub battery: bool,                  // 電池でバックアップされるRAM/EEPROM/フラッシュ/RTCを持つ
+    pub rom_size_code: u8,
+    pub rom_size: Option<usize>,
+    pub ram_size_code: u8,
+    pub ram_size: Option<usize>,
+    pub destination: Destination,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub header_checksum_calc: u8,
+    pub global_checksum: u16,
+    pub global_checksum_calc: u16,
+    pub logo_valid: bool,
+}
+
+impl CartridgeHeader {
+    // Decodes the cartridge header of the ROM.
+    pub fn parse(rom: &[u8]) -> Result<Self, LoadError> {
+        if rom.len() < HEADER_END {
+            return Err(LoadError::TruncatedRom { size: rom.len() });
+        }
+
+        let offset = mmm01::header_offset(rom);
+        let h = &rom[offset..offset + HEADER_END];
+
+        let cgb_flag = h[0x0143];
+        let cgb = match cgb_flag {
+            0xC0 => CgbSupport::Only,
+            n if n & 0x80 != 0 => CgbSupport::Compatible,
+            _ => CgbSupport::None,
+        };
+
+        // CGB対応ソフトはタイトルが短く、後ろにメーカーコード(大文字4文字)が入ることがある
+        let code = &h[0x013F..0x0143];
+        let (title_end, manufacturer) = if cgb == CgbSupport::None {
+            (0x0144, None)
+        } else if code.iter().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit()) {
+            (0x013F, Some(String::from_utf8_lossy(code).into_owned()))
+        } else {
+            (0x0143, None)
+        };
+        let title = h[0x0134..title_end]
+            .iter()
+            .take_while(|&&c| c != 0)
+            .map(|&c| if c.is_ascii_graphic() || c == b' ' { c as char } else { '?' })
+            .collect::<String>()
+            .trim_end()
+            .to_string();
+
+        let old_licensee = h[0x014B];
+        let (licensee, publisher) = if old_licensee == 0x33 {
+            let code = String::from_utf8_lossy(&h[0x0144..0x0146]).into_owned();
+            let publisher = new_licensee_name(&code);
+            (code, publisher)
+        } else {
+            (format!("{:02X}", old_licensee), old_licensee_name(old_licensee))
+        };
+
+        let cartridge_type = h[0x0147];
+        let rom_size_code = h[0x0148];
+        let ram_size_code = h[0x0149];
+
+        let mut header_checksum_calc: u8 = 0;
+        for val in &h[0x0134..0x014D] {
+            header_checksum_calc = header_checksum_calc.wrapping_sub(*val).wrapping_sub(1);
+        }
+
+        // グローバルチェックサムは自分自身の2バイト以外の全バイトの和
+        let global_checksum_calc = rom
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| i != offset + 0x014E && i != offset + 0x014F)
+            .fold(0u16, |sum, (_, &val)| sum.wrapping_add(val as u16));
+
+        Ok(CartridgeHeader {
+            offset,
+            title,
+            manufacturer,
+            cgb_flag,
+            cgb,
+            // SGBの機能は旧ライセンシーコードが$33の時だけ使える
+            sgb: h[0x0146] == 0x03 && old_licensee == 0x33,
+            licensee,
+            publisher,
+            cartridge_type,
+            cartridge_name: cartridge_type_name(cartridge_type),
+            battery: has_battery(cartridge_type),
+            rom_size_code,
+            rom_size: match rom_size_code {
+                n @ 0..=8 => Some((32 * 1024) << n),
+                _ => None,
+            },
+            ram_size_code,
+            ram_size: match ram_size_code {
+                0 => Some(0),
+                1 => Some(2 * 1024),
+                2 => Some(8 * 1024),
+                3 => Some(32 * 1024),
+                4 => Some(128 * 1024),
+                5 => Some(64 * 1024),
+                _ => None,
+            },
+            destination: match h[0x014A] {
+                0x00 => Destination::Japan,
+                0x01 => Destination::Overseas,
+                n => Destination::Unknown(n),
+            },
+            version: h[0x014C],
+            header_checksum: h[0x014D],
+            header_checksum_calc,
+            global_checksum: (h[0x014E] as u16) << 8 | h[0x014F] as u16,
+            global_checksum_calc,
+            logo_valid: h[0x0104..0x0134] == NINTENDO_LOGO,
+        })
+    }
+
+    pub fn header_checksum_ok(&self) -> bool {
+        self.header_checksum == self.header_checksum_calc
+    }
+
+    pub fn global_checksum_ok(&self) -> bool {
+        self.global_checksum == self.global_checksum_calc
+    }
+
+    // Returns the header as a JSON object.
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(self).unwrap_or_default()
+    }
+}
+
+impl fmt::Display for CartridgeHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ok = |valid: bool| if valid { "OK" } else { "NG" };
+        let size = |size: Option<usize>, code: u8| match size {
+            Some(0) => format!("None (${:02X})", code),
+            Some(size) if size >= 1024 * 1024 => format!("{}MB (${:02X})", size / 1024 / 1024, code),
+            Some(size) => format!("{}KB (${:02X})", size / 1024, code),
+            None => format!("Unknown (${:02X})", code),
+        };
+
+        writeln!(f, "Title           : {}", self.title)?;
+        if let Some(ref code) = self.manufacturer {
+            writeln!(f, "Manufacturer    : {}", code)?;
+        }
+        writeln!(f, "Publisher       : {} ({})", self.publisher.unwrap_or("Unknown"), self.licensee)?;
+        writeln!(
+            f,
+            "Cartridge type  : {} (${:02X})",
+            self.cartridge_name.unwrap_or("Unknown"),
+            self.cartridge_type
+        )?;
+        writeln!(f, "Battery         : {}", if self.battery { "Yes" } else { "No" })?;
+        writeln!(f, "ROM size        : {}", size(self.rom_size, self.rom_size_code))?;
+        writeln!(f, "RAM size        : {}", size(self.ram_size, self.ram_size_code))?;
+        writeln!(f, "CGB             : {:?} (${:02X})", self.cgb, self.cgb_flag)?;
+        writeln!(f, "SGB             : {}", if self.sgb { "Yes" } else { "No" })?;
+        writeln!(f, "Destination     : {:?}", self.destination)?;
+        writeln!(f, "Version         : {}", self.version)?;
+        writeln!(
+            f,
+            "Header checksum : ${:02X} ({}, calculated ${:02X})",
+            self.header_checksum,
+            ok(self.header_checksum_ok()),
+            self.header_checksum_calc
+        )?;
+        writeln!(
+            f,
+            "Global checksum : ${:04X} ({}, calculated ${:04X})",
+            self.global_checksum,
+            ok(self.global_checksum_ok()),
+            self.global_checksum_calc
+        )?;
+        write!(f, "Logo            : {}", ok(self.logo_valid))
+    }
+}
+
+// Reads the cartridge header of a ROM file. (.zip/.gzも可)
+pub fn read_header(path: &str) -> Result<CartridgeHeader, LoadError> {
+    let rom = archive::read_rom(path, None)?;
+    CartridgeHeader::parse(&rom)
+}
+
+// Returns true if the file has a ROM (or archive) extension.
+pub fn is_rom_path(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| {
+            ROM_EXTENSIONS
+                .iter()
+                .chain(ARCHIVE_EXTENSIONS.iter())
+                .any(|rom_ext| ext.eq_ignore_ascii_case(rom_ext))
+        })
+        .unwrap_or(false)
+}
+
+// Returns the name of the cartridge type ($0147).
+pub fn cartridge_type_name(code: u8) -> Option<&'static str> {
+    let name = match code {
+        0x00 => "ROM ONLY",
+        0x01 => "MBC1",
+        0x02 => "MBC1+RAM",
+        0x03 => "MBC1+RAM+BATTERY",
+        0x05 => "MBC2",
+        0x06 => "MBC2+BATTERY",
+        0x08 => "ROM+RAM",
+        0x09 => "ROM+RAM+BATTERY",
+        0x0B => "MMM01",
+        0x0C => "MMM01+RAM",
+        0x0D => "MMM01+RAM+BATTERY",
+        0x0F => "MBC3+TIMER+BATTERY",
+        0x10 => "MBC3+TIMER+RAM+BATTERY",
+        0x11 => "MBC3",
+        0x12 => "MBC3+RAM",
+        0x13 => "MBC3+RAM+BATTERY",
+        0x19 => "MBC5",
+        0x1A => "MBC5+RAM",
+        0x1B => "MBC5+RAM+BATTERY",
+        0x1C => "MBC5+RUMBLE",
+        0x1D => "MBC5+RUMBLE+RAM",
+        0x1E => "MBC5+RUMBLE+RAM+BATTERY",
+        0x20 => "MBC6",
+        0x22 => "MBC7+SENSOR+RUMBLE+RAM+BATTERY",
+        0xFC => "POCKET CAMERA",
+        0xFD => "BANDAI TAMA5",
+        0xFE => "HuC3",
+        0xFF => "HuC1+RAM+BATTERY",
+        _ => return None,
+    };
+    Some(name)
+}
+
+// Returns true if the cartridge type keeps its save data. ($0147)
+// MBC6のフラッシュ, MBC7/TAMA5のEEPROMも含む
+pub fn has_battery(code: u8) -> bool {
+    matches!(
+        code,
+        0x03 | 0x06 | 0x09 | 0x0D | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E | 0x20 | 0x22 | 0xFC | 0xFD | 0xFE | 0xFF
+    )
+}
+
+// 旧ライセンシーコード ($014B)
+fn old_licensee_name(code: u8) -> Option<&'static str> {
+    let name = match code {
+        0x00 => "None",
+        0x01 | 0x31 => "Nintendo",
+        0x08 | 0x38 => "Capcom",
+        0x09 => "Hot-B",
+        0x0A | 0xE0 => "Jaleco",
+        0x0B => "Coconuts Japan",
+        0x0C | 0x6E => "Elite Systems",
+        0x13 | 0x69 => "Electronic Arts",
+        0x18 => "Hudson Soft",
+        0x19 => "ITC Entertainment",
+        0x1A => "Yanoman",
+        0x1D => "Japan Clary",
+        0x1F | 0x4A | 0x61 => "Virgin Interactive",
+        0x24 => "PCM Complete",
+        0x25 => "San-X",
+        0x28 => "Kotobuki Systems",
+        0x29 => "Seta",
+        0x30 | 0x70 => "Infogrames",
+        0x32 | 0xA2 | 0xB2 => "Bandai",
+        0x34 | 0xA4 => "Konami",
+        0x35 => "HectorSoft",
+        0x39 | 0x9D | 0xD9 => "Banpresto",
+        0x3C => "Entertainment Interactive",
+        0x3E => "Gremlin",
+        0x41 => "Ubi Soft",
+        0x42 | 0xEB => "Atlus",
+        0x44 | 0x4D => "Malibu Interactive",
+        0x46 | 0xCF => "Angel",
+        0x47 => "Spectrum HoloByte",
+        0x49 => "Irem",
+        0x4F => "U.S. Gold",
+        0x50 => "Absolute",
+        0x51 | 0xB0 => "Acclaim",
+        0x52 => "Activision",
+        0x53 => "Sammy USA",
+        0x54 => "GameTek",
+        0x55 => "Park Place",
+        0x56 | 0xDB | 0xFF => "LJN",
+        0x57 => "Matchbox",
+        0x59 => "Milton Bradley",
+        0x5A => "Mindscape",
+        0x5B => "Romstar",
+        0x5C | 0xD6 => "Naxat Soft",
+        0x5D => "Tradewest",
+        0x60 => "Titus Interactive",
+        0x67 => "Ocean Software",
+        0x6F => "Electro Brain",
+        0x71 => "Interplay",
+        0x72 | 0xAA => "Broderbund",
+        0x73 => "Sculptured Software",
+        0x75 => "The Sales Curve",
+        0x78 => "THQ",
+        0x79 => "Accolade",
+        0x7A => "Triffix Entertainment",
+        0x7C => "MicroProse",
+        0x7F | 0xC2 => "Kemco",
+        0x80 => "Misawa Entertainment",
+        0x83 => "LOZC G.",
+        0x86 | 0xC4 => "Tokuma Shoten",
+        0x8B => "Bullet-Proof Software",
+        0x8C => "Vic Tokai",
+        0x8E => "Ape",
+        0x8F => "I'Max",
+        0x91 => "Chunsoft",
+        0x92 => "Video System",
+        0x93 => "Tsuburaya Productions",
+        0x95 | 0xE3 => "Varie",
+        0x96 => "Yonezawa/S'Pal",
+        0x97 => "Kaneko",
+        0x99 => "Arc",
+        0x9A => "Nihon Bussan",
+        0x9B => "Tecmo",
+        0x9C => "Imagineer",
+        0x9F => "Nova",
+        0xA1 => "Hori Electric",
+        0xA6 => "Kawada",
+        0xA7 => "Takara",
+        0xA9 => "Technos Japan",
+        0xAC => "Toei Animation",
+        0xAD => "Toho",
+        0xAF => "Namco",
+        0xB1 => "ASCII/Nexsoft",
+        0xB4 => "Square Enix",
+        0xB6 => "HAL Laboratory",
+        0xB7 => "SNK",
+        0xB9 | 0xCE => "Pony Canyon",
+        0xBA => "Culture Brain",
+        0xBB => "Sunsoft",
+        0xBD => "Sony Imagesoft",
+        0xBF => "Sammy",
+        0xC0 | 0xD0 => "Taito",
+        0xC3 => "Square",
+        0xC5 => "Data East",
+        0xC6 => "Tonkin House",
+        0xC8 => "Koei",
+        0xC9 => "UFL",
+        0xCA => "Ultra Games",
+        0xCB => "VAP",
+        0xCC => "Use Corporation",
+        0xCD => "Meldac",
+        0xD1 => "SOFEL",
+        0xD2 => "Quest",
+        0xD3 => "Sigma Enterprises",
+        0xD4 => "ASK Kodansha",
+        0xD7 => "Copya System",
+        0xDA => "Tomy",
+        0xDD => "NCS",
+        0xDE => "Human",
+        0xDF => "Altron",
+        0xE1 => "Towa Chiki",
+        0xE2 => "Yutaka",
+        0xE5 => "Epoch",
+        0xE7 => "Athena",
+        0xE8 => "Asmik Ace Entertainment",
+        0xE9 => "Natsume",
+        0xEA => "King Records",
+        0xEC => "Epic/Sony Records",
+        0xEE => "IGS",
+        0xF0 => "A Wave",
+        0xF3 => "Extreme Entertainment",
+        _ => return None,
+    };
+    Some(name)
+}
+
+// 新ライセンシーコード ($0144-$0145)
+fn new_licensee_name(code: &str) -> Option<&'static str> {
+    let name = match code {
+        "00" => "None",
+        "01" | "31" => "Nintendo",
+        "08" => "Capcom",
+        "13" | "69" => "Electronic Arts",
+        "18" | "38" => "Hudson Soft",
+        "19" => "B-AI",
+        "20" => "KSS",
+        "22" => "Planning Office WADA",
+        "24" => "PCM Complete",
+        "25" => "San-X",
+        "28" => "Kemco",
+        "29" => "SETA Corporation",
+        "30" => "Viacom",
+        "32" => "Bandai",
+        "33" | "93" => "Ocean Software/Acclaim Entertainment",
+        "34" | "54" | "A4" => "Konami",
+        "35" => "HectorSoft",
+        "37" => "Taito",
+        "39" => "Banpresto",
+        "41" => "Ubi Soft",
+        "42" => "Atlus",
+        "44" => "Malibu Interactive",
+        "46" => "Angel",
+        "47" => "Bullet-Proof Software",
+        "49" => "Irem",
+        "50" => "Absolute",
+        "51" => "Acclaim Entertainment",
+        "52" => "Activision",
+        "53" => "Sammy USA",
+        "55" => "Hi Tech Expressions",
+        "56" => "LJN",
+        "57" => "Matchbox",
+        "58" => "Mattel",
+        "59" => "Milton Bradley",
+        "60" => "Titus Interactive",
+        "61" => "Virgin Games",
+        "64" => "Lucasfilm Games",
+        "67" => "Ocean Software",
+        "70" => "Infogrames",
+        "71" => "Interplay",
+        "72" => "Broderbund",
+        "73" => "Sculptured Software",
+        "75" => "The Sales Curve",
+        "78" => "THQ",
+        "79" => "Accolade",
+        "80" => "Misawa Entertainment",
+        "83" => "LOZC G.",
+        "86" => "Tokuma Shoten",
+        "87" => "Tsukuda Original",
+        "91" => "Chunsoft",
+        "92" => "Video System",
+        "95" => "Varie",
+        "96" => "Yonezawa/S'Pal",
+        "97" => "Kaneko",
+        "99" => "Pack-In-Video",
+        _ => return None,
+    };
+    Some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom(size: usize, title: &[u8], cartridge_type: u8) -> Vec<u8> {
+        let mut rom = vec![0; size];
+        rom[0x0104..0x0134].copy_from_slice(&NINTENDO_LOGO);
+        rom[0x0134..0x0134 + title.len()].copy_from_slice(title);
+        rom[0x0147] = cartridge_type;
+        rom
+    }
+
+    fn fix_checksums(rom: &mut [u8], offset: usize) {
+        let h = &mut rom[offset..offset + HEADER_END];
+        h[0x014D] = h[0x0134..0x014D].iter().fold(0u8, |sum, &val| sum.wrapping_sub(val).wrapping_sub(1));
+        h[0x014E] = 0;
+        h[0x014F] = 0;
+        let sum = rom.iter().fold(0u16, |sum, &val| sum.wrapping_add(val as u16));
+        rom[offset + 0x014E] = (sum >> 8) as u8;
+        rom[offset + 0x014F] = sum as u8;
+    }
+
+    #[test]
+    fn dmg_header() {
+        let mut rom = rom(0x10000, b"TETRIS", 0x03);
+        rom[0x0148] = 0x01;
+        rom[0x0149] = 0x02;
+        rom[0x014B] = 0x01;
+        rom[0x014C] = 0x01;
+        rom[0x4000] = 0xAB;
+        fix_checksums(&mut rom, 0);
+
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.offset, 0);
+        assert_eq!(header.title, "TETRIS");
+        assert_eq!(header.manufacturer, None);
+        assert_eq!(header.cgb, CgbSupport::None);
+        assert!(!header.sgb);
+        assert_eq!(header.licensee, "01");
+        assert_eq!(header.publisher, Some("Nintendo"));
+        assert_eq!(header.cartridge_name, Some("MBC1+RAM+BATTERY"));
+        assert!(header.battery);
+        assert_eq!(header.rom_size, Some(64 * 1024));
+        assert_eq!(header.ram_size, Some(8 * 1024));
+        assert_eq!(header.destination, Destination::Japan);
+        assert_eq!(header.version, 1);
+        assert!(header.header_checksum_ok());
+        assert!(header.global_checksum_ok());
+        assert!(header.logo_valid);
+    }
+
+    #[test]
+    fn cgb_header_with_manufacturer_and_new_licensee() {
+        let mut rom = rom(0x8000, b"POKEMON_GLDAAUE", 0x10);
+        rom[0x0143] = 0x80;
+        rom[0x0144..0x0146].copy_from_slice(b"01");
+        rom[0x0146] = 0x03;
+        rom[0x014A] = 0x01;
+        rom[0x014B] = 0x33;
+        fix_checksums(&mut rom, 0);
+
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.title, "POKEMON_GLD");
+        assert_eq!(header.manufacturer.as_deref(), Some("AAUE"));
+        assert_eq!(header.cgb, CgbSupport::Compatible);
+        assert!(header.sgb);
+        assert_eq!(header.licensee, "01");
+        assert_eq!(header.publisher, Some("Nintendo"));
+        assert_eq!(header.destination, Destination::Overseas);
+
+        rom[0x0143] = 0xC0;
+        assert_eq!(CartridgeHeader::parse(&rom).unwrap().cgb, CgbSupport::Only);
+    }
+
+    #[test]
+    fn unknown_codes_and_bad_checksums() {
+        let mut rom = rom(0x8000, b"TEST\x01", 0x42);
+        rom[0x0104] = 0x00;
+        rom[0x0148] = 0x52;
+        rom[0x0149] = 0x09;
+        rom[0x014A] = 0x02;
+        rom[0x014D] = 0x12;
+
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.title, "TEST?");
+        assert_eq!(header.cartridge_name, None);
+        assert!(!header.battery);
+        assert_eq!(header.rom_size, None);
+        assert_eq!(header.ram_size, None);
+        assert_eq!(header.destination, Destination::Unknown(0x02));
+        assert!(!header.header_checksum_ok());
+        assert!(!header.global_checksum_ok());
+        assert!(!header.logo_valid);
+
+        let text = header.to_string();
+        assert!(text.contains("Cartridge type  : Unknown ($42)"));
+        assert!(text.contains("Logo            : NG"));
+        assert_eq!(header.to_json()["title"], "TEST?");
+    }
+
+    #[test]
+    fn truncated_rom() {
+        match CartridgeHeader::parse(&[0; 0x100]) {
+            Err(LoadError::TruncatedRom { size }) => assert_eq!(size, 0x100),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn mmm01_multicart_header_in_last_bank() {
+        let mut rom = rom(0x20000, b"MENU", 0x00);
+        let offset = rom.len() - 0x8000;
+        rom[offset + 0x0104..offset + 0x0134].copy_from_slice(&NINTENDO_LOGO);
+        rom[offset + 0x0134..offset + 0x0139].copy_from_slice(b"MULTI");
+        rom[offset + 0x0147] = 0x0D;
+        fix_checksums(&mut rom, offset);
+
+        let header = CartridgeHeader::parse(&rom).unwrap();
+        assert_eq!(header.offset, offset);
+        assert_eq!(header.title, "MULTI");
+        assert_eq!(header.cartridge_name, Some("MMM01+RAM+BATTERY"));
+        assert!(header.global_checksum_ok());
+    }
+
+    #[test]
+    fn rom_extensions() {
+        assert!(is_rom_path(Path::new("game.GB")));
+        assert!(is_rom_path(Path::new("dir/game.gbc")));
+        assert!(is_rom_path(Path::new("game.zip")));
+        assert!(!is_rom_path(Path::new("game.sav")));
+        assert!(!is_rom_path(Path::new("game")));
+    }
+}
@@ -0,0 +1,80 @@
+use serde::{Deserialize, Serialize};
+use common::IO;
+
+// 割り込み要因 (IE/IFのBit、Bit0が最優先)
+pub const INT_VBLANK: u8 = 0x01;    // $0040
+pub const INT_STAT: u8 = 0x02;      // $0048
+pub const INT_TIMER: u8 = 0x04;     // $0050
+pub const INT_SERIAL: u8 = 0x08;    // $0058
+pub const INT_JOYPAD: u8 = 0x10;    // $0060
+
+const INT_MASK: u8 = 0x1F;
+const VECTOR_BASE: u16 = 0x0040;
+
+// [割り込みコントローラ]
+// https://gbdev.io/pandocs/Interrupts.html
+// $FF0F IF: 割り込み要求 (Bit5~7は読むと常に1)
+// $FFFF IE: 割り込み許可 (8bitすべてR/W)
+#[derive(Serialize, Deserialize)]
+pub struct Interrupt {
+    int_flag: u8,
+    int_enable: u8,
+}
+
+impl Default for Interrupt {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interrupt {
+    pub fn new() -> Self {
+        Interrupt {
+            int_flag: 0,
+            int_enable: 0,
+        }
+    }
+
+    // Requests interrupts. (INT_*)
+    pub fn request(&mut self, irq: u8) {
+        self.int_flag |= irq & INT_MASK;
+    }
+
+    // Returns the requested and enabled interrupts. (IE & IF)
+    pub fn pending(&self) -> u8 {
+        self.int_flag & self.int_enable & INT_MASK
+    }
+
+    // Clears the highest priority pending interrupt in IF and returns its vector.
+    // Returns None if no interrupt is pending any more. (IEの書き込みでキャンセルされた時)
+    pub fn acknowledge(&mut self) -> Option<u16> {
+        let pending = self.pending();
+        if pending == 0 {
+            return None;
+        }
+
+        let id = pending.trailing_zeros();
+        self.int_flag &= !(1 << id);
+        Some(VECTOR_BASE + id as u16 * 8)
+    }
+}
+
+impl IO for Interrupt {
+    fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0xFF0F => self.int_flag = val & INT_MASK,
+            0xFFFF => self.int_enable = val,
+            _ => unreachable!("Unexpected address: 0x{:04X}", addr),
+        }
+    }
+
+    fn read(&mut self, addr: u16) -> u8 {
+        match addr {
+            0xFF0F => !INT_MASK | self.int_flag,
+            0xFFFF => self.int_enable,
+            _ => unreachable!("Unexpected address: 0x{:04X}", addr),
+        }
+    }
+
+    fn update(&mut self, _tick: u8) {}
+}
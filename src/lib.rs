@@ -0,0 +1,52 @@
+// =========================================================================
+// RSGB Core Library
+// =========================================================================
+// エミュレータのコア部分（CPU, MMU, PPU, 各ペリフェラル）
+// フロントエンド（SDL2等）には依存しない
+#![allow(clippy::upper_case_acronyms)]
+
+extern crate bitvec;
+extern crate bincode;
+extern crate crc32fast;
+extern crate flate2;
+extern crate png;
+extern crate serde;
+extern crate serde_big_array;
+extern crate serde_json;
+extern crate zip;
+
+#[macro_use]
+extern crate log;
+
+pub mod bios;
+pub mod archive;
+pub mod cartridge;
+pub mod header;
+pub mod patch;
+pub mod mbc;
+pub mod camera;
+pub mod cpu;
+pub mod common;
+pub mod mmu;
+pub mod ppu;
+pub mod apu;
+pub mod resampler;
+pub mod serial;
+pub mod interrupt;
+pub mod timer;
+pub mod gamepad;
+pub mod cgb;
+pub mod error;
+pub mod savestate;
+pub mod rewind;
+mod emulator;
+
+pub use apu::SAMPLE_RATE;
+pub use camera::{CAMERA_H, CAMERA_W};
+pub use bios::Model;
+pub use emulator::{Config, Emulator, Event, FRAME_TICKS};
+pub use error::LoadError;
+pub use resampler::Resampler;
+pub use gamepad::Key;
+pub use header::CartridgeHeader;
+pub use rewind::Rewind;
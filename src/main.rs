@@ -1,67 +1,166 @@
 use std::env;
-use std::path::PathBuf;
-use std::thread;
-use std::time;
-
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use sdl2::pixels::PixelFormatEnum;
-
-mod bios;
-mod cartridge;
-mod cpu;
-mod common;
-mod mmu;
-mod ppu;
-mod serial;
-mod timer;
-mod gamepad;
-mod cgb;
-
-use common::*;
-
-extern crate bitvec;
+use std::fs;
+use std::process;
+use std::path::{Path, PathBuf};
 
 #[macro_use]
-// extern crate bitvec;
 extern crate log;
 extern crate env_logger;
+extern crate rsgb;
+#[cfg(feature = "sdl")]
 extern crate sdl2;
+extern crate serde_json;
+
+use rsgb::header;
+use rsgb::Config;
+use rsgb::Model;
+
+// SDL2フロントエンド (--features sdl)
+#[cfg(feature = "sdl")]
+mod frontend;
+
+// SDL2なしでビルドした時は info だけ使える
+#[cfg(not(feature = "sdl"))]
+mod frontend {
+    use std::process;
 
-fn translate_keycode(key: Keycode) -> Option<gamepad::Key> {
-    match key {
-        Keycode::Down => Some(gamepad::Key::Down),
-        Keycode::Up => Some(gamepad::Key::Up),
-        Keycode::Left => Some(gamepad::Key::Left),
-        Keycode::Right => Some(gamepad::Key::Right),
-        Keycode::Return => Some(gamepad::Key::Start),
-        Keycode::RShift => Some(gamepad::Key::Select),
-        Keycode::X => Some(gamepad::Key::A),
-        Keycode::Z => Some(gamepad::Key::B),
-        _ => None,
+    use Args;
+
+    pub fn run(_args: Args) {
+        eprintln!("rsgb was built without the SDL2 frontend (cargo run --features sdl -- <ROM>)");
+        process::exit(1);
     }
 }
 
-// Handles key down event.
-fn handle_keydown(cpu: &mut cpu::CPU, key: Keycode) {
-    translate_keycode(key).map(|k| cpu.mmu.gamepad.keydown(k));
+// Command line arguments.
+// rsgb <ROM> [--bios <BOOT ROM>] [--model <dmg|mgb|sgb|cgb|agb>] [--entry <NAME>] [--patch <IPS/UPS/BPS>] [--camera <PNG/PGM>] [--lenient]
+#[cfg_attr(not(feature = "sdl"), allow(dead_code))]
+struct Args {
+    rom: String,
+    config: Config,
 }
 
-// Handles key up event.
-fn handle_keyup(cpu: &mut cpu::CPU, key: Keycode) {
-    translate_keycode(key).map(|k| cpu.mmu.gamepad.keyup(k));
+const USAGE: &str = "Usage: rsgb <ROM> [--bios <BOOT ROM>] [--model <dmg|mgb|sgb|cgb|agb>] [--entry <NAME>] [--patch <IPS/UPS/BPS>] [--camera <PNG/PGM>] [--lenient]";
+
+// Prints the usage and exits.
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(1);
 }
 
-// Returns ROM filename.
-fn rom_fname() -> String {
-    env::args().nth(1).unwrap()
+// Parses command line arguments. (mainで1回だけ呼ぶ)
+fn parse_args() -> Args {
+    let mut rom = None;
+    let mut config = Config::default();
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--bios" => config.bios_path = args.next(),
+            "--model" => {
+                config.model = args.next().and_then(|name| Model::from_name(&name));
+                if config.model.is_none() {
+                    warn!("Unknown model (dmg, mgb, sgb, cgb, agb)");
+                }
+            }
+            "--entry" => config.rom_entry = args.next(),
+            "--patch" => config.patch = args.next(),
+            "--camera" => config.camera_image = args.next(),
+            "--lenient" => config.lenient = true,
+            _ if arg.starts_with("--") => {
+                eprintln!("Unknown option: {}", arg);
+                usage();
+            }
+            _ => rom = Some(arg),
+        }
+    }
+
+    let rom = match rom {
+        Some(rom) => rom,
+        None => usage(),
+    };
+
+    Args { rom, config }
 }
 
-// Returns save filename for current ROM.
-fn save_fname() -> String {
-    let mut path_buf = PathBuf::from(rom_fname());
-    path_buf.set_extension("sav");
-    path_buf.to_str().unwrap().to_string()
+// rsgb info <ROM or directory> [--json]
+// Prints the cartridge header of the ROM (or of every ROM in the directory). Returns the exit code.
+fn run_info(args: &[String]) -> i32 {
+    let mut json = false;
+    let mut target = None;
+    for arg in args {
+        match arg.as_str() {
+            "--json" => json = true,
+            _ => target = Some(arg.as_str()),
+        }
+    }
+
+    let target = match target {
+        Some(target) => Path::new(target),
+        None => {
+            eprintln!("Usage: rsgb info <ROM or directory> [--json]");
+            return 1;
+        }
+    };
+
+    let is_dir = target.is_dir();
+    let files = if is_dir {
+        let mut files: Vec<PathBuf> = match fs::read_dir(target) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.is_file() && header::is_rom_path(path))
+                .collect(),
+            Err(e) => {
+                eprintln!("Cannot read {}: {}", target.display(), e);
+                return 1;
+            }
+        };
+        files.sort();
+        files
+    } else {
+        vec![target.to_path_buf()]
+    };
+
+    let mut exit_code = 0;
+    let mut entries = Vec::new();
+    for file in &files {
+        let path = file.to_string_lossy().into_owned();
+
+        match header::read_header(&path) {
+            Ok(header) if json => {
+                let mut entry = header.to_json();
+                entry["path"] = path.into();
+                entries.push(entry);
+            }
+            Ok(header) => {
+                if is_dir {
+                    println!("== {} ==", path);
+                }
+                println!("{}", header);
+                if is_dir {
+                    println!();
+                }
+            }
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                exit_code = 1;
+                if json {
+                    entries.push(serde_json::json!({ "path": path, "error": e.to_string() }));
+                }
+            }
+        }
+    }
+
+    if json {
+        let out = if is_dir {
+            serde_json::Value::Array(entries)
+        } else {
+            entries.pop().unwrap_or_default()
+        };
+        println!("{}", serde_json::to_string_pretty(&out).unwrap());
+    }
+
+    exit_code
 }
 
 fn main() {
@@ -70,96 +169,14 @@ fn main() {
     // ============================================================================
     env_logger::init();
 
-    // ============================================================================
-    // SDL2 Init
-    // ============================================================================
-    let sdl_context = sdl2::init().unwrap();
-    let video_subsystem = sdl_context.video().unwrap();
-    let window = video_subsystem
-        .window("RSGB -Rust GB Emu-", SCREEN_W as u32 * 2, SCREEN_H as u32 * 2)
-        .position_centered()
-        .build()
-        .unwrap();
-    let mut canvas = window.into_canvas().build().unwrap();
-    let texture_creator = canvas.texture_creator();
-    let mut texture = texture_creator
-        .create_texture_streaming(PixelFormatEnum::RGB24, 160, 144)
-        .unwrap();
-    let mut event_pump = sdl_context.event_pump().unwrap();
-
     // ============================================================================
     // App Init
     // ============================================================================
-    // FIXME 開発時はパス固定
-    const BIOS_PATH: &str = "rom/bios/GB/gb_bios.bin";
-
-    let mut cpu: cpu::CPU = cpu::CPU::new(BIOS_PATH, &rom_fname());
-    cpu.mmu.cartridge.read_save_file(&save_fname());
-
-    // ============================================================================
-    // CGB Unlock
-    // ============================================================================
-    let cgb_flg: u8 = cpu.mmu.cartridge.get_cgb_mode();
-    cpu.mmu.cgb.cgb_unlock(cgb_flg);
-
-    // ============================================================================
-
-    'running: loop {
-        let now = time::Instant::now();
-        let mut elapsed_tick: u32 = 0;
-
-        // Emulate one frame
-        while elapsed_tick < 456 * (144 + 10) {
-            elapsed_tick += cpu.step() as u32;
-        }
-
-        texture
-            .with_lock(None, |buf: &mut [u8], pitch: usize| {
-                let fb = cpu.mmu.ppu.frame_buffer();
-
-                for y in 0..144 {
-                    for x in 0..160 {
-                        let offset = y * pitch + x * 3;
-                        let color = fb[y * 160 + x];
-
-                        buf[offset] = color;        // R
-                        buf[offset + 1] = color;    // G
-                        buf[offset + 2] = color;    // B
-                    }
-                }
-            })
-            .unwrap();
-
-        canvas.clear();
-        canvas.copy(&texture, None, None).unwrap();
-        canvas.present();
-
-        for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit { .. }
-                | Event::KeyDown {
-                    keycode: Some(Keycode::Escape),
-                    ..
-                } => break 'running,
-                Event::KeyDown {
-                    keycode: Some(keycode),
-                    ..
-                } => handle_keydown(&mut cpu, keycode),
-                Event::KeyUp {
-                    keycode: Some(keycode),
-                    ..
-                } => handle_keyup(&mut cpu, keycode),
-                _ => (),
-            }
-        }
-
-        let wait = time::Duration::from_micros(1000000 / 60);
-        let elapsed = now.elapsed();
-
-        if wait > elapsed {
-            thread::sleep(wait - elapsed);
-        }
+    let argv: Vec<String> = env::args().skip(1).collect();
+    if argv.first().map(|arg| arg.as_str()) == Some("info") {
+        process::exit(run_info(&argv[1..]));
     }
 
-    cpu.mmu.cartridge.write_save_file(&save_fname());
+    let args = parse_args();
+    frontend::run(args);
 }
@@ -0,0 +1,216 @@
+use serde::{Deserialize, Serialize};
+use serde_big_array::BigArray;
+use camera::{test_pattern, CAMERA_H, CAMERA_W};
+use mbc::*;
+
+// ポケットカメラ ($FC) ... MAC-GBD + M64282FP イメージセンサー
+// https://gbdev.io/pandocs/Gameboy_Camera.html
+// $0000-$1FFF: RAM Enable ($0A、書き込みのみ必要で読み出しは常にできる)
+// $2000-$3FFF: ROMバンク番号 (6bit)
+// $4000-$5FFF: RAMバンク番号 ($00-$0F)、Bit4 = 1 で $A000-$BFFF がカメラのレジスタになる
+// [カメラのレジスタ] ($A000-$A07Fのミラー)
+//   $A000: Bit0 = 撮影開始/撮影中, Bit1-2 = エッジ処理の出力モード
+//   $A001: Bit7 = N, Bit5-6 = VH (エッジ強調の方向), Bit0-4 = ゲイン
+//   $A002-$A003: 露光時間 (上位, 下位)
+//   $A004: Bit4-6 = エッジ強調の比率, Bit3 = 反転, Bit0-2 = 基準電圧
+//   $A005: 出力の基準電圧/ゼロ点 (未使用)
+//   $A006-$A035: 4×4のディザ行列 (1画素あたり3つのしきい値)
+// 撮影した画像は 2bpp のタイル(16×14枚)として RAMバンク0 の $A100-$AEFF に書かれる
+const CAMERA_REG_SIZE: usize = 0x36;
+const DITHER_MATRIX: usize = 0x06;
+const CAMERA_RAM_BANK: u8 = 0x10;
+const IMAGE_OFFSET: usize = 0x0100;
+
+// 露光時間がこの値の時にセンサーの明るさをそのまま出力する
+const EXPOSURE_REF: f32 = 0x1000 as f32;
+// ゲインの1段あたりの増幅量 (dB)
+const GAIN_STEP_DB: f32 = 0.5;
+// エッジ強調の比率 ($A004 Bit4-6)
+const EDGE_RATIO: [f32; 8] = [0.50, 0.75, 1.00, 1.25, 2.00, 3.00, 4.00, 5.00];
+
+#[derive(Serialize, Deserialize)]
+pub struct PocketCamera {
+    ram_enable: bool,
+    rom_bank: u8,
+    ram_bank: u8,
+    #[serde(with = "BigArray")]
+    regs: [u8; CAMERA_REG_SIZE],
+    busy_cycles: u32,       // 撮影が終わるまでのクロック数
+    sensor: Vec<u8>,        // センサーの画像 (CAMERA_W × CAMERA_H)
+}
+
+impl Default for PocketCamera {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PocketCamera {
+    pub fn new() -> Self {
+        PocketCamera {
+            ram_enable: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            regs: [0; CAMERA_REG_SIZE],
+            busy_cycles: 0,
+            sensor: test_pattern(),
+        }
+    }
+
+    fn camera_selected(&self) -> bool {
+        self.ram_bank & CAMERA_RAM_BANK != 0
+    }
+
+    // Returns true if a captured image changed RAM.
+    fn write_camera_reg(&mut self, ram: &mut [u8], addr: u16, val: u8) -> bool {
+        let reg = (addr & 0x7F) as usize;
+
+        match reg {
+            0x00 => {
+                self.regs[0] = val & 0x07;
+                if val & 0x01 != 0 && self.busy_cycles == 0 {
+                    return self.capture(ram);
+                }
+            }
+            0x01..=0x35 => self.regs[reg] = val,
+            _ => (),
+        }
+        false
+    }
+
+    // 撮影 (センサーの読み出し → 露光/ゲイン → エッジ強調 → ディザでタイルに変換)
+    // 画像はすぐに書き込み、撮影時間が経つまで撮影中のフラグを立てておく
+    fn capture(&mut self, ram: &mut [u8]) -> bool {
+        let n = self.regs[1] & 0x80 != 0;
+        let exposure = (self.regs[2] as u32) << 8 | self.regs[3] as u32;
+
+        // 撮影時間 (M-cycle) → クロック
+        self.busy_cycles = (32446 + if n { 0 } else { 512 } + 16 * exposure) * 4;
+
+        let gain = 10f32.powf((self.regs[1] & 0x1F) as f32 * GAIN_STEP_DB / 20.0);
+        let level = exposure as f32 / EXPOSURE_REF * gain;
+        let exposed: Vec<f32> = self.sensor.iter().map(|&px| px as f32 * level).collect();
+        let at = |x: isize, y: isize| {
+            let x = x.clamp(0, CAMERA_W as isize - 1) as usize;
+            let y = y.clamp(0, CAMERA_H as isize - 1) as usize;
+            exposed[y * CAMERA_W + x]
+        };
+
+        let ratio = EDGE_RATIO[(self.regs[4] as usize >> 4) & 0x07];
+        let invert = self.regs[4] & 0x08 != 0;
+        let vh = (self.regs[1] >> 5) & 0x03;
+
+        let image = match ram.get_mut(IMAGE_OFFSET..IMAGE_OFFSET + CAMERA_W * CAMERA_H / 4) {
+            Some(image) => image,
+            None => return false,
+        };
+        let old = image.to_vec();
+        image.iter_mut().for_each(|b| *b = 0);
+
+        for y in 0..CAMERA_H {
+            for x in 0..CAMERA_W {
+                let (ix, iy) = (x as isize, y as isize);
+                let center = at(ix, iy);
+
+                // エッジ強調 (VH: 0 = なし, 1 = 縦, 2 = 横, 3 = 縦横)
+                let edge = match vh {
+                    1 => 2.0 * center - at(ix, iy - 1) - at(ix, iy + 1),
+                    2 => 2.0 * center - at(ix - 1, iy) - at(ix + 1, iy),
+                    3 => 4.0 * center - at(ix, iy - 1) - at(ix, iy + 1) - at(ix - 1, iy) - at(ix + 1, iy),
+                    _ => 0.0,
+                };
+                let mut val = (center + edge * ratio).clamp(0.0, 255.0) as u8;
+                if invert {
+                    val = 255 - val;
+                }
+
+                // ディザ行列のしきい値で4階調にする (暗いほど濃い色)
+                let m = DITHER_MATRIX + ((y & 3) * 4 + (x & 3)) * 3;
+                let color = if val < self.regs[m] {
+                    3
+                } else if val < self.regs[m + 1] {
+                    2
+                } else if val < self.regs[m + 2] {
+                    1
+                } else {
+                    0
+                };
+
+                let i = ((y / 8) * (CAMERA_W / 8) + x / 8) * 16 + (y % 8) * 2;
+                let bit = 7 - (x % 8);
+                image[i] |= (color & 0x01) << bit;
+                image[i + 1] |= (color >> 1) << bit;
+            }
+        }
+
+        image[..] != old[..]
+    }
+}
+
+impl Mbc for PocketCamera {
+    fn read_rom(&self, rom: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => read_rom_bank(rom, 0, addr),
+            _ => read_rom_bank(rom, self.rom_bank as usize, addr),
+        }
+    }
+
+    fn write_reg(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enable = val & 0x0F == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank = val & 0x3F,
+            0x4000..=0x5FFF => self.ram_bank = val & 0x1F,
+            _ => (),
+        }
+    }
+
+    fn read_ram(&mut self, ram: &[u8], addr: u16) -> u8 {
+        if self.camera_selected() {
+            // 読めるのは$A000だけ
+            return match addr & 0x7F {
+                0x00 => self.regs[0] | (self.busy_cycles != 0) as u8,
+                _ => 0x00,
+            };
+        }
+
+        // 撮影中はRAMが読めない
+        if self.busy_cycles != 0 {
+            return 0x00;
+        }
+
+        match ram_index(ram, self.ram_bank as usize & 0x0F, addr) {
+            Some(i) => ram[i],
+            None => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, val: u8) -> bool {
+        if self.camera_selected() {
+            return self.write_camera_reg(ram, addr, val);
+        }
+
+        if !self.ram_enable || self.busy_cycles != 0 {
+            return false;
+        }
+
+        let i = ram_index(ram, self.ram_bank as usize & 0x0F, addr);
+        write_byte(ram, i, val)
+    }
+
+    fn update(&mut self, tick: u8) {
+        if self.busy_cycles != 0 {
+            self.busy_cycles = self.busy_cycles.saturating_sub(tick as u32);
+            if self.busy_cycles == 0 {
+                self.regs[0] &= !0x01;
+            }
+        }
+    }
+
+    fn set_camera_image(&mut self, image: &[u8]) {
+        if image.len() == CAMERA_W * CAMERA_H {
+            self.sensor.copy_from_slice(image);
+        } else {
+            warn!("Camera image must be {}x{} (got {} bytes)", CAMERA_W, CAMERA_H, image.len());
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use serde::{Deserialize, Serialize};
+use mbc::*;
+
+// HuC1 ($FF) ... 赤外線通信ポート付き (ポケットカード系など)
+// https://gbdev.io/pandocs/HuC1.html
+// $0000-$1FFF: $0E = 赤外線モード, それ以外 = RAMモード
+// $2000-$3FFF: ROMバンク番号 (6bit)
+// $4000-$5FFF: RAMバンク番号 (2bit)
+// [赤外線モード] ($A000-$BFFF)
+//   Read : $C0 = 受光なし, $C1 = 受光あり
+//   Write: Bit0 = LED
+#[derive(Serialize, Deserialize)]
+pub struct HuC1 {
+    ir_mode: bool,
+    rom_bank: u8,
+    ram_bank: u8,
+    ir_led: bool,
+    ir_input: bool,
+}
+
+impl Default for HuC1 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HuC1 {
+    pub fn new() -> Self {
+        HuC1 {
+            ir_mode: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            ir_led: false,
+            ir_input: false,
+        }
+    }
+}
+
+impl Mbc for HuC1 {
+    fn read_rom(&self, rom: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => read_rom_bank(rom, 0, addr),
+            _ => read_rom_bank(rom, self.rom_bank as usize, addr),
+        }
+    }
+
+    fn write_reg(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ir_mode = val & 0x0F == 0x0E,
+            0x2000..=0x3FFF => self.rom_bank = val & 0x3F,
+            0x4000..=0x5FFF => self.ram_bank = val & 0x03,
+            _ => (),
+        }
+    }
+
+    fn read_ram(&mut self, ram: &[u8], addr: u16) -> u8 {
+        if self.ir_mode {
+            return 0xC0 | self.ir_input as u8;
+        }
+
+        match ram_index(ram, self.ram_bank as usize, addr) {
+            Some(i) => ram[i],
+            None => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, val: u8) -> bool {
+        if self.ir_mode {
+            self.ir_led = val & 0x01 != 0;
+            return false;
+        }
+
+        let i = ram_index(ram, self.ram_bank as usize, addr);
+        write_byte(ram, i, val)
+    }
+
+    fn ir_led(&self) -> bool {
+        self.ir_led
+    }
+
+    fn set_ir_input(&mut self, light: bool) {
+        self.ir_input = light;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mbc::tests::{bank_at, banked_rom};
+
+    #[test]
+    fn rom_and_ram_banking() {
+        let rom = banked_rom(64);
+        let mut ram = vec![0; 32 * 1024];
+        let mut mbc = HuC1::new();
+
+        mbc.write_reg(0x2000, 0x7F);
+        assert_eq!(bank_at(&mbc, &rom, 0x4000), 0x3F);
+
+        // RAM Enableは不要
+        mbc.write_reg(0x4000, 0x03);
+        assert!(mbc.write_ram(&mut ram, 0xA123, 0x42));
+        assert_eq!(ram[3 * RAM_BANK_SIZE + 0x123], 0x42);
+        assert_eq!(mbc.read_ram(&ram, 0xA123), 0x42);
+    }
+
+    #[test]
+    fn infrared_mode() {
+        let mut ram = vec![0; 8 * 1024];
+        let mut mbc = HuC1::new();
+        mbc.write_reg(0x0000, 0x0E);
+
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0xC0);
+        mbc.set_ir_input(true);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0xC1);
+
+        // LEDの書き込みはRAMを書き換えない
+        assert!(!mbc.write_ram(&mut ram, 0xA000, 0x01));
+        assert!(mbc.ir_led());
+        assert_eq!(ram[0], 0x00);
+
+        mbc.write_reg(0x0000, 0x00);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0x00);
+    }
+}
@@ -0,0 +1,333 @@
+use serde::{Deserialize, Serialize};
+use mbc::*;
+
+// HuC3 ($FE) ... RTC + 赤外線通信ポート付き (ロボットポンコッツなど)
+// https://gbdev.io/pandocs/HuC3.html
+// $0000-$1FFF: $A000-$BFFFのモード
+//   $0 = RAM (読み出しのみ), $A = RAM, $B = RTCコマンド (Write)
+//   $C = RTCの応答 (Read), $D = RTCのセマフォ, $E = 赤外線
+// $2000-$3FFF: ROMバンク番号 (7bit)
+// $4000-$5FFF: RAMバンク番号 (2bit)
+const MODE_RAM_READ: u8 = 0x0;
+const MODE_RAM: u8 = 0xA;
+const MODE_RTC_COMMAND: u8 = 0xB;
+const MODE_RTC_RESPONSE: u8 = 0xC;
+const MODE_RTC_SEMAPHORE: u8 = 0xD;
+const MODE_IR: u8 = 0xE;
+
+#[derive(Serialize, Deserialize)]
+pub struct HuC3 {
+    mode: u8,
+    rom_bank: u8,
+    ram_bank: u8,
+    rtc: HuC3Rtc,
+    ir_led: bool,
+    ir_input: bool,
+}
+
+impl Default for HuC3 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HuC3 {
+    pub fn new() -> Self {
+        HuC3 {
+            mode: MODE_RAM_READ,
+            rom_bank: 1,
+            ram_bank: 0,
+            rtc: HuC3Rtc::new(),
+            ir_led: false,
+            ir_input: false,
+        }
+    }
+}
+
+impl Mbc for HuC3 {
+    fn read_rom(&self, rom: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => read_rom_bank(rom, 0, addr),
+            _ => read_rom_bank(rom, self.rom_bank as usize, addr),
+        }
+    }
+
+    fn write_reg(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.mode = val & 0x0F,
+            0x2000..=0x3FFF => self.rom_bank = val & 0x7F,
+            0x4000..=0x5FFF => self.ram_bank = val & 0x03,
+            _ => (),
+        }
+    }
+
+    fn read_ram(&mut self, ram: &[u8], addr: u16) -> u8 {
+        match self.mode {
+            MODE_RAM_READ | MODE_RAM => match ram_index(ram, self.ram_bank as usize, addr) {
+                Some(i) => ram[i],
+                None => 0xFF,
+            },
+            MODE_RTC_RESPONSE => self.rtc.response(),
+            // コマンドはすぐに実行するので常にReady
+            MODE_RTC_SEMAPHORE => 0xFF,
+            MODE_IR => 0xC0 | self.ir_input as u8,
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, val: u8) -> bool {
+        match self.mode {
+            MODE_RAM => {
+                let i = ram_index(ram, self.ram_bank as usize, addr);
+                return write_byte(ram, i, val);
+            }
+            MODE_RTC_COMMAND => self.rtc.command(val),
+            MODE_IR => self.ir_led = val & 0x01 != 0,
+            _ => (),
+        }
+        false
+    }
+
+    fn save_extra(&mut self) -> Vec<u8> {
+        self.rtc.save()
+    }
+
+    fn extra_size(&self) -> usize {
+        RTC_FOOTER_SIZE
+    }
+
+    fn load_extra(&mut self, data: &[u8]) {
+        self.rtc.load(data);
+    }
+
+    fn ir_led(&self) -> bool {
+        self.ir_led
+    }
+
+    fn set_ir_input(&mut self, light: bool) {
+        self.ir_input = light;
+    }
+}
+
+// [HuC3のRTC]
+// 4bit × 256のメモリをコマンドで読み書きする
+// コマンド ($A000 Write, Bit4-6 = コマンド, Bit0-3 = 引数)
+//   $1: メモリ[アドレス]を応答に入れる (アドレス+1)
+//   $3: メモリ[アドレス]に引数を書く (アドレス+1)
+//   $4: アドレスの下位4bit    $5: アドレスの上位4bit
+//   $6: 拡張コマンド (引数 $0 = 時刻をメモリ$00~$05に読み出す, $1 = メモリ$00~$05を時刻に書き込む, $2 = 状態 (常に1))
+// 応答 ($A000 Read) = $80 | コマンド << 4 | 結果
+// 時刻はメモリ$00~$02に分 (0~1439)、$03~$05に日数 (12bit) をリトルエンディアンの4bitずつ
+//
+// ホストの時計(UNIX時間)との差分で進める
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+// [.savのRTCフッター (SameBoy形式、17Byte、リトルエンディアン)]
+// | UNIX時間 (u64) | 分 (u16) | 日 (u16) | アラームの分 (u16) | アラームの日 (u16) | アラーム有効 (u8) |
+// ※ アラームは未対応 (0を書く)
+const RTC_FOOTER_SIZE: usize = 17;
+
+#[derive(Serialize, Deserialize)]
+struct HuC3Rtc {
+    mem: Vec<u8>,       // 4bit × 256
+    addr: u8,
+    cmd: u8,
+    result: u8,
+    minutes: u32,       // 0時からの分
+    days: u32,
+    seconds: u64,       // 1分に満たない秒
+    last_time: u64,     // 最後に時刻を進めた時刻 (UNIX時間)
+}
+
+impl HuC3Rtc {
+    fn new() -> Self {
+        HuC3Rtc {
+            mem: vec![0; 0x100],
+            addr: 0,
+            cmd: 0,
+            result: 0,
+            minutes: 0,
+            days: 0,
+            seconds: 0,
+            last_time: unix_time(),
+        }
+    }
+
+    // Advances the clock to the current host time.
+    fn sync(&mut self) {
+        let now = unix_time();
+        let total = self.seconds + now.saturating_sub(self.last_time);
+        self.last_time = now;
+        self.seconds = total % 60;
+
+        let minutes = self.minutes as u64 + total / 60;
+        self.minutes = (minutes % MINUTES_PER_DAY as u64) as u32;
+        self.days = ((self.days as u64 + minutes / MINUTES_PER_DAY as u64) & 0x0FFF) as u32;
+    }
+
+    fn response(&self) -> u8 {
+        0x80 | self.cmd << 4 | self.result
+    }
+
+    fn command(&mut self, val: u8) {
+        self.cmd = (val >> 4) & 0x07;
+        let arg = val & 0x0F;
+
+        match self.cmd {
+            0x1 => {
+                self.result = self.mem[self.addr as usize];
+                self.addr = self.addr.wrapping_add(1);
+            }
+            0x3 => {
+                self.mem[self.addr as usize] = arg;
+                self.addr = self.addr.wrapping_add(1);
+            }
+            0x4 => self.addr = (self.addr & 0xF0) | arg,
+            0x5 => self.addr = (self.addr & 0x0F) | arg << 4,
+            0x6 => match arg {
+                0x0 => {
+                    self.sync();
+                    let (minutes, days) = (self.minutes, self.days);
+                    for i in 0..3 {
+                        self.mem[i] = (minutes >> (i * 4)) as u8 & 0x0F;
+                        self.mem[3 + i] = (days >> (i * 4)) as u8 & 0x0F;
+                    }
+                }
+                0x1 => {
+                    self.sync();
+                    let mem = &self.mem;
+                    let nibbles = |base: usize| (0..3).fold(0, |acc, i| acc | (mem[base + i] as u32) << (i * 4));
+                    let (minutes, days) = (nibbles(0), nibbles(3));
+                    self.minutes = minutes % MINUTES_PER_DAY;
+                    self.days = days;
+                    self.seconds = 0;
+                }
+                0x2 => self.result = 0x01,
+                // $E: 音を鳴らす (未対応)
+                _ => (),
+            },
+            _ => (),
+        }
+    }
+
+    fn save(&mut self) -> Vec<u8> {
+        self.sync();
+
+        let mut data = Vec::with_capacity(RTC_FOOTER_SIZE);
+        data.extend_from_slice(&(self.last_time - self.seconds).to_le_bytes());
+        data.extend_from_slice(&(self.minutes as u16).to_le_bytes());
+        data.extend_from_slice(&(self.days as u16).to_le_bytes());
+        data.extend_from_slice(&[0; 5]);
+        data
+    }
+
+    fn load(&mut self, data: &[u8]) {
+        if data.len() != RTC_FOOTER_SIZE {
+            if !data.is_empty() {
+                warn!("Unknown RTC footer size ({} bytes)", data.len());
+            }
+            return;
+        }
+
+        let mut time = [0u8; 8];
+        time.copy_from_slice(&data[0..8]);
+        self.last_time = u64::from_le_bytes(time);
+        self.minutes = u16::from_le_bytes([data[8], data[9]]) as u32 % MINUTES_PER_DAY;
+        self.days = u16::from_le_bytes([data[10], data[11]]) as u32 & 0x0FFF;
+        self.seconds = 0;
+
+        // セーブしてからの経過時間を進める
+        self.sync();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(mbc: &mut HuC3, ram: &mut [u8], val: u8) -> u8 {
+        mbc.write_reg(0x0000, MODE_RTC_COMMAND);
+        mbc.write_ram(ram, 0xA000, val);
+        mbc.write_reg(0x0000, MODE_RTC_RESPONSE);
+        mbc.read_ram(ram, 0xA000)
+    }
+
+    // メモリ[addr]から4bitずつ書き込む
+    fn write_mem(mbc: &mut HuC3, ram: &mut [u8], addr: u8, nibbles: &[u8]) {
+        command(mbc, ram, 0x40 | (addr & 0x0F));
+        command(mbc, ram, 0x50 | addr >> 4);
+        for &val in nibbles {
+            command(mbc, ram, 0x30 | val);
+        }
+    }
+
+    fn read_mem(mbc: &mut HuC3, ram: &mut [u8], addr: u8, len: usize) -> Vec<u8> {
+        command(mbc, ram, 0x40 | (addr & 0x0F));
+        command(mbc, ram, 0x50 | addr >> 4);
+        (0..len).map(|_| command(mbc, ram, 0x10) & 0x0F).collect()
+    }
+
+    #[test]
+    fn ram_modes() {
+        let mut ram = vec![0; 32 * 1024];
+        let mut mbc = HuC3::new();
+
+        // $0は読み出しのみ
+        assert!(!mbc.write_ram(&mut ram, 0xA000, 0x12));
+        mbc.write_reg(0x0000, MODE_RAM);
+        mbc.write_reg(0x4000, 0x02);
+        assert!(mbc.write_ram(&mut ram, 0xA000, 0x12));
+        assert_eq!(ram[2 * RAM_BANK_SIZE], 0x12);
+        mbc.write_reg(0x0000, MODE_RAM_READ);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0x12);
+
+        mbc.write_reg(0x0000, MODE_RTC_SEMAPHORE);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0xFF);
+    }
+
+    #[test]
+    fn rtc_memory_commands() {
+        let mut ram = vec![0; 8 * 1024];
+        let mut mbc = HuC3::new();
+
+        write_mem(&mut mbc, &mut ram, 0x10, &[0x7, 0xA]);
+        assert_eq!(read_mem(&mut mbc, &mut ram, 0x10, 2), [0x7, 0xA]);
+        // 応答 = $80 | コマンド << 4 | 結果
+        assert_eq!(command(&mut mbc, &mut ram, 0x10), 0x90);
+        assert_eq!(command(&mut mbc, &mut ram, 0x62), 0xE1);
+    }
+
+    #[test]
+    fn rtc_time_is_written_and_saved() {
+        let mut ram = vec![0; 8 * 1024];
+        let mut mbc = HuC3::new();
+
+        // 分 = $123 (291)、日 = $456
+        write_mem(&mut mbc, &mut ram, 0x00, &[0x3, 0x2, 0x1, 0x6, 0x5, 0x4]);
+        command(&mut mbc, &mut ram, 0x61);
+        write_mem(&mut mbc, &mut ram, 0x00, &[0; 6]);
+        command(&mut mbc, &mut ram, 0x60);
+        assert_eq!(read_mem(&mut mbc, &mut ram, 0x00, 6), [0x3, 0x2, 0x1, 0x6, 0x5, 0x4]);
+
+        let footer = mbc.save_extra();
+        assert_eq!(footer.len(), mbc.extra_size());
+        assert_eq!(&footer[8..12], &[0x23, 0x01, 0x56, 0x04]);
+
+        let mut loaded = HuC3::new();
+        loaded.load_extra(&footer);
+        assert_eq!((loaded.rtc.minutes, loaded.rtc.days), (0x123, 0x456));
+    }
+
+    #[test]
+    fn infrared_mode() {
+        let mut ram = vec![0; 8 * 1024];
+        let mut mbc = HuC3::new();
+        mbc.write_reg(0x0000, MODE_IR);
+
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0xC0);
+        mbc.set_ir_input(true);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0xC1);
+        mbc.write_ram(&mut ram, 0xA000, 0x01);
+        assert!(mbc.ir_led());
+    }
+}
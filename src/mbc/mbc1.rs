@@ -0,0 +1,186 @@
+use serde::{Deserialize, Serialize};
+use cartridge::NINTENDO_LOGO;
+use mbc::*;
+
+// MBC1 ($01~$03)
+// https://gbdev.io/pandocs/MBC1.html
+// $0000-$1FFF: RAM Enable ($0A)
+// $2000-$3FFF: ROMバンク番号 下位5bit (BANK1、0は1になる)
+// $4000-$5FFF: RAMバンク番号 or ROMバンク番号 上位2bit (BANK2)
+// $6000-$7FFF: バンキングモード (0=シンプル, 1=アドバンス)
+//
+// [MBC1M (1MBマルチカート)]
+// BANK2がBit4から繋がっている (ROMバンク番号 = BANK2 << 4 | BANK1の下位4bit)
+#[derive(Serialize, Deserialize)]
+pub struct Mbc1 {
+    ram_enable: bool,
+    bank1: u8,
+    bank2: u8,
+    mode: bool,
+    multicart: bool,
+}
+
+impl Mbc1 {
+    pub fn new(rom: &[u8]) -> Self {
+        let multicart = is_multicart(rom);
+        if multicart {
+            info!("MBC1 multicart (MBC1M)");
+        }
+
+        Mbc1 {
+            ram_enable: false,
+            bank1: 1,
+            bank2: 0,
+            mode: false,
+            multicart,
+        }
+    }
+
+    fn bank2_shift(&self) -> u8 {
+        if self.multicart { 4 } else { 5 }
+    }
+
+    fn bank1_mask(&self) -> u8 {
+        if self.multicart { 0x0F } else { 0x1F }
+    }
+
+    fn rom_bank_lo(&self) -> usize {
+        // アドバンスモードでは$0000-$3FFFもBANK2で切り替わる
+        if self.mode {
+            (self.bank2 << self.bank2_shift()) as usize
+        } else {
+            0
+        }
+    }
+
+    fn rom_bank_hi(&self) -> usize {
+        ((self.bank2 << self.bank2_shift()) | (self.bank1 & self.bank1_mask())) as usize
+    }
+
+    fn ram_bank(&self) -> usize {
+        if self.mode {
+            self.bank2 as usize
+        } else {
+            0
+        }
+    }
+}
+
+// MBC1M: 1MBのROMで、バンク$10にもヘッダー(ロゴ)があればマルチカート
+fn is_multicart(rom: &[u8]) -> bool {
+    let logo = 0x10 * ROM_BANK_SIZE + 0x0104;
+    rom.len() == 1024 * 1024 && rom[logo..logo + NINTENDO_LOGO.len()] == NINTENDO_LOGO
+}
+
+impl Mbc for Mbc1 {
+    fn read_rom(&self, rom: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => read_rom_bank(rom, self.rom_bank_lo(), addr),
+            _ => read_rom_bank(rom, self.rom_bank_hi(), addr),
+        }
+    }
+
+    fn write_reg(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enable = val & 0x0F == 0x0A,
+            0x2000..=0x3FFF => {
+                // 0は1として扱う (5bit全体で判定)
+                self.bank1 = if val & 0x1F == 0 { 1 } else { val & 0x1F };
+            }
+            0x4000..=0x5FFF => self.bank2 = val & 0x03,
+            _ => self.mode = val & 0x01 != 0,
+        }
+    }
+
+    fn read_ram(&mut self, ram: &[u8], addr: u16) -> u8 {
+        if !self.ram_enable {
+            return 0xFF;
+        }
+        match ram_index(ram, self.ram_bank(), addr) {
+            Some(i) => ram[i],
+            None => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, val: u8) -> bool {
+        if !self.ram_enable {
+            return false;
+        }
+        let i = ram_index(ram, self.ram_bank(), addr);
+        write_byte(ram, i, val)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mbc::tests::{bank_at, banked_rom};
+
+    #[test]
+    fn rom_banking() {
+        // 2MB (128バンク)
+        let rom = banked_rom(128);
+        let mut mbc = Mbc1::new(&rom);
+        assert_eq!(bank_at(&mbc, &rom, 0x4000), 1);
+
+        // 0は1になる (BANK2と組み合わせても$20,$40,$60は選べない)
+        mbc.write_reg(0x2000, 0x00);
+        assert_eq!(bank_at(&mbc, &rom, 0x4000), 1);
+        mbc.write_reg(0x4000, 0x01);
+        assert_eq!(bank_at(&mbc, &rom, 0x4000), 0x21);
+        // 下位5bitだけで0を判定する
+        mbc.write_reg(0x2000, 0xE0);
+        assert_eq!(bank_at(&mbc, &rom, 0x4000), 0x21);
+        mbc.write_reg(0x2000, 0x1F);
+        assert_eq!(bank_at(&mbc, &rom, 0x4000), 0x3F);
+
+        // アドバンスモードでは$0000-$3FFFもBANK2で切り替わる
+        assert_eq!(bank_at(&mbc, &rom, 0x0000), 0);
+        mbc.write_reg(0x6000, 0x01);
+        assert_eq!(bank_at(&mbc, &rom, 0x0000), 0x20);
+    }
+
+    #[test]
+    fn ram_banking() {
+        let rom = banked_rom(4);
+        let mut ram = vec![0; 32 * 1024];
+        let mut mbc = Mbc1::new(&rom);
+
+        // RAM Enableの前は読み書きできない
+        assert!(!mbc.write_ram(&mut ram, 0xA000, 0x11));
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0xFF);
+
+        mbc.write_reg(0x0000, 0x0A);
+        mbc.write_reg(0x4000, 0x02);
+        // シンプルモードではバンク0
+        assert!(mbc.write_ram(&mut ram, 0xA000, 0x11));
+        assert_eq!(ram[0], 0x11);
+
+        mbc.write_reg(0x6000, 0x01);
+        assert!(mbc.write_ram(&mut ram, 0xA000, 0x22));
+        assert_eq!(ram[2 * RAM_BANK_SIZE], 0x22);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0x22);
+    }
+
+    #[test]
+    fn multicart_banking() {
+        // 1MB、バンク$10にもロゴがあればMBC1M
+        let mut rom = banked_rom(64);
+        rom[0x10 * ROM_BANK_SIZE + 0x0104..0x10 * ROM_BANK_SIZE + 0x0134].copy_from_slice(&NINTENDO_LOGO);
+        let mut mbc = Mbc1::new(&rom);
+        assert!(mbc.multicart);
+
+        // ROMバンク番号 = BANK2 << 4 | BANK1の下位4bit
+        mbc.write_reg(0x2000, 0x12);
+        mbc.write_reg(0x4000, 0x01);
+        assert_eq!(bank_at(&mbc, &rom, 0x4000), 0x12);
+        mbc.write_reg(0x4000, 0x03);
+        assert_eq!(bank_at(&mbc, &rom, 0x4000), 0x32);
+
+        mbc.write_reg(0x6000, 0x01);
+        assert_eq!(bank_at(&mbc, &rom, 0x0000), 0x30);
+
+        // ロゴがなければ通常のMBC1
+        assert!(!Mbc1::new(&banked_rom(64)).multicart);
+    }
+}
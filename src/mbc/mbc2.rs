@@ -0,0 +1,110 @@
+use serde::{Deserialize, Serialize};
+use mbc::*;
+
+// MBC2 ($05, $06)
+// https://gbdev.io/pandocs/MBC2.html
+// $0000-$3FFF: アドレスのBit8で切り替え
+//              Bit8=0: RAM Enable ($0A)
+//              Bit8=1: ROMバンク番号 (4bit、0は1になる)
+// $A000-$A1FF: 内蔵RAM 512×4bit ($A200-$BFFFはミラー、上位4bitはオープンバス)
+pub const MBC2_RAM_SIZE: usize = 512;
+
+#[derive(Serialize, Deserialize)]
+pub struct Mbc2 {
+    ram_enable: bool,
+    rom_bank: u8,
+}
+
+impl Default for Mbc2 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mbc2 {
+    pub fn new() -> Self {
+        Mbc2 {
+            ram_enable: false,
+            rom_bank: 1,
+        }
+    }
+}
+
+impl Mbc for Mbc2 {
+    fn read_rom(&self, rom: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => read_rom_bank(rom, 0, addr),
+            _ => read_rom_bank(rom, self.rom_bank as usize, addr),
+        }
+    }
+
+    fn write_reg(&mut self, addr: u16, val: u8) {
+        if addr >= 0x4000 {
+            return;
+        }
+
+        if addr & 0x0100 == 0 {
+            self.ram_enable = val & 0x0F == 0x0A;
+        } else {
+            self.rom_bank = if val & 0x0F == 0 { 1 } else { val & 0x0F };
+        }
+    }
+
+    fn read_ram(&mut self, ram: &[u8], addr: u16) -> u8 {
+        if !self.ram_enable || ram.is_empty() {
+            return 0xFF;
+        }
+        0xF0 | (ram[(addr as usize & (MBC2_RAM_SIZE - 1)) % ram.len()] & 0x0F)
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, val: u8) -> bool {
+        if !self.ram_enable || ram.is_empty() {
+            return false;
+        }
+        let i = (addr as usize & (MBC2_RAM_SIZE - 1)) % ram.len();
+        write_byte(ram, Some(i), val & 0x0F)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mbc::tests::{bank_at, banked_rom};
+
+    #[test]
+    fn rom_bank_is_selected_by_address_bit8() {
+        let rom = banked_rom(16);
+        let mut mbc = Mbc2::new();
+        assert_eq!(bank_at(&mbc, &rom, 0x4000), 1);
+
+        mbc.write_reg(0x2100, 0x13);
+        assert_eq!(bank_at(&mbc, &rom, 0x4000), 3);
+        mbc.write_reg(0x0100, 0x00);
+        assert_eq!(bank_at(&mbc, &rom, 0x4000), 1);
+        // Bit8=0はRAM Enable
+        mbc.write_reg(0x2000, 0x05);
+        assert_eq!(bank_at(&mbc, &rom, 0x4000), 1);
+        // $4000以降は何もしない
+        mbc.write_reg(0x4100, 0x05);
+        assert_eq!(bank_at(&mbc, &rom, 0x4000), 1);
+    }
+
+    #[test]
+    fn ram_is_4bit_and_mirrored() {
+        let mut ram = vec![0; MBC2_RAM_SIZE];
+        let mut mbc = Mbc2::new();
+        assert!(!mbc.write_ram(&mut ram, 0xA000, 0x0F));
+
+        mbc.write_reg(0x0000, 0x0A);
+        assert!(mbc.write_ram(&mut ram, 0xA001, 0xAB));
+        assert_eq!(ram[1], 0x0B);
+        // 上位4bitはオープンバス
+        assert_eq!(mbc.read_ram(&ram, 0xA001), 0xFB);
+        // $A200-$BFFFはミラー
+        assert_eq!(mbc.read_ram(&ram, 0xA201), 0xFB);
+        assert_eq!(mbc.read_ram(&ram, 0xBE01), 0xFB);
+
+        mbc.write_reg(0x0000, 0x00);
+        assert_eq!(mbc.read_ram(&ram, 0xA001), 0xFF);
+    }
+}
@@ -0,0 +1,362 @@
+use serde::{Deserialize, Serialize};
+use mbc::*;
+
+// MBC3 ($0F~$13)
+// https://gbdev.io/pandocs/MBC3.html
+// $0000-$1FFF: RAM/RTC Enable ($0A)
+// $2000-$3FFF: ROMバンク番号 (7bit、0は1になる)
+// $4000-$5FFF: RAMバンク番号 ($00~$07) or RTCレジスタ選択 ($08~$0C)
+// $6000-$7FFF: RTCラッチ ($00 → $01の書き込みで現在時刻をラッチ)
+#[derive(Serialize, Deserialize)]
+pub struct Mbc3 {
+    ram_enable: bool,
+    rom_bank: u8,
+    ram_bank: u8,
+    latch_prev: u8,
+    rtc: Option<Rtc>,   // MBC3+TIMER ($0F, $10) のみ
+}
+
+impl Mbc3 {
+    pub fn new(has_rtc: bool) -> Self {
+        Mbc3 {
+            ram_enable: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            latch_prev: 0xFF,
+            rtc: if has_rtc { Some(Rtc::new()) } else { None },
+        }
+    }
+}
+
+impl Mbc for Mbc3 {
+    fn read_rom(&self, rom: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => read_rom_bank(rom, 0, addr),
+            _ => read_rom_bank(rom, self.rom_bank as usize, addr),
+        }
+    }
+
+    fn write_reg(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enable = val & 0x0F == 0x0A,
+            0x2000..=0x3FFF => {
+                self.rom_bank = if val & 0x7F == 0 { 1 } else { val & 0x7F };
+            }
+            0x4000..=0x5FFF => self.ram_bank = val & 0x0F,
+            _ => {
+                if self.latch_prev == 0x00 && val == 0x01 {
+                    if let Some(rtc) = self.rtc.as_mut() {
+                        rtc.latch();
+                    }
+                }
+                self.latch_prev = val;
+            }
+        }
+    }
+
+    fn read_ram(&mut self, ram: &[u8], addr: u16) -> u8 {
+        if !self.ram_enable {
+            return 0xFF;
+        }
+        match (self.ram_bank, self.rtc.as_mut()) {
+            (0x00..=0x07, _) => match ram_index(ram, self.ram_bank as usize, addr) {
+                Some(i) => ram[i],
+                None => 0xFF,
+            },
+            (0x08..=0x0C, Some(rtc)) => rtc.read(self.ram_bank - 0x08),
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, val: u8) -> bool {
+        if !self.ram_enable {
+            return false;
+        }
+        match (self.ram_bank, self.rtc.as_mut()) {
+            (0x00..=0x07, _) => {
+                let i = ram_index(ram, self.ram_bank as usize, addr);
+                return write_byte(ram, i, val);
+            }
+            (0x08..=0x0C, Some(rtc)) => rtc.write(self.ram_bank - 0x08, val),
+            _ => (),
+        }
+        false
+    }
+
+    fn save_extra(&mut self) -> Vec<u8> {
+        match self.rtc.as_mut() {
+            Some(rtc) => rtc.save(),
+            None => Vec::new(),
+        }
+    }
+
+    fn extra_size(&self) -> usize {
+        if self.rtc.is_some() {
+            RTC_FOOTER_SIZE
+        } else {
+            0
+        }
+    }
+
+    fn load_extra(&mut self, data: &[u8]) {
+        if let Some(rtc) = self.rtc.as_mut() {
+            rtc.load(data);
+        }
+    }
+}
+
+// [RTC (Real Time Clock)]
+// $08: 秒 (0~59)
+// $09: 分 (0~59)
+// $0A: 時 (0~23)
+// $0B: 日カウンタ 下位8bit
+// $0C: Bit0 = 日カウンタ Bit8, Bit6 = 停止, Bit7 = 日カウンタのキャリー
+//
+// ホストの時計(UNIX時間)との差分で進める
+const RTC_S: usize = 0;
+const RTC_M: usize = 1;
+const RTC_H: usize = 2;
+const RTC_DL: usize = 3;
+const RTC_DH: usize = 4;
+const RTC_MASK: [u8; 5] = [0x3F, 0x3F, 0x1F, 0xFF, 0xC1];
+const RTC_HALT: u8 = 0x40;
+const RTC_CARRY: u8 = 0x80;
+
+// [.savのRTCフッター (BGB/VBA形式、48Byte、リトルエンディアン)]
+// | 秒, 分, 時, 日L, 日H (u32 × 5) | ラッチ済みの秒, 分, 時, 日L, 日H (u32 × 5) | UNIX時間 (u64) |
+// ※ 古いVBAはUNIX時間がu32の44Byte
+const RTC_FOOTER_SIZE: usize = 48;
+const RTC_FOOTER_SIZE_OLD: usize = 44;
+
+#[derive(Serialize, Deserialize)]
+pub struct Rtc {
+    regs: [u8; 5],
+    latched: [u8; 5],
+    last_time: u64,     // 最後にレジスタを進めた時刻 (UNIX時間)
+}
+
+impl Rtc {
+    pub fn new() -> Self {
+        Rtc {
+            regs: [0; 5],
+            latched: [0; 5],
+            last_time: unix_time(),
+        }
+    }
+
+    // Advances the clock to the current host time.
+    fn sync(&mut self) {
+        let now = unix_time();
+        let elapsed = now.saturating_sub(self.last_time);
+        self.last_time = now;
+
+        if self.regs[RTC_DH] & RTC_HALT != 0 || elapsed == 0 {
+            return;
+        }
+
+        let days = ((self.regs[RTC_DH] & 0x01) as u64) << 8 | self.regs[RTC_DL] as u64;
+        let total = elapsed
+            + self.regs[RTC_S] as u64
+            + self.regs[RTC_M] as u64 * 60
+            + self.regs[RTC_H] as u64 * 3600
+            + days * 86400;
+
+        self.regs[RTC_S] = (total % 60) as u8;
+        self.regs[RTC_M] = (total / 60 % 60) as u8;
+        self.regs[RTC_H] = (total / 3600 % 24) as u8;
+
+        let days = total / 86400;
+        if days > 0x1FF {
+            self.regs[RTC_DH] |= RTC_CARRY;
+        }
+        self.regs[RTC_DL] = days as u8;
+        self.regs[RTC_DH] = (self.regs[RTC_DH] & !0x01) | ((days >> 8) & 0x01) as u8;
+    }
+
+    fn latch(&mut self) {
+        self.sync();
+        self.latched = self.regs;
+    }
+
+    fn read(&self, reg: u8) -> u8 {
+        self.latched[reg as usize]
+    }
+
+    fn write(&mut self, reg: u8, val: u8) {
+        self.sync();
+        let reg = reg as usize;
+        self.regs[reg] = val & RTC_MASK[reg];
+        self.latched[reg] = self.regs[reg];
+    }
+
+    fn save(&mut self) -> Vec<u8> {
+        self.sync();
+
+        let mut data = Vec::with_capacity(RTC_FOOTER_SIZE);
+        for &val in self.regs.iter().chain(self.latched.iter()) {
+            data.extend_from_slice(&(val as u32).to_le_bytes());
+        }
+        data.extend_from_slice(&self.last_time.to_le_bytes());
+        data
+    }
+
+    fn load(&mut self, data: &[u8]) {
+        if data.len() != RTC_FOOTER_SIZE && data.len() != RTC_FOOTER_SIZE_OLD {
+            if !data.is_empty() {
+                warn!("Unknown RTC footer size ({} bytes)", data.len());
+            }
+            return;
+        }
+
+        let u32_at = |i: usize| {
+            let mut buf = [0u8; 4];
+            buf.copy_from_slice(&data[i..i + 4]);
+            u32::from_le_bytes(buf)
+        };
+        for (i, mask) in RTC_MASK.iter().enumerate() {
+            self.regs[i] = u32_at(i * 4) as u8 & mask;
+            self.latched[i] = u32_at(20 + i * 4) as u8 & mask;
+        }
+        self.last_time = if data.len() == RTC_FOOTER_SIZE {
+            let mut buf = [0u8; 8];
+            buf.copy_from_slice(&data[40..48]);
+            u64::from_le_bytes(buf)
+        } else {
+            u32_at(40) as u64
+        };
+
+        // セーブしてからの経過時間を進める
+        self.sync();
+    }
+}
+
+impl Default for Rtc {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `secs`秒前に止めた時計 (テスト中に秒が変わることがあるので秒は±1まで許す)
+    fn rtc_since(secs: u64) -> Rtc {
+        let mut rtc = Rtc::new();
+        rtc.last_time -= secs;
+        rtc
+    }
+
+    fn assert_secs(rtc: &Rtc, secs: u8) {
+        assert!((secs..=secs + 1).contains(&rtc.regs[RTC_S]), "{}", rtc.regs[RTC_S]);
+    }
+
+    #[test]
+    fn advances_with_host_time() {
+        let mut rtc = rtc_since(86400 + 3600 + 60 + 1);
+        rtc.sync();
+
+        assert_secs(&rtc, 1);
+        assert_eq!(rtc.regs[RTC_M], 1);
+        assert_eq!(rtc.regs[RTC_H], 1);
+        assert_eq!(rtc.regs[RTC_DL], 1);
+        assert_eq!(rtc.regs[RTC_DH], 0);
+    }
+
+    #[test]
+    fn halted_clock_does_not_advance() {
+        let mut rtc = rtc_since(3600);
+        rtc.regs[RTC_DH] = RTC_HALT;
+        rtc.sync();
+
+        assert_eq!(rtc.regs, [0, 0, 0, 0, RTC_HALT]);
+        // 止めていた間の時間は後から進まない
+        rtc.regs[RTC_DH] = 0;
+        rtc.sync();
+        assert_eq!(rtc.regs[RTC_H], 0);
+    }
+
+    #[test]
+    fn day_counter_overflow_sets_carry() {
+        let mut rtc = rtc_since(10);
+        rtc.regs = [55, 59, 23, 0xFF, 0x01];
+        rtc.sync();
+
+        assert_secs(&rtc, 5);
+        assert_eq!(rtc.regs[RTC_M..=RTC_DL], [0, 0, 0]);
+        assert_eq!(rtc.regs[RTC_DH], RTC_CARRY);
+
+        // キャリーはソフトが消すまで残る
+        rtc.last_time -= 86400;
+        rtc.sync();
+        assert_eq!(rtc.regs[RTC_DL], 1);
+        assert_eq!(rtc.regs[RTC_DH], RTC_CARRY);
+    }
+
+    #[test]
+    fn registers_are_latched() {
+        let mut mbc = Mbc3::new(true);
+        let mut ram = [];
+        mbc.write_reg(0x0000, 0x0A);
+        mbc.write_reg(0x4000, 0x0A);
+        mbc.write_ram(&mut ram, 0xA000, 0x05);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0x05);
+
+        mbc.rtc.as_mut().unwrap().regs[RTC_H] = 0x07;
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0x05);
+
+        // $00 → $01 でラッチ
+        mbc.write_reg(0x6000, 0x01);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0x05);
+        mbc.write_reg(0x6000, 0x00);
+        mbc.write_reg(0x6000, 0x01);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0x07);
+    }
+
+    #[test]
+    fn writes_are_masked() {
+        let mut rtc = Rtc::new();
+        rtc.write(RTC_S as u8, 0xFF);
+        rtc.write(RTC_DH as u8, 0xFF);
+        assert_eq!(rtc.read(RTC_S as u8), 0x3F);
+        assert_eq!(rtc.read(RTC_DH as u8), 0xC1);
+    }
+
+    #[test]
+    fn footer_round_trip() {
+        let mut rtc = Rtc::new();
+        rtc.regs = [10, 20, 3, 0x34, RTC_HALT | 0x01];
+        rtc.latched = [1, 2, 3, 4, 0];
+        let data = rtc.save();
+        assert_eq!(data.len(), RTC_FOOTER_SIZE);
+
+        let mut loaded = Rtc::new();
+        loaded.load(&data);
+        assert_eq!(loaded.regs, rtc.regs);
+        assert_eq!(loaded.latched, rtc.latched);
+        assert_eq!(loaded.last_time, rtc.last_time);
+
+        // 古いVBA形式 (UNIX時間がu32)
+        let mut old = data[..40].to_vec();
+        old.extend_from_slice(&(rtc.last_time as u32).to_le_bytes());
+        let mut loaded = Rtc::new();
+        loaded.load(&old);
+        assert_eq!(loaded.regs, rtc.regs);
+
+        // 知らないサイズは読まない
+        let mut loaded = Rtc::new();
+        loaded.load(&data[..20]);
+        assert_eq!(loaded.regs, [0; 5]);
+    }
+
+    #[test]
+    fn footer_advances_elapsed_time() {
+        let mut rtc = Rtc::new();
+        let mut data = rtc.save();
+        let last_time = rtc.last_time - 120;
+        data[40..48].copy_from_slice(&last_time.to_le_bytes());
+
+        rtc.load(&data);
+        assert_eq!(rtc.regs[RTC_M], 2);
+    }
+}
@@ -0,0 +1,140 @@
+use serde::{Deserialize, Serialize};
+use mbc::*;
+
+// MBC5 ($19~$1E)
+// https://gbdev.io/pandocs/MBC5.html
+// $0000-$1FFF: RAM Enable ($0A)
+// $2000-$2FFF: ROMバンク番号 下位8bit (0もそのまま使える)
+// $3000-$3FFF: ROMバンク番号 Bit8
+// $4000-$5FFF: RAMバンク番号 (4bit)
+//              MBC5+RUMBLE ($1C~$1E)はBit3が振動モーター、RAMバンク番号は3bit
+#[derive(Serialize, Deserialize)]
+pub struct Mbc5 {
+    ram_enable: bool,
+    rom_bank: u16,
+    ram_bank: u8,
+    has_rumble: bool,
+    rumble: bool,
+}
+
+impl Mbc5 {
+    pub fn new(has_rumble: bool) -> Self {
+        Mbc5 {
+            ram_enable: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            has_rumble,
+            rumble: false,
+        }
+    }
+}
+
+impl Mbc for Mbc5 {
+    fn read_rom(&self, rom: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => read_rom_bank(rom, 0, addr),
+            _ => read_rom_bank(rom, self.rom_bank as usize, addr),
+        }
+    }
+
+    fn write_reg(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enable = val & 0x0F == 0x0A,
+            0x2000..=0x2FFF => self.rom_bank = (self.rom_bank & 0x100) | val as u16,
+            0x3000..=0x3FFF => self.rom_bank = (self.rom_bank & 0xFF) | ((val & 0x01) as u16) << 8,
+            0x4000..=0x5FFF => {
+                if self.has_rumble {
+                    self.rumble = val & 0x08 != 0;
+                    self.ram_bank = val & 0x07;
+                } else {
+                    self.ram_bank = val & 0x0F;
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn read_ram(&mut self, ram: &[u8], addr: u16) -> u8 {
+        if !self.ram_enable {
+            return 0xFF;
+        }
+        match ram_index(ram, self.ram_bank as usize, addr) {
+            Some(i) => ram[i],
+            None => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, val: u8) -> bool {
+        if !self.ram_enable {
+            return false;
+        }
+        let i = ram_index(ram, self.ram_bank as usize, addr);
+        write_byte(ram, i, val)
+    }
+
+    fn rumble(&self) -> bool {
+        self.rumble
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mbc::tests::{bank_at, banked_rom};
+
+    #[test]
+    fn rom_bank_is_9bit() {
+        let rom = banked_rom(512);
+        let mut mbc = Mbc5::new(false);
+        assert_eq!(bank_at(&mbc, &rom, 0x4000), 1);
+
+        // バンク0もそのまま選べる
+        mbc.write_reg(0x2000, 0x00);
+        assert_eq!(bank_at(&mbc, &rom, 0x4000), 0);
+        mbc.write_reg(0x2000, 0x34);
+        mbc.write_reg(0x3000, 0x01);
+        assert_eq!(bank_at(&mbc, &rom, 0x4000), 0x134);
+        mbc.write_reg(0x3000, 0x00);
+        assert_eq!(bank_at(&mbc, &rom, 0x4000), 0x34);
+        assert_eq!(bank_at(&mbc, &rom, 0x0000), 0);
+    }
+
+    #[test]
+    fn ram_bank_is_4bit() {
+        let mut ram = vec![0; 128 * 1024];
+        let mut mbc = Mbc5::new(false);
+        mbc.write_reg(0x0000, 0x0A);
+
+        mbc.write_reg(0x4000, 0x0F);
+        assert!(mbc.write_ram(&mut ram, 0xA000, 0x55));
+        assert_eq!(ram[15 * RAM_BANK_SIZE], 0x55);
+        mbc.write_reg(0x4000, 0x1F);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0x55);
+
+        mbc.write_reg(0x0000, 0x00);
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0xFF);
+    }
+
+    #[test]
+    fn rumble_bit_is_masked_from_ram_bank() {
+        let mut ram = vec![0; 32 * 1024];
+        let mut mbc = Mbc5::new(true);
+        mbc.write_reg(0x0000, 0x0A);
+        assert!(!mbc.rumble());
+
+        // Bit3は振動モーター、RAMバンク番号は3bit
+        mbc.write_reg(0x4000, 0x0B);
+        assert!(mbc.rumble());
+        assert!(mbc.write_ram(&mut ram, 0xA000, 0x66));
+        assert_eq!(ram[3 * RAM_BANK_SIZE], 0x66);
+
+        mbc.write_reg(0x4000, 0x03);
+        assert!(!mbc.rumble());
+        assert_eq!(mbc.read_ram(&ram, 0xA000), 0x66);
+
+        // 振動モーターがなければBit3もRAMバンク番号
+        let mut mbc = Mbc5::new(false);
+        mbc.write_reg(0x4000, 0x08);
+        assert!(!mbc.rumble());
+    }
+}
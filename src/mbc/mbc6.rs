@@ -0,0 +1,245 @@
+use std::mem;
+use serde::{Deserialize, Serialize};
+use mbc::*;
+
+// MBC6 ($20) ... 8KB単位のROM/フラッシュと4KB単位のRAMを2つずつ切り替える (ネットでゲット)
+// https://gbdev.io/pandocs/MBC6.html
+// $0000-$03FF: RAM Enable ($0A)
+// $0400-$07FF: RAMバンクA ($A000-$AFFF)    $0800-$0BFF: RAMバンクB ($B000-$BFFF)
+// $0C00-$0FFF: フラッシュ Enable (Bit0)     $1000: フラッシュ書き込み Enable (Bit0)
+// $2000-$27FF: ROM/フラッシュのバンクA ($4000-$5FFF)    $2800-$2FFF: バンクAの選択 ($00 = ROM, $08 = フラッシュ)
+// $3000-$37FF: ROM/フラッシュのバンクB ($6000-$7FFF)    $3800-$3FFF: バンクBの選択
+// フラッシュ(1MB)はセーブファイルのRAMの後ろに保存する
+pub const MBC6_FLASH_SIZE: usize = 1024 * 1024;
+const HALF_BANK_SIZE: usize = 8 * 1024;
+const RAM_HALF_BANK_SIZE: usize = 4 * 1024;
+
+// フラッシュのID (Macronix MX29F008)
+const FLASH_MANUFACTURER_ID: u8 = 0xC2;
+const FLASH_DEVICE_ID: u8 = 0x81;
+// セクター消去の単位
+const FLASH_SECTOR_SIZE: usize = HALF_BANK_SIZE;
+
+#[derive(Serialize, Deserialize)]
+pub struct Mbc6 {
+    ram_enable: bool,
+    ram_bank: [u8; 2],
+    flash_enable: bool,
+    flash_write_enable: bool,
+    rom_bank: [u8; 2],
+    flash_select: [bool; 2],
+    flash: Flash,
+}
+
+impl Default for Mbc6 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mbc6 {
+    pub fn new() -> Self {
+        Mbc6 {
+            ram_enable: false,
+            ram_bank: [0; 2],
+            flash_enable: false,
+            flash_write_enable: false,
+            rom_bank: [0; 2],
+            flash_select: [false; 2],
+            flash: Flash::new(),
+        }
+    }
+
+    // $4000-$5FFF = A, $6000-$7FFF = B
+    fn half(addr: u16) -> usize {
+        ((addr >> 13) & 0x01) as usize
+    }
+
+    fn flash_addr(&self, half: usize, addr: u16) -> usize {
+        (self.rom_bank[half] as usize * HALF_BANK_SIZE + (addr & 0x1FFF) as usize) % MBC6_FLASH_SIZE
+    }
+
+    // Moves the flash data from another MBC6 (used after loading a save state).
+    // フラッシュはセーブステートに含めず、セーブファイルと同じく読み込み前の内容を使う
+    pub fn take_flash(&mut self, other: &mut Mbc6) {
+        self.flash.data = mem::take(&mut other.flash.data);
+        self.flash.modified = other.flash.modified;
+    }
+
+    fn ram_index(&self, ram: &[u8], addr: u16) -> Option<usize> {
+        if ram.is_empty() {
+            return None;
+        }
+        let half = ((addr >> 12) & 0x01) as usize;
+        Some((self.ram_bank[half] as usize * RAM_HALF_BANK_SIZE + (addr & 0x0FFF) as usize) % ram.len())
+    }
+}
+
+impl Mbc for Mbc6 {
+    fn read_rom(&self, rom: &[u8], addr: u16) -> u8 {
+        if addr < 0x4000 {
+            return read_rom_bank(rom, 0, addr);
+        }
+
+        let half = Self::half(addr);
+        if self.flash_select[half] {
+            self.flash.read(self.flash_addr(half, addr))
+        } else {
+            rom[(self.rom_bank[half] as usize * HALF_BANK_SIZE + (addr & 0x1FFF) as usize) & (rom.len() - 1)]
+        }
+    }
+
+    fn write_reg(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x03FF => self.ram_enable = val & 0x0F == 0x0A,
+            0x0400..=0x07FF => self.ram_bank[0] = val & 0x07,
+            0x0800..=0x0BFF => self.ram_bank[1] = val & 0x07,
+            0x0C00..=0x0FFF => self.flash_enable = val & 0x01 != 0,
+            0x1000 => self.flash_write_enable = val & 0x01 != 0,
+            0x2000..=0x27FF => self.rom_bank[0] = val & 0x7F,
+            0x2800..=0x2FFF => self.flash_select[0] = val == 0x08,
+            0x3000..=0x37FF => self.rom_bank[1] = val & 0x7F,
+            0x3800..=0x3FFF => self.flash_select[1] = val == 0x08,
+            0x4000..=0x7FFF => {
+                let half = Self::half(addr);
+                if self.flash_select[half] && self.flash_enable {
+                    let flash_addr = self.flash_addr(half, addr);
+                    self.flash.write(flash_addr, val, self.flash_write_enable);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn read_ram(&mut self, ram: &[u8], addr: u16) -> u8 {
+        if !self.ram_enable {
+            return 0xFF;
+        }
+
+        match self.ram_index(ram, addr) {
+            Some(i) => ram[i],
+            None => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, val: u8) -> bool {
+        if !self.ram_enable {
+            return false;
+        }
+
+        let i = self.ram_index(ram, addr);
+        write_byte(ram, i, val)
+    }
+
+    fn save_extra(&mut self) -> Vec<u8> {
+        self.flash.data.clone()
+    }
+
+    fn extra_size(&self) -> usize {
+        self.flash.data.len()
+    }
+
+    fn save_modified(&mut self) -> bool {
+        mem::replace(&mut self.flash.modified, false)
+    }
+
+    fn load_extra(&mut self, data: &[u8]) {
+        if data.len() == MBC6_FLASH_SIZE {
+            self.flash.data.copy_from_slice(data);
+        } else if !data.is_empty() {
+            warn!("Unknown flash size ({} bytes)", data.len());
+        }
+    }
+}
+
+// [フラッシュメモリ]
+// $5555 ← $AA, $2AAA ← $55 の後にコマンドを書く (アドレスはフラッシュ内の下位15bit)
+//   $A0: 次に書いた1バイトを書き込む (1→0にしかできない)
+//   $80: 消去 ($AA, $55 の後に $30 = 8KBのセクター消去, $10 = 全消去)
+//   $90: ID読み出し ($00 = メーカーID, $01 = デバイスID)
+//   $F0: リセット (読み出しに戻る)
+// 書き込み/消去はすぐに終わる
+#[derive(Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+enum FlashState {
+    Read,
+    Unlock1,
+    Unlock2,
+    Program,
+    EraseSetup,
+    EraseUnlock1,
+    EraseUnlock2,
+    Id,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Flash {
+    #[serde(skip)]
+    data: Vec<u8>,      // セーブステートに含めない (1MB)
+    state: FlashState,
+    #[serde(skip)]
+    modified: bool,     // 前回のセーブから書き換えられた
+}
+
+impl Flash {
+    fn new() -> Self {
+        Flash {
+            data: vec![0xFF; MBC6_FLASH_SIZE],
+            state: FlashState::Read,
+            modified: false,
+        }
+    }
+
+    fn read(&self, addr: usize) -> u8 {
+        match self.state {
+            FlashState::Id => match addr & 0xFF {
+                0x00 => FLASH_MANUFACTURER_ID,
+                0x01 => FLASH_DEVICE_ID,
+                _ => 0x00,
+            },
+            _ => self.data[addr],
+        }
+    }
+
+    fn write(&mut self, addr: usize, val: u8, write_enable: bool) {
+        let cmd_addr = addr & 0x7FFF;
+
+        if val == 0xF0 {
+            self.state = FlashState::Read;
+            return;
+        }
+
+        self.state = match (self.state, cmd_addr, val) {
+            (FlashState::Read, 0x5555, 0xAA) | (FlashState::Id, 0x5555, 0xAA) => FlashState::Unlock1,
+            (FlashState::Unlock1, 0x2AAA, 0x55) => FlashState::Unlock2,
+            (FlashState::Unlock2, 0x5555, 0xA0) => FlashState::Program,
+            (FlashState::Unlock2, 0x5555, 0x80) => FlashState::EraseSetup,
+            (FlashState::Unlock2, 0x5555, 0x90) => FlashState::Id,
+            (FlashState::Program, _, _) => {
+                if write_enable {
+                    self.data[addr] &= val;
+                    self.modified = true;
+                }
+                FlashState::Read
+            }
+            (FlashState::EraseSetup, 0x5555, 0xAA) => FlashState::EraseUnlock1,
+            (FlashState::EraseUnlock1, 0x2AAA, 0x55) => FlashState::EraseUnlock2,
+            (FlashState::EraseUnlock2, _, 0x30) => {
+                if write_enable {
+                    let start = addr / FLASH_SECTOR_SIZE * FLASH_SECTOR_SIZE;
+                    self.data[start..start + FLASH_SECTOR_SIZE].iter_mut().for_each(|b| *b = 0xFF);
+                    self.modified = true;
+                }
+                FlashState::Read
+            }
+            (FlashState::EraseUnlock2, 0x5555, 0x10) => {
+                if write_enable {
+                    self.data.iter_mut().for_each(|b| *b = 0xFF);
+                    self.modified = true;
+                }
+                FlashState::Read
+            }
+            (FlashState::Id, _, _) => FlashState::Id,
+            _ => FlashState::Read,
+        };
+    }
+}
@@ -0,0 +1,424 @@
+use std::mem;
+use serde::{Deserialize, Serialize};
+use mbc::*;
+
+// MBC7 ($22) ... 加速度センサー + EEPROM (コロコロカービィなど)
+// https://gbdev.io/pandocs/MBC7.html
+// $0000-$1FFF: RAM Enable 1 ($0A)
+// $2000-$3FFF: ROMバンク番号 (7bit)
+// $4000-$5FFF: RAM Enable 2 ($40)
+// $A000-$AFFF: レジスタ (アドレスのBit[7:4]で選択、両方のRAM Enableが必要)
+//   $Ax0x: $55を書くとラッチを消去    $Ax1x: $AAを書くと加速度をラッチ
+//   $Ax2x: X 下位    $Ax3x: X 上位    $Ax4x: Y 下位    $Ax5x: Y 上位
+//   $Ax6x: $00       $Ax7x: $FF       $Ax8x: EEPROM     $Ax9x~: $FF
+// $B000-$BFFF: $FF
+pub const MBC7_EEPROM_SIZE: usize = 256;
+
+// 加速度センサーの値 (水平で$81D0、1Gで約$70)
+const ACCEL_CENTER: f32 = 0x81D0 as f32;
+const ACCEL_1G: f32 = 0x70 as f32;
+const ACCEL_ERASED: u16 = 0x8000;
+
+#[derive(Serialize, Deserialize)]
+pub struct Mbc7 {
+    ram_enable1: bool,
+    ram_enable2: bool,
+    rom_bank: u8,
+    accel_x: u16,       // 現在の加速度 (入力APIから設定)
+    accel_y: u16,
+    latch_x: u16,       // ラッチした加速度
+    latch_y: u16,
+    latched: bool,
+    eeprom: Eeprom,
+}
+
+impl Default for Mbc7 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mbc7 {
+    pub fn new() -> Self {
+        Mbc7 {
+            ram_enable1: false,
+            ram_enable2: false,
+            rom_bank: 1,
+            accel_x: ACCEL_CENTER as u16,
+            accel_y: ACCEL_CENTER as u16,
+            latch_x: ACCEL_ERASED,
+            latch_y: ACCEL_ERASED,
+            latched: false,
+            eeprom: Eeprom::new(),
+        }
+    }
+
+    fn reg_enabled(&self, addr: u16) -> bool {
+        self.ram_enable1 && self.ram_enable2 && addr <= 0xAFFF
+    }
+}
+
+impl Mbc for Mbc7 {
+    fn read_rom(&self, rom: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => read_rom_bank(rom, 0, addr),
+            _ => read_rom_bank(rom, self.rom_bank as usize, addr),
+        }
+    }
+
+    fn write_reg(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.ram_enable1 = val & 0x0F == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank = val & 0x7F,
+            0x4000..=0x5FFF => self.ram_enable2 = val == 0x40,
+            _ => (),
+        }
+    }
+
+    fn read_ram(&mut self, _ram: &[u8], addr: u16) -> u8 {
+        if !self.reg_enabled(addr) {
+            return 0xFF;
+        }
+
+        match addr & 0x00F0 {
+            0x20 => self.latch_x as u8,
+            0x30 => (self.latch_x >> 8) as u8,
+            0x40 => self.latch_y as u8,
+            0x50 => (self.latch_y >> 8) as u8,
+            0x60 => 0x00,
+            0x80 => self.eeprom.read(),
+            _ => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, val: u8) -> bool {
+        if !self.reg_enabled(addr) {
+            return false;
+        }
+
+        match addr & 0x00F0 {
+            0x00 if val == 0x55 => {
+                self.latch_x = ACCEL_ERASED;
+                self.latch_y = ACCEL_ERASED;
+                self.latched = false;
+            }
+            0x10 if val == 0xAA && !self.latched => {
+                self.latch_x = self.accel_x;
+                self.latch_y = self.accel_y;
+                self.latched = true;
+            }
+            0x80 => return self.eeprom.write(ram, val),
+            _ => (),
+        }
+        false
+    }
+
+    fn set_accelerometer(&mut self, x: f32, y: f32) {
+        let to_reg = |g: f32| (ACCEL_CENTER + g * ACCEL_1G).clamp(0.0, 0xFFFF as f32) as u16;
+        self.accel_x = to_reg(x);
+        self.accel_y = to_reg(y);
+    }
+}
+
+// [93LC56 シリアルEEPROM (256Byte = 128ワード × 16bit)]
+// $Ax8x: Bit7 = CS, Bit6 = CLK, Bit1 = DI, Bit0 = DO (Read)
+// CS=1の間、CLKの立ち上がりでDIを1bitずつ読む
+// スタートビット(1)の後に10bitのコマンド
+//   10 xAAAAAAA : READ   (続けて16bitをDOに出力、最初にダミーの0)
+//   01 xAAAAAAA : WRITE  (続けて16bitのデータを受け取る)
+//   11 xAAAAAAA : ERASE
+//   00 11xxxxxx : EWEN (書き込み許可)   00 00xxxxxx : EWDS (書き込み禁止)
+//   00 10xxxxxx : ERAL (全消去)         00 01xxxxxx : WRAL (続けて16bitのデータで全書き込み)
+// データはワード単位でリトルエンディアン
+#[derive(Copy, Clone, Serialize, Deserialize)]
+enum EepromState {
+    Idle,
+    Command { bits: u8, val: u16 },
+    Read { bits: u8, val: u16 },
+    Write { addr: Option<u8>, bits: u8, val: u16 },   // addr: None = WRAL
+}
+
+#[derive(Serialize, Deserialize)]
+struct Eeprom {
+    cs: bool,
+    clk: bool,
+    di: bool,
+    dout: bool,
+    write_enable: bool,
+    state: EepromState,
+    #[serde(skip)]
+    modified: bool,         // EEPROMのデータが書き換えられた
+}
+
+impl Eeprom {
+    fn new() -> Self {
+        Eeprom {
+            cs: false,
+            clk: false,
+            di: false,
+            dout: true,
+            write_enable: false,
+            state: EepromState::Idle,
+            modified: false,
+        }
+    }
+
+    fn read(&self) -> u8 {
+        (self.cs as u8) << 7 | (self.clk as u8) << 6 | (self.di as u8) << 1 | self.dout as u8
+    }
+
+    // Returns true if the EEPROM data was changed.
+    fn write(&mut self, ram: &mut [u8], val: u8) -> bool {
+        let cs = val & 0x80 != 0;
+        let clk = val & 0x40 != 0;
+        self.di = val & 0x02 != 0;
+
+        if !cs {
+            self.state = EepromState::Idle;
+        } else if !self.clk && clk {
+            self.clock(ram);
+        }
+
+        self.cs = cs;
+        self.clk = clk;
+        mem::replace(&mut self.modified, false)
+    }
+
+    // CLKの立ち上がり
+    fn clock(&mut self, ram: &mut [u8]) {
+        let di = self.di as u16;
+
+        self.state = match self.state {
+            // スタートビット待ち
+            EepromState::Idle if self.di => EepromState::Command { bits: 0, val: 0 },
+            EepromState::Idle => EepromState::Idle,
+            EepromState::Command { bits, val } => {
+                let val = val << 1 | di;
+                if bits + 1 < 10 {
+                    EepromState::Command { bits: bits + 1, val }
+                } else {
+                    self.command(ram, val)
+                }
+            }
+            EepromState::Read { bits, val } => {
+                self.dout = val & 0x8000 != 0;
+                if bits > 1 {
+                    EepromState::Read { bits: bits - 1, val: val << 1 }
+                } else {
+                    EepromState::Idle
+                }
+            }
+            EepromState::Write { addr, bits, val } => {
+                let val = val << 1 | di;
+                if bits + 1 < 16 {
+                    EepromState::Write { addr, bits: bits + 1, val }
+                } else {
+                    if self.write_enable {
+                        match addr {
+                            Some(addr) => self.write_word(ram, addr, val),
+                            None => (0..0x80).for_each(|addr| self.write_word(ram, addr, val)),
+                        }
+                    }
+                    self.dout = true;   // Ready
+                    EepromState::Idle
+                }
+            }
+        };
+    }
+
+    fn command(&mut self, ram: &mut [u8], cmd: u16) -> EepromState {
+        let addr = (cmd & 0x7F) as u8;
+
+        match cmd >> 8 {
+            // READ
+            0b10 => {
+                self.dout = false;
+                EepromState::Read { bits: 16, val: read_word(ram, addr) }
+            }
+            // WRITE
+            0b01 => EepromState::Write { addr: Some(addr), bits: 0, val: 0 },
+            // ERASE
+            0b11 => {
+                if self.write_enable {
+                    self.write_word(ram, addr, 0xFFFF);
+                }
+                self.dout = true;
+                EepromState::Idle
+            }
+            _ => match (cmd >> 6) & 0x03 {
+                // EWEN
+                0b11 => {
+                    self.write_enable = true;
+                    EepromState::Idle
+                }
+                // EWDS
+                0b00 => {
+                    self.write_enable = false;
+                    EepromState::Idle
+                }
+                // ERAL
+                0b10 => {
+                    if self.write_enable {
+                        (0..0x80).for_each(|addr| self.write_word(ram, addr, 0xFFFF));
+                    }
+                    self.dout = true;
+                    EepromState::Idle
+                }
+                // WRAL
+                _ => EepromState::Write { addr: None, bits: 0, val: 0 },
+            },
+        }
+    }
+
+    fn write_word(&mut self, ram: &mut [u8], addr: u8, val: u16) {
+        let i = (addr as usize * 2) % MBC7_EEPROM_SIZE;
+        if let Some(word) = ram.get_mut(i..i + 2) {
+            if word != val.to_le_bytes() {
+                word.copy_from_slice(&val.to_le_bytes());
+                self.modified = true;
+            }
+        }
+    }
+}
+
+fn read_word(ram: &[u8], addr: u8) -> u16 {
+    let i = (addr as usize * 2) % MBC7_EEPROM_SIZE;
+    match ram.get(i..i + 2) {
+        Some(word) => u16::from_le_bytes([word[0], word[1]]),
+        None => 0xFFFF,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EEPROM: u16 = 0xA080;
+
+    fn enabled() -> Mbc7 {
+        let mut mbc = Mbc7::new();
+        mbc.write_reg(0x0000, 0x0A);
+        mbc.write_reg(0x4000, 0x40);
+        mbc
+    }
+
+    // CLKを1回立ち上げてDOを返す。EEPROMが書き換えられたらtrue
+    fn clock(mbc: &mut Mbc7, ram: &mut [u8], di: bool, modified: &mut bool) -> bool {
+        let di = (di as u8) << 1;
+        *modified |= mbc.write_ram(ram, EEPROM, 0x80 | di);
+        *modified |= mbc.write_ram(ram, EEPROM, 0xC0 | di);
+        mbc.read_ram(ram, EEPROM) & 0x01 != 0
+    }
+
+    // スタートビット + コマンド(10bit) + データ(16bit) を送り、書き換えられたらtrue
+    fn send(mbc: &mut Mbc7, ram: &mut [u8], cmd: u16, data: Option<u16>) -> bool {
+        let mut modified = false;
+        mbc.write_ram(ram, EEPROM, 0x00);
+        clock(mbc, ram, true, &mut modified);
+        for i in (0..10).rev() {
+            clock(mbc, ram, cmd >> i & 0x01 != 0, &mut modified);
+        }
+        if let Some(data) = data {
+            for i in (0..16).rev() {
+                clock(mbc, ram, data >> i & 0x01 != 0, &mut modified);
+            }
+        }
+        modified
+    }
+
+    fn read_word(mbc: &mut Mbc7, ram: &mut [u8], addr: u16) -> u16 {
+        let mut modified = false;
+        send(mbc, ram, 0b10 << 8 | addr, None);
+        // 最初にダミーの0
+        assert_eq!(mbc.read_ram(ram, EEPROM) & 0x01, 0);
+        (0..16).fold(0, |val, _| val << 1 | clock(mbc, ram, false, &mut modified) as u16)
+    }
+
+    const EWEN: u16 = 0b00_1100_0000;
+    const EWDS: u16 = 0b00_0000_0000;
+    const ERAL: u16 = 0b00_1000_0000;
+    const WRAL: u16 = 0b00_0100_0000;
+
+    fn write(addr: u16) -> u16 {
+        0b01 << 8 | addr
+    }
+
+    #[test]
+    fn eeprom_write_and_read() {
+        let mut mbc = enabled();
+        let mut ram = vec![0xFF; MBC7_EEPROM_SIZE];
+
+        // 書き込み禁止の間は書き込めない
+        assert!(!send(&mut mbc, &mut ram, write(5), Some(0x1234)));
+        assert!(ram.iter().all(|&b| b == 0xFF));
+
+        send(&mut mbc, &mut ram, EWEN, None);
+        assert!(send(&mut mbc, &mut ram, write(5), Some(0x1234)));
+        assert_eq!(ram[10..12], [0x34, 0x12]);
+        assert_eq!(read_word(&mut mbc, &mut ram, 5), 0x1234);
+        assert_eq!(read_word(&mut mbc, &mut ram, 6), 0xFFFF);
+
+        // 同じ値の書き込みは変更なし
+        assert!(!send(&mut mbc, &mut ram, write(5), Some(0x1234)));
+
+        send(&mut mbc, &mut ram, EWDS, None);
+        assert!(!send(&mut mbc, &mut ram, write(5), Some(0x0000)));
+        assert_eq!(read_word(&mut mbc, &mut ram, 5), 0x1234);
+    }
+
+    #[test]
+    fn eeprom_erase_and_write_all() {
+        let mut mbc = enabled();
+        let mut ram = vec![0x00; MBC7_EEPROM_SIZE];
+
+        send(&mut mbc, &mut ram, EWEN, None);
+        assert!(send(&mut mbc, &mut ram, 0b11 << 8 | 0x7F, None));
+        assert_eq!(ram[0xFE..], [0xFF, 0xFF]);
+        assert!(ram[..0xFE].iter().all(|&b| b == 0x00));
+
+        assert!(send(&mut mbc, &mut ram, ERAL, None));
+        assert!(ram.iter().all(|&b| b == 0xFF));
+
+        assert!(send(&mut mbc, &mut ram, WRAL, Some(0xA55A)));
+        assert!(ram.chunks(2).all(|w| w == [0x5A, 0xA5]));
+    }
+
+    #[test]
+    fn accelerometer_latch() {
+        let mut mbc = enabled();
+        let mut ram = vec![];
+        let read16 = |mbc: &mut Mbc7, lo: u16| {
+            mbc.read_ram(&[], lo) as u16 | (mbc.read_ram(&[], lo + 0x10) as u16) << 8
+        };
+
+        mbc.set_accelerometer(1.0, -0.5);
+        assert_eq!(read16(&mut mbc, 0xA020), ACCEL_ERASED);
+
+        mbc.write_ram(&mut ram, 0xA000, 0x55);
+        mbc.write_ram(&mut ram, 0xA010, 0xAA);
+        assert_eq!(read16(&mut mbc, 0xA020), 0x81D0 + 0x70);
+        assert_eq!(read16(&mut mbc, 0xA040), 0x81D0 - 0x38);
+
+        // 消去するまで再ラッチしない
+        mbc.set_accelerometer(0.0, 0.0);
+        mbc.write_ram(&mut ram, 0xA010, 0xAA);
+        assert_eq!(read16(&mut mbc, 0xA020), 0x81D0 + 0x70);
+
+        mbc.write_ram(&mut ram, 0xA000, 0x55);
+        assert_eq!(read16(&mut mbc, 0xA020), ACCEL_ERASED);
+        mbc.write_ram(&mut ram, 0xA010, 0xAA);
+        assert_eq!(read16(&mut mbc, 0xA020), 0x81D0);
+    }
+
+    #[test]
+    fn registers_need_both_enables() {
+        let mut mbc = Mbc7::new();
+        mbc.write_reg(0x0000, 0x0A);
+        assert_eq!(mbc.read_ram(&[], 0xA060), 0xFF);
+        mbc.write_reg(0x4000, 0x40);
+        assert_eq!(mbc.read_ram(&[], 0xA060), 0x00);
+        // $B000-$BFFF は常に$FF
+        assert_eq!(mbc.read_ram(&[], 0xB060), 0xFF);
+    }
+}
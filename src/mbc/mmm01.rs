@@ -0,0 +1,160 @@
+use serde::{Deserialize, Serialize};
+use cartridge::NINTENDO_LOGO;
+use mbc::*;
+
+// MMM01 ($0B~$0D) ... マルチカート用のMBC (百貨店コレクションなど)
+// https://gbdev.io/pandocs/MMM01.html
+// 起動時はROMの最後の32KB(メニュー)が$0000-$7FFFに見えていて、メニューがゲームのバンクを設定してからロックする
+// ロック後はMBC1として動き、メニューが固定(マスク)したbitは書き換えられない
+// $0000-$1FFF: Bit0-3 = RAM Enable ($0A), Bit4-5 = RAMバンクのマスク, Bit6 = ロック
+// $2000-$3FFF: Bit0-4 = ROMバンク, Bit5-6 = ROMバンク中位 (ロック前のみ)
+// $4000-$5FFF: Bit0-1 = RAMバンク, Bit2-3 = RAMバンク上位, Bit4-5 = ROMバンク上位, Bit6 = モード書き込み禁止 (Bit2-6はロック前のみ)
+// $6000-$7FFF: Bit0 = MBC1のモード, Bit2-5 = ROMバンク(Bit1-4)のマスク (ロック前のみ)
+#[derive(Serialize, Deserialize)]
+pub struct Mmm01 {
+    menu_bank: usize,       // メニューの先頭バンク
+    locked: bool,
+    ram_enable: bool,
+    rom_bank: u8,           // Bit0-4
+    rom_bank_mid: u8,       // Bit5-6
+    rom_bank_high: u8,      // Bit7-8
+    rom_mask: u8,           // ロック後に書き換えられないROMバンクのbit (Bit1-4)
+    ram_bank: u8,           // Bit0-1
+    ram_bank_high: u8,      // Bit2-3
+    ram_mask: u8,           // ロック後に書き換えられないRAMバンクのbit
+    mode: bool,
+    mode_lock: bool,
+}
+
+// Returns the offset of the cartridge header. (ROMの最後の32KBにMMM01のヘッダーがあればそこ)
+pub fn header_offset(rom: &[u8]) -> usize {
+    if rom.len() < 0x10000 || is_mmm01(rom, 0) {
+        return 0;
+    }
+
+    let base = rom.len() - 0x8000;
+    if is_mmm01(rom, base) {
+        info!("MMM01 multicart (header in the last bank)");
+        base
+    } else {
+        0
+    }
+}
+
+fn is_mmm01(rom: &[u8], base: usize) -> bool {
+    rom[base + 0x0104..base + 0x0134] == NINTENDO_LOGO && (0x0B..=0x0D).contains(&rom[base + 0x0147])
+}
+
+impl Mmm01 {
+    pub fn new(rom: &[u8]) -> Self {
+        Mmm01 {
+            menu_bank: header_offset(rom) / ROM_BANK_SIZE,
+            locked: false,
+            ram_enable: false,
+            rom_bank: 0,
+            rom_bank_mid: 0,
+            rom_bank_high: 0,
+            rom_mask: 0,
+            ram_bank: 0,
+            ram_bank_high: 0,
+            ram_mask: 0,
+            mode: false,
+            mode_lock: false,
+        }
+    }
+
+    // ロック後に書けるbitだけを書き換える
+    fn masked(old: u8, val: u8, mask: u8) -> u8 {
+        (old & mask) | (val & !mask)
+    }
+
+    fn outer_bank(&self) -> usize {
+        (self.rom_bank_high as usize) << 7 | (self.rom_bank_mid as usize) << 5
+    }
+
+    // MBC1のモード0ではRAMバンクの下位2bitは使わない (メニューが固定したbitを除く)
+    fn ram_bank_index(&self) -> usize {
+        let low = if self.mode { self.ram_bank } else { self.ram_bank & self.ram_mask };
+        (self.ram_bank_high as usize) << 2 | low as usize
+    }
+}
+
+impl Mbc for Mmm01 {
+    fn read_rom(&self, rom: &[u8], addr: u16) -> u8 {
+        if !self.locked {
+            let bank = self.menu_bank + (addr >= 0x4000) as usize;
+            return read_rom_bank(rom, bank, addr);
+        }
+
+        let bank = match addr {
+            // メニューが固定したbitはそのまま、ゲームが選ぶbitは0
+            0x0000..=0x3FFF => self.outer_bank() | (self.rom_bank & self.rom_mask) as usize,
+            _ => {
+                let mut bank = self.rom_bank;
+                if bank & !self.rom_mask & 0x1F == 0 {
+                    bank |= 0x01;
+                }
+                self.outer_bank() | bank as usize
+            }
+        };
+        read_rom_bank(rom, bank, addr)
+    }
+
+    fn write_reg(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => {
+                self.ram_enable = val & 0x0F == 0x0A;
+                if !self.locked {
+                    self.ram_mask = (val >> 4) & 0x03;
+                    self.locked = val & 0x40 != 0;
+                }
+            }
+            0x2000..=0x3FFF => {
+                if self.locked {
+                    self.rom_bank = Self::masked(self.rom_bank, val & 0x1F, self.rom_mask);
+                } else {
+                    self.rom_bank = val & 0x1F;
+                    self.rom_bank_mid = (val >> 5) & 0x03;
+                }
+            }
+            0x4000..=0x5FFF => {
+                if self.locked {
+                    self.ram_bank = Self::masked(self.ram_bank, val & 0x03, self.ram_mask);
+                } else {
+                    self.ram_bank = val & 0x03;
+                    self.ram_bank_high = (val >> 2) & 0x03;
+                    self.rom_bank_high = (val >> 4) & 0x03;
+                    self.mode_lock = val & 0x40 != 0;
+                }
+            }
+            _ => {
+                if !self.mode_lock {
+                    self.mode = val & 0x01 != 0;
+                }
+                if !self.locked {
+                    self.rom_mask = (val >> 1) & 0x1E;
+                }
+            }
+        }
+    }
+
+    fn read_ram(&mut self, ram: &[u8], addr: u16) -> u8 {
+        if !self.ram_enable {
+            return 0xFF;
+        }
+
+        match ram_index(ram, self.ram_bank_index(), addr) {
+            Some(i) => ram[i],
+            None => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, val: u8) -> bool {
+        if !self.ram_enable {
+            return false;
+        }
+
+        let i = ram_index(ram, self.ram_bank_index(), addr);
+        write_byte(ram, i, val)
+    }
+}
@@ -0,0 +1,231 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Deserialize, Serialize};
+
+pub mod rom_only;
+pub mod mbc1;
+pub mod mmm01;
+pub mod mbc2;
+pub mod mbc3;
+pub mod mbc5;
+pub mod mbc6;
+pub mod mbc7;
+pub mod camera;
+pub mod huc1;
+pub mod huc3;
+pub mod tama5;
+
+pub use self::rom_only::RomOnly;
+pub use self::mbc1::Mbc1;
+pub use self::mmm01::Mmm01;
+pub use self::mbc2::Mbc2;
+pub use self::mbc3::Mbc3;
+pub use self::mbc5::Mbc5;
+pub use self::mbc6::Mbc6;
+pub use self::mbc7::Mbc7;
+pub use self::camera::PocketCamera;
+pub use self::huc1::HuC1;
+pub use self::huc3::HuC3;
+pub use self::tama5::Tama5;
+
+pub const ROM_BANK_SIZE: usize = 16 * 1024;
+pub const RAM_BANK_SIZE: usize = 8 * 1024;
+
+// [MBC (Memory Bank Controller)]
+// https://gbdev.io/pandocs/MBCs.html
+// ROM/RAMのデータはCartridgeが持ち、MBCはバンク切り替えのレジスタだけを持つ
+pub trait Mbc {
+    // Reads ROM ($0000-$7FFF).
+    fn read_rom(&self, rom: &[u8], addr: u16) -> u8;
+    // Writes MBC registers ($0000-$7FFF).
+    fn write_reg(&mut self, addr: u16, val: u8);
+    // Reads external RAM ($A000-$BFFF).
+    fn read_ram(&mut self, ram: &[u8], addr: u16) -> u8;
+    // Writes external RAM ($A000-$BFFF).
+    // Returns true if the RAM data was changed. (レジスタへの書き込みや同じ値の書き込みはfalse)
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, val: u8) -> bool;
+
+    fn update(&mut self, _tick: u8) {
+        // NOP
+    }
+
+    // Returns extra data appended to the save file after RAM. (RTC etc.)
+    fn save_extra(&mut self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    // Returns the size of save_extra() without building it. (RTCの同期などの副作用なし)
+    fn extra_size(&self) -> usize {
+        0
+    }
+
+    // Restores extra data read from the save file after RAM.
+    fn load_extra(&mut self, _data: &[u8]) {
+        // NOP
+    }
+
+    // Returns true (once) if the save data kept by the mapper itself (save_extra) was modified.
+    fn save_modified(&mut self) -> bool {
+        false
+    }
+
+    // Returns true if the rumble motor is on.
+    fn rumble(&self) -> bool {
+        false
+    }
+
+    // Sets the accelerometer input in G. (x: +右, y: +下)
+    fn set_accelerometer(&mut self, _x: f32, _y: f32) {
+        // NOP
+    }
+
+    // Sets the image seen by the camera sensor. (CAMERA_W × CAMERA_H, grayscale)
+    fn set_camera_image(&mut self, _image: &[u8]) {
+        // NOP
+    }
+
+    // Returns true if the IR LED is on. (HuC1/HuC3)
+    fn ir_led(&self) -> bool {
+        false
+    }
+
+    // Sets whether the IR receiver sees light. (HuC1/HuC3)
+    fn set_ir_input(&mut self, _light: bool) {
+        // NOP
+    }
+}
+
+// カートリッジタイプ($0147)毎のMBC
+#[derive(Serialize, Deserialize)]
+pub enum Mapper {
+    RomOnly(RomOnly),
+    Mbc1(Mbc1),
+    Mmm01(Mmm01),
+    Mbc2(Mbc2),
+    Mbc3(Mbc3),
+    Mbc5(Mbc5),
+    Mbc6(Mbc6),
+    Mbc7(Mbc7),
+    PocketCamera(PocketCamera),
+    HuC1(HuC1),
+    HuC3(HuC3),
+    Tama5(Tama5),
+}
+
+impl Mapper {
+    // Selects the MBC from the cartridge type ($0147).
+    pub fn new(mbc_type: u8, rom: &[u8]) -> Self {
+        match mbc_type {
+            0x00 | 0x08 | 0x09 => Mapper::RomOnly(RomOnly::new()),
+            0x01..=0x03 => Mapper::Mbc1(Mbc1::new(rom)),
+            0x05 | 0x06 => Mapper::Mbc2(Mbc2::new()),
+            0x0B..=0x0D => Mapper::Mmm01(Mmm01::new(rom)),
+            0x0F..=0x13 => Mapper::Mbc3(Mbc3::new(mbc_type == 0x0F || mbc_type == 0x10)),
+            0x19..=0x1E => Mapper::Mbc5(Mbc5::new(mbc_type >= 0x1C)),
+            0x20 => Mapper::Mbc6(Mbc6::new()),
+            0x22 => Mapper::Mbc7(Mbc7::new()),
+            0xFC => Mapper::PocketCamera(PocketCamera::new()),
+            0xFD => Mapper::Tama5(Tama5::new()),
+            0xFE => Mapper::HuC3(HuC3::new()),
+            0xFF => Mapper::HuC1(HuC1::new()),
+            _ => {
+                // 未対応のMBCはMBC1として扱う
+                warn!("Cartridge type ${:02X} is not supported (using MBC1)", mbc_type);
+                Mapper::Mbc1(Mbc1::new(rom))
+            }
+        }
+    }
+
+    // Moves data that is not part of save states from the old mapper. (MBC6のフラッシュ)
+    pub fn take_extra(&mut self, old: &mut Mapper) {
+        if let (Mapper::Mbc6(mbc), Mapper::Mbc6(old)) = (self, old) {
+            mbc.take_flash(old);
+        }
+    }
+
+    pub fn mbc(&self) -> &dyn Mbc {
+        match self {
+            Mapper::RomOnly(mbc) => mbc,
+            Mapper::Mbc1(mbc) => mbc,
+            Mapper::Mmm01(mbc) => mbc,
+            Mapper::Mbc2(mbc) => mbc,
+            Mapper::Mbc3(mbc) => mbc,
+            Mapper::Mbc5(mbc) => mbc,
+            Mapper::Mbc6(mbc) => mbc,
+            Mapper::Mbc7(mbc) => mbc,
+            Mapper::PocketCamera(mbc) => mbc,
+            Mapper::HuC1(mbc) => mbc,
+            Mapper::HuC3(mbc) => mbc,
+            Mapper::Tama5(mbc) => mbc,
+        }
+    }
+
+    pub fn mbc_mut(&mut self) -> &mut dyn Mbc {
+        match self {
+            Mapper::RomOnly(mbc) => mbc,
+            Mapper::Mbc1(mbc) => mbc,
+            Mapper::Mmm01(mbc) => mbc,
+            Mapper::Mbc2(mbc) => mbc,
+            Mapper::Mbc3(mbc) => mbc,
+            Mapper::Mbc5(mbc) => mbc,
+            Mapper::Mbc6(mbc) => mbc,
+            Mapper::Mbc7(mbc) => mbc,
+            Mapper::PocketCamera(mbc) => mbc,
+            Mapper::HuC1(mbc) => mbc,
+            Mapper::HuC3(mbc) => mbc,
+            Mapper::Tama5(mbc) => mbc,
+        }
+    }
+}
+
+// Reads ROM in the given bank. (バンク番号はROMサイズでマスクする)
+pub fn read_rom_bank(rom: &[u8], bank: usize, addr: u16) -> u8 {
+    rom[(bank * ROM_BANK_SIZE + (addr & 0x3FFF) as usize) & (rom.len() - 1)]
+}
+
+// Returns RAM index in the given bank. (RAMが無ければNone)
+pub fn ram_index(ram: &[u8], bank: usize, addr: u16) -> Option<usize> {
+    if ram.is_empty() {
+        None
+    } else {
+        Some((bank * RAM_BANK_SIZE + (addr & 0x1FFF) as usize) & (ram.len() - 1))
+    }
+}
+
+// Writes a byte to RAM and returns true if it was changed.
+pub fn write_byte(ram: &mut [u8], index: Option<usize>, val: u8) -> bool {
+    match index.and_then(|i| ram.get_mut(i)) {
+        Some(b) if *b != val => {
+            *b = val;
+            true
+        }
+        _ => false,
+    }
+}
+
+// Returns the host time in seconds. (RTCの基準)
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    // 各バンクの先頭2バイトにバンク番号 (LE) を書いたROM
+    pub fn banked_rom(banks: usize) -> Vec<u8> {
+        let mut rom = vec![0; banks * ROM_BANK_SIZE];
+        for bank in 0..banks {
+            rom[bank * ROM_BANK_SIZE] = bank as u8;
+            rom[bank * ROM_BANK_SIZE + 1] = (bank >> 8) as u8;
+        }
+        rom
+    }
+
+    // addrから見えているROMバンクの番号
+    pub fn bank_at(mbc: &dyn Mbc, rom: &[u8], addr: u16) -> usize {
+        mbc.read_rom(rom, addr) as usize | (mbc.read_rom(rom, addr + 1) as usize) << 8
+    }
+}
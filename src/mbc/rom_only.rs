@@ -0,0 +1,35 @@
+use serde::{Deserialize, Serialize};
+use mbc::*;
+
+// ROM ONLY ($00), ROM+RAM ($08), ROM+RAM+BATTERY ($09)
+// 32KBのROMのみ、バンク切り替え無し (RAMがあれば常にアクセスできる)
+#[derive(Default, Serialize, Deserialize)]
+pub struct RomOnly {}
+
+impl RomOnly {
+    pub fn new() -> Self {
+        RomOnly {}
+    }
+}
+
+impl Mbc for RomOnly {
+    fn read_rom(&self, rom: &[u8], addr: u16) -> u8 {
+        rom[addr as usize & (rom.len() - 1)]
+    }
+
+    fn write_reg(&mut self, _addr: u16, _val: u8) {
+        // NOP
+    }
+
+    fn read_ram(&mut self, ram: &[u8], addr: u16) -> u8 {
+        match ram_index(ram, 0, addr) {
+            Some(i) => ram[i],
+            None => 0xFF,
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, val: u8) -> bool {
+        let i = ram_index(ram, 0, addr);
+        write_byte(ram, i, val)
+    }
+}
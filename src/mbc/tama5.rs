@@ -0,0 +1,332 @@
+use serde::{Deserialize, Serialize};
+use mbc::*;
+
+// BANDAI TAMA5 ($FD) ... TAMA5 + TAMA6(RTC) + 32ByteのEEPROM (たまごっち3)
+// レジスタは $A001 で番号を選んで $A000 で4bitずつ読み書きする
+// $A000: データ (Write: 下位4bit, Read: $F0 | 4bit)
+// $A001: レジスタ番号 (Write)
+//   $0: ROMバンク下位4bit    $1: ROMバンク上位1bit
+//   $4: 書き込みデータ下位4bit    $5: 書き込みデータ上位4bit
+//   $6: Bit0 = アドレス上位1bit, Bit1-3 = コマンド
+//   $7: アドレス下位4bit (書くとコマンドを実行する)
+//   $A: (Read) 準備完了 = 1
+//   $C: (Read) 読み出しデータ下位4bit    $D: (Read) 読み出しデータ上位4bit
+// コマンド
+//   $0: EEPROM[アドレス] ← データ
+//   $1: EEPROM[アドレス] を読み出す
+//   $2: RTC (アドレス $00 = 停止, $01 = 動作, $04 = 分を書く(BCD), $05 = 時を書く(BCD), $06 = 分を読む, $07 = 時を読む)
+//   $4: RTCのレジスタ (データ下位4bit = レジスタ番号, アドレス $0 = データ上位4bitを書く, $1 = 読む)
+pub const TAMA5_EEPROM_SIZE: usize = 32;
+
+const REG_ROM_BANK_LO: u8 = 0x0;
+const REG_ROM_BANK_HI: u8 = 0x1;
+const REG_WRITE_LO: u8 = 0x4;
+const REG_WRITE_HI: u8 = 0x5;
+const REG_ADDR_HI: u8 = 0x6;
+const REG_ADDR_LO: u8 = 0x7;
+const REG_ACTIVE: u8 = 0xA;
+const REG_READ_LO: u8 = 0xC;
+const REG_READ_HI: u8 = 0xD;
+
+#[derive(Serialize, Deserialize)]
+pub struct Tama5 {
+    reg: u8,                // 選択中のレジスタ
+    regs: [u8; 8],          // $0~$7
+    read_val: u8,           // コマンドで読み出したデータ
+    rtc: Tama6,
+}
+
+impl Default for Tama5 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Tama5 {
+    pub fn new() -> Self {
+        Tama5 {
+            reg: 0,
+            regs: [0; 8],
+            read_val: 0,
+            rtc: Tama6::new(),
+        }
+    }
+
+    fn rom_bank(&self) -> usize {
+        ((self.regs[REG_ROM_BANK_HI as usize] & 0x01) << 4 | self.regs[REG_ROM_BANK_LO as usize]) as usize
+    }
+
+    fn execute(&mut self, ram: &mut [u8]) -> bool {
+        let data = self.regs[REG_WRITE_HI as usize] << 4 | self.regs[REG_WRITE_LO as usize];
+        let addr_hi = self.regs[REG_ADDR_HI as usize];
+        let addr = ((addr_hi & 0x01) << 4 | self.regs[REG_ADDR_LO as usize]) as usize;
+
+        match addr_hi >> 1 {
+            0x0 => return write_byte(ram, Some(addr), data),
+            0x1 => self.read_val = ram.get(addr).copied().unwrap_or(0xFF),
+            0x2 => match addr {
+                0x00 => self.rtc.set_running(false),
+                0x01 => self.rtc.set_running(true),
+                0x04 => self.rtc.write_minute(data),
+                0x05 => self.rtc.write_hour(data),
+                0x06 => self.read_val = self.rtc.minute(),
+                0x07 => self.read_val = self.rtc.hour(),
+                _ => (),
+            },
+            0x4 => {
+                let reg = data & 0x0F;
+                match self.regs[REG_ADDR_LO as usize] {
+                    0x0 => self.rtc.write_nibble(reg, data >> 4),
+                    0x1 => self.read_val = self.rtc.read_nibble(reg),
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+        false
+    }
+}
+
+impl Mbc for Tama5 {
+    fn read_rom(&self, rom: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => read_rom_bank(rom, 0, addr),
+            _ => read_rom_bank(rom, self.rom_bank(), addr),
+        }
+    }
+
+    fn write_reg(&mut self, _addr: u16, _val: u8) {
+        // $0000-$7FFFにレジスタは無い
+    }
+
+    fn read_ram(&mut self, _ram: &[u8], addr: u16) -> u8 {
+        if addr & 0x1FFF != 0 {
+            return 0xFF;
+        }
+
+        0xF0 | match self.reg {
+            REG_ACTIVE => 0x01,
+            REG_READ_LO => self.read_val & 0x0F,
+            REG_READ_HI => self.read_val >> 4,
+            _ => 0x00,
+        }
+    }
+
+    fn write_ram(&mut self, ram: &mut [u8], addr: u16, val: u8) -> bool {
+        match addr & 0x1FFF {
+            0x0000 => {
+                if let Some(reg) = self.regs.get_mut(self.reg as usize) {
+                    *reg = val & 0x0F;
+                    if self.reg == REG_ADDR_LO {
+                        return self.execute(ram);
+                    }
+                }
+            }
+            0x0001 => self.reg = val & 0x0F,
+            _ => (),
+        }
+        false
+    }
+
+    fn save_extra(&mut self) -> Vec<u8> {
+        self.rtc.save()
+    }
+
+    fn extra_size(&self) -> usize {
+        RTC_FOOTER_SIZE
+    }
+
+    fn load_extra(&mut self, data: &[u8]) {
+        self.rtc.load(data);
+    }
+}
+
+// [TAMA6 (RTC)]
+// レジスタ (BCD 4bit): $0,$1 = 秒, $2,$3 = 分, $4,$5 = 時, $6 = 曜日, $7,$8 = 日, $9,$A = 月, $B,$C = 年 (それぞれ1の位, 10の位)
+// ホストの時計(UNIX時間)との差分で進める
+//
+// [.savのRTCフッター (16Byte)]
+// | UNIX時間 (u64, LE) | 秒, 分, 時, 曜日, 日, 月, 年 (u8 × 7) | 動作中 (u8) |
+const RTC_FOOTER_SIZE: usize = 16;
+const RTC_SEC: usize = 0;
+const RTC_MIN: usize = 1;
+const RTC_HOUR: usize = 2;
+const RTC_WEEK: usize = 3;
+const RTC_DAY: usize = 4;
+const RTC_MONTH: usize = 5;
+const RTC_YEAR: usize = 6;
+// 各カウンタの最大値 (日は月によって変わる)
+const RTC_MAX: [u8; 7] = [59, 59, 23, 6, 31, 12, 99];
+
+#[derive(Serialize, Deserialize)]
+struct Tama6 {
+    time: [u8; 7],      // 秒, 分, 時, 曜日, 日, 月, 年 (2進数)
+    running: bool,
+    last_time: u64,     // 最後に時刻を進めた時刻 (UNIX時間)
+}
+
+impl Tama6 {
+    fn new() -> Self {
+        Tama6 {
+            time: [0, 0, 0, 0, 1, 1, 0],
+            running: true,
+            last_time: unix_time(),
+        }
+    }
+
+    // Advances the clock to the current host time.
+    fn sync(&mut self) {
+        let now = unix_time();
+        let elapsed = now.saturating_sub(self.last_time);
+        self.last_time = now;
+
+        if !self.running || elapsed == 0 {
+            return;
+        }
+
+        let total = elapsed
+            + self.time[RTC_SEC] as u64
+            + self.time[RTC_MIN] as u64 * 60
+            + self.time[RTC_HOUR] as u64 * 3600;
+        self.time[RTC_SEC] = (total % 60) as u8;
+        self.time[RTC_MIN] = (total / 60 % 60) as u8;
+        self.time[RTC_HOUR] = (total / 3600 % 24) as u8;
+
+        for _ in 0..total / 86400 {
+            self.next_day();
+        }
+    }
+
+    fn next_day(&mut self) {
+        self.time[RTC_WEEK] = (self.time[RTC_WEEK] + 1) % 7;
+        self.time[RTC_DAY] += 1;
+        if self.time[RTC_DAY] > self.days_in_month() {
+            self.time[RTC_DAY] = 1;
+            self.time[RTC_MONTH] += 1;
+            if self.time[RTC_MONTH] > 12 {
+                self.time[RTC_MONTH] = 1;
+                self.time[RTC_YEAR] = (self.time[RTC_YEAR] + 1) % 100;
+            }
+        }
+    }
+
+    fn days_in_month(&self) -> u8 {
+        match self.time[RTC_MONTH] {
+            2 if self.time[RTC_YEAR] & 0x03 == 0 => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            _ => 31,
+        }
+    }
+
+    fn set_running(&mut self, running: bool) {
+        self.sync();
+        self.running = running;
+    }
+
+    fn minute(&mut self) -> u8 {
+        self.sync();
+        to_bcd(self.time[RTC_MIN])
+    }
+
+    fn hour(&mut self) -> u8 {
+        self.sync();
+        to_bcd(self.time[RTC_HOUR])
+    }
+
+    fn write_minute(&mut self, bcd: u8) {
+        self.set(RTC_MIN, from_bcd(bcd));
+        self.time[RTC_SEC] = 0;
+    }
+
+    fn write_hour(&mut self, bcd: u8) {
+        self.set(RTC_HOUR, from_bcd(bcd));
+    }
+
+    fn set(&mut self, i: usize, val: u8) {
+        self.sync();
+        self.time[i] = val.min(RTC_MAX[i]);
+    }
+
+    fn read_nibble(&mut self, reg: u8) -> u8 {
+        self.sync();
+        match reg {
+            0x6 => self.time[RTC_WEEK],
+            0x0..=0xC => {
+                let i = Self::nibble_index(reg);
+                let bcd = to_bcd(self.time[i]);
+                if Self::is_ones(reg) { bcd & 0x0F } else { bcd >> 4 }
+            }
+            _ => 0x00,
+        }
+    }
+
+    fn write_nibble(&mut self, reg: u8, val: u8) {
+        match reg {
+            0x6 => self.set(RTC_WEEK, val),
+            0x0..=0xC => {
+                let i = Self::nibble_index(reg);
+                let bcd = to_bcd(self.time[i]);
+                let bcd = if Self::is_ones(reg) {
+                    (bcd & 0xF0) | (val & 0x0F)
+                } else {
+                    (bcd & 0x0F) | (val & 0x0F) << 4
+                };
+                self.set(i, from_bcd(bcd));
+            }
+            _ => (),
+        }
+    }
+
+    // レジスタ番号 → time[]のインデックス ($0,$1 = 秒, ..., $6 = 曜日, $7,$8 = 日, ...)
+    fn nibble_index(reg: u8) -> usize {
+        match reg {
+            0x0..=0x5 => (reg / 2) as usize,
+            0x6 => RTC_WEEK,
+            _ => ((reg - 0x7) / 2) as usize + RTC_DAY,
+        }
+    }
+
+    // 1の位のレジスタならtrue (10の位ならfalse)
+    fn is_ones(reg: u8) -> bool {
+        (reg & 0x01 == 0) == (reg < 0x6)
+    }
+
+    fn save(&mut self) -> Vec<u8> {
+        self.sync();
+
+        let mut data = Vec::with_capacity(RTC_FOOTER_SIZE);
+        data.extend_from_slice(&self.last_time.to_le_bytes());
+        data.extend_from_slice(&self.time);
+        data.push(self.running as u8);
+        data
+    }
+
+    fn load(&mut self, data: &[u8]) {
+        if data.len() != RTC_FOOTER_SIZE {
+            if !data.is_empty() {
+                warn!("Unknown RTC footer size ({} bytes)", data.len());
+            }
+            return;
+        }
+
+        let mut time = [0u8; 8];
+        time.copy_from_slice(&data[0..8]);
+        self.last_time = u64::from_le_bytes(time);
+        for (i, max) in RTC_MAX.iter().enumerate() {
+            self.time[i] = data[8 + i].min(*max);
+        }
+        self.running = data[15] != 0;
+
+        // セーブしてからの経過時間を進める
+        self.sync();
+    }
+}
+
+fn to_bcd(val: u8) -> u8 {
+    ((val / 10) << 4) | (val % 10)
+}
+
+fn from_bcd(bcd: u8) -> u8 {
+    (bcd >> 4) * 10 + (bcd & 0x0F)
+}
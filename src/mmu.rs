@@ -1,11 +1,16 @@
+use serde::{Deserialize, Serialize};
+use serde_big_array::BigArray;
 use common::*;
-use bios::BIOS;
+use bios;
+use bios::{BIOS, Model};
 use cgb::*;
 use cartridge::Cartridge;
 use serial::Serial;
 use gamepad::GamePad;
+use interrupt::*;
 use timer::Timer;
 use ppu::PPU;
+use apu::APU;
 
 // WRAM(Work RAM)
 // const WRAM_SIZE: u16 = 8 * 1024;    // DMG
@@ -13,41 +18,91 @@ const WRAM_SIZE: u16 = 32 * 1024;   // CGB (4KB * 8バンク)
 const WRAM_BANK_SIZE: u16 = 4 * 1024;
 // HRAM(High RAM)
 const HRAM_SIZE: u16 = 0x7F;
+// 速度切り替えにかかる時間 (2050 M-cycle)
+const SPEED_SWITCH_TICKS: u32 = 2050 * 4;
+// VRAM DMAで16Byte転送するのにかかる時間 (8µs、通常速度のクロック数)
+const HDMA_BLOCK_TICKS: u32 = 32;
 
+#[derive(Serialize, Deserialize)]
 pub struct MMU {
     pub bios: BIOS,
     pub cartridge: Cartridge,
     pub cgb: CGB,
+    #[serde(with = "BigArray")]
     wram: [u8; WRAM_SIZE as usize],
+    #[serde(with = "BigArray")]
     hram: [u8; HRAM_SIZE as usize],
     pub serial: Serial,
     pub gamepad: GamePad,
     timer: Timer,
     pub ppu: PPU,
-    pub int_flag: u8,
-    pub int_enable: u8,
+    pub apu: APU,
+    pub interrupt: Interrupt,
+    pub model: Model,
+    pub cpu_stall: u32,     // CPUが止まっている残りクロック (速度切り替え, VRAM DMA)
 }
 
 impl MMU {
-    pub fn new(bios_path: &str, rom_path: &str) -> Self {
-        let cgb: CGB = CGB::new();
-        // BG/OBJカラーパレットのポインタを取得
-        let p_bg_col_plt: *const u8 = cgb.bg_col_plt.as_ptr();
-        let p_obj_col_plt: *const u8 = cgb.obj_col_plt.as_ptr();
-
+    pub fn new(bios: BIOS, cartridge: Cartridge) -> Self {
         MMU {
-            bios: BIOS::new(bios_path),
-            cartridge: Cartridge::new(rom_path),
-            cgb: cgb,
+            bios,
+            cartridge,
+            cgb: CGB::new(),
             wram: [0; WRAM_SIZE as usize],
             hram: [0; HRAM_SIZE as usize],
             serial: Serial::new(),
             gamepad: GamePad::new(),
-            ppu: PPU::new(p_bg_col_plt, p_obj_col_plt),
+            ppu: PPU::new(),
+            apu: APU::new(),
             timer: Timer::new(),
-            int_flag: 0,
-            int_enable: 0,
+            interrupt: Interrupt::new(),
+            model: Model::DMG,
+            cpu_stall: 0,
+        }
+    }
+
+    // Sets I/O registers to the state after the boot ROM has finished.
+    pub fn post_boot(&mut self) {
+        let cgb_mode = self.cgb.unlock_flg && self.cgb.cgb_mode != CGB_MODE_NON_CGB;
+
+        // ブートROMが書き込む値なので、KEY0も書き込めるようにブートROMの実行中として扱う
+        self.bios.is_boot = true;
+        for (addr, val) in bios::post_boot_io(self.model, cgb_mode) {
+            self.write(addr, val);
         }
+        self.timer.set_counter(bios::post_boot_div(self.model));
+        self.bios.is_boot = false;
+    }
+
+    // Restores state that is not included in a save state.
+    // (ROM data is moved from the old MMU.)
+    pub fn restore_from(&mut self, old: &mut MMU) {
+        self.cartridge.take_rom(&mut old.cartridge);
+    }
+
+    // Returns true if the CPU runs at double speed. (CGB)
+    pub fn double_speed(&self) -> bool {
+        self.cgb.double_speed
+    }
+
+    // Returns true if a speed switch is prepared with KEY1. (CGBモードのみ)
+    pub fn speed_switch_requested(&self) -> bool {
+        let cgb_mode = self.cgb.unlock_flg && self.cgb.cgb_mode != CGB_MODE_NON_CGB;
+        self.model.is_cgb() && cgb_mode && self.cgb.key_1 & 0x01 != 0
+    }
+
+    // Switches between normal and double speed. (STOP)
+    // 切り替え中はCPUが止まる
+    pub fn switch_speed(&mut self) {
+        self.cgb.double_speed = !self.cgb.double_speed;
+        self.cgb.key_1 = 0;
+        self.cpu_stall += SPEED_SWITCH_TICKS;
+        info!("Speed switch: {}", if self.cgb.double_speed { "double" } else { "normal" });
+    }
+
+    // Resets DIV. (STOP)
+    pub fn reset_div(&mut self) {
+        self.timer.reset_counter();
     }
 
     pub fn write(&mut self, addr: u16, val: u8) {
@@ -60,7 +115,7 @@ impl MMU {
             0xA000..=0xBFFF => self.cartridge.write(addr, val),
             // WRAM
             0xC000..=0xDFFF => {
-                if self.cgb.unlock_flg != false {
+                if self.cgb.unlock_flg {
                     let offset = WRAM_BANK_SIZE as usize * self.cgb.svbk as usize;
                     self.wram[(addr & 0x1FFF) as usize + offset] = val;
                 }else{
@@ -69,7 +124,7 @@ impl MMU {
             },
             // Echo RAM
             0xE000..=0xFDFF => {
-                if self.cgb.unlock_flg != false {
+                if self.cgb.unlock_flg {
                     let offset: usize = WRAM_BANK_SIZE as usize * self.cgb.svbk as usize;
                     self.wram[((addr - WRAM_SIZE) & 0x1FFF) as usize + offset] = val;
                 }else{
@@ -85,28 +140,38 @@ impl MMU {
             // Timer
             0xFF04..=0xFF07 => self.timer.write(addr, val),
             // Interrupt Flag
-            0xFF0F => self.int_flag = val,
-            // TODO APU
-            0xFF10..=0xFF26 => { warn!("APU I/O Write ${:#04X}", addr) },
-            // TODO Wave Pattern (APU)
-            0xFF30..=0xFF3F => { warn!("Wave Patter I/O Write ${:#04X}", addr); },
+            0xFF0F => self.interrupt.write(addr, val),
+            // APU, Wave Pattern
+            0xFF10..=0xFF3F => self.apu.write(addr, val),
             // PPU
             0xFF40..=0xFF45 | 0xFF47..=0xFF4B => self.ppu.write(addr, val),
+            // (CGB Only) Color palette
+            0xFF68..=0xFF6B => self.ppu.write(addr, val),
             // OAM DMA
-            0xFF46 => self.oam_dma_start(val),
+            0xFF46 => {
+                self.ppu.write(addr, val);
+                self.oam_dma_start(val);
+            },
+            // (CGB Only) KEY0 (ブートROMの実行中のみ)
+            0xFF4C if self.bios.is_boot => self.cgb.write(addr, val),
+            // Boot ROM disable
+            0xFF50 if val != 0 => self.bios.is_boot = false,
+            // (CGB Only) HDMA5 (DMA転送開始・H-Blank DMAの中止)
+            0xFF55 => self.cgb_dma_start(val),
             // (CGB Only) I/O Reg
             0xFF4D..=0xFF77 => {
                 self.cgb.write(addr, val);
-                // HDMA5 ($FF55)への書き込みはDMA転送開始
-                if addr == 0xFF55 {
-                    self.cgb_dma_start();
+                // VBK ($FF4F)は直後のVRAMアクセスから反映する
+                if addr == 0xFF4F {
+                    self.ppu.vram_bank = self.cgb.vbk;
                 }
             },
             // HRAM
             0xFF80..=0xFFFE => self.hram[(addr & HRAM_SIZE) as usize] = val,
             // Interrupt Enable
-            0xFFFF => self.int_enable = val,
-            _ => panic!("[ERR] Invalid Write Addr ${:#04X}", addr),
+            0xFFFF => self.interrupt.write(addr, val),
+            // 未使用領域・未実装のI/Oレジスタへの書き込みは無視する
+            _ => (),
         }
     }
 
@@ -114,13 +179,8 @@ impl MMU {
         match addr {
             // BIOS or ROM
             0x0000..=0x7FFF => {
-                if self.bios.is_boot != false {
-                    if addr > 0x00FF {
-                        self.bios.is_boot = false;
-                        self.cartridge.read(addr)
-                    }else{
-                        self.bios.read(addr)
-                    }
+                if self.bios.is_mapped(addr) {
+                    self.bios.read(addr)
                 }else{
                     self.cartridge.read(addr)
                 }
@@ -131,7 +191,7 @@ impl MMU {
             0xA000..=0xBFFF => self.cartridge.read(addr),
             // WRAM
             0xC000..=0xDFFF => {
-                if self.cgb.unlock_flg != false {
+                if self.cgb.unlock_flg {
                     let offset = WRAM_BANK_SIZE as usize * self.cgb.svbk as usize;
                     self.wram[(addr & 0x1FFF) as usize + offset]
                 }else{
@@ -140,7 +200,7 @@ impl MMU {
             },
             // Echo RAM
             0xE000..=0xFDFF => {
-                if self.cgb.unlock_flg != false {
+                if self.cgb.unlock_flg {
                     let offset: usize = WRAM_BANK_SIZE as usize * self.cgb.svbk as usize;
                     self.wram[((addr - WRAM_SIZE) & 0x1FFF) as usize + offset]
                 }else{
@@ -156,100 +216,279 @@ impl MMU {
             // Timer
             0xFF04..=0xFF07 => self.timer.read(addr),
             // Interrupt flag
-            0xFF0F => self.int_flag,
-            // TODO APU
-            0xFF10..=0xFF26 => { warn!("APU I/O Read ${:#04X}", addr);
-                                0xFF },
-            // TODO Wave Pattern (APU)
-            0xFF30..=0xFF3F => { warn!("Wave Patter I/O Read ${:#04X}", addr);
-                                0xFF },
-            // PPU
-            0xFF40..=0xFF45 | 0xFF47..=0xFF4B => self.ppu.read(addr),
+            0xFF0F => self.interrupt.read(addr),
+            // APU, Wave Pattern
+            0xFF10..=0xFF3F => self.apu.read(addr),
+            // PPU, OAM DMA
+            0xFF40..=0xFF4B => self.ppu.read(addr),
+            // (CGB Only) Color palette
+            0xFF68..=0xFF6B => self.ppu.read(addr),
+            // Boot ROM disable (書き込み専用)
+            0xFF50 => 0xFF,
             // (CGB Only) I/O Reg
-            0xFF4D..=0xFF77 => self.cgb.read(addr),
+            0xFF4C..=0xFF77 => self.cgb.read(addr),
             // HRAM
             0xFF80..=0xFFFE => self.hram[(addr & HRAM_SIZE) as usize],
             // Interrupt enable
-            0xFFFF => self.int_enable,
-            _ => panic!("[ERR] Invalid Read Addr ${:#04X}", addr),
+            0xFFFF => self.interrupt.read(addr),
+            // 未使用領域・未実装のI/Oレジスタはオープンバス (0xFF)
+            _ => 0xFF,
         }
     }
 
-    pub fn update(&mut self, tick: u8) {
+    // Advances the peripherals by `tick` CPU clocks.
+    // Returns the elapsed clocks at normal speed (4.194304 MHz).
+    //
+    // [倍速モード]
+    // CPUのクロックで動く: Timer (DIV), Serial, カートリッジ (PHI)
+    // 速度が変わらない: PPU, APU, VRAM DMA → CPUクロックの半分だけ進める
+    pub fn update(&mut self, tick: u8) -> u8 {
+        let real_tick = if self.cgb.double_speed { tick / 2 } else { tick };
+
         self.ppu.cgb_mode = self.cgb.cgb_mode;
+        self.ppu.opri = self.cgb.opri;
         self.ppu.cgb_unlock_flg = self.cgb.unlock_flg;
         self.ppu.vram_bank = self.cgb.vbk;
+        self.timer.double_speed = self.cgb.double_speed;
 
         self.bios.update(tick);
-        self.cgb.update(tick);
+        self.cgb.update(real_tick);
         self.cartridge.update(tick);
-        self.ppu.update(tick);
+        self.ppu.update(real_tick);
+        // H-Blankに入る度にH-Blank DMAを1ブロック転送する
+        if self.ppu.hblank_start {
+            self.ppu.hblank_start = false;
+            if self.cgb.hdma_active {
+                self.hdma_block();
+            }
+        }
         self.timer.update(tick);
-        self.gamepad.update(tick);
+        // DIV-APUでフレームシーケンサを進める
+        for _ in 0..self.timer.div_apu {
+            self.apu.step_frame_sequencer();
+        }
+        self.timer.div_apu = 0;
+        self.apu.update(real_tick);
+        self.gamepad.update(real_tick);
         self.serial.update(tick);
 
         // IRQのポーリング
         self.irq_poll();
+
+        real_tick
+    }
+
+    // Advances time in STOP mode. Returns the elapsed clocks at normal speed.
+    // システムクロックが止まるので、ボタン入力以外は何も進めない
+    pub fn update_stopped(&mut self, tick: u8) -> u8 {
+        let real_tick = if self.cgb.double_speed { tick / 2 } else { tick };
+        self.apu.output_silence(real_tick);
+        self.irq_poll();
+
+        real_tick
     }
 
+    // Collects interrupt requests from the peripherals.
     fn irq_poll(&mut self) {
         if self.ppu.irq_vblank {
-            self.int_flag |= 0x01;
+            self.interrupt.request(INT_VBLANK);
             self.ppu.irq_vblank = false;
         }
 
         if self.ppu.irq_lcdc {
-            self.int_flag |= 0x02;
+            self.interrupt.request(INT_STAT);
             self.ppu.irq_lcdc = false;
         }
 
         if self.timer.irq {
-            self.int_flag |= 0x04;
+            self.interrupt.request(INT_TIMER);
             self.timer.irq = false;
         }
 
+        if self.serial.irq {
+            self.interrupt.request(INT_SERIAL);
+            self.serial.irq = false;
+        }
+
         if self.gamepad.irq {
-            self.int_flag |= 0x10;
+            self.interrupt.request(INT_JOYPAD);
             self.gamepad.irq = false;
         }
     }
 
-    fn cgb_dma_start(&mut self) {
-        // TODO CGBの汎用DMA、H-Blank DMAの転送タイミング
-        let src_addr: u16 = ((self.cgb.hdma1 as u16) << 8) | self.cgb.hdma2 as u16;
-        let dst_addr: u16 = ((self.cgb.hdma3 as u16) << 8) | self.cgb.hdma4 as u16;
-        let mut _dma_len: u16 = 0;
+    // [VRAM DMA]
+    // 汎用DMA: 0x10~0x800(16~2048)Byteをすぐに転送する
+    // H-Blank DMA: H-Blankに入る度に0x10Byteずつ転送する
+    //   転送中のHDMA5はBit7 = 0で残りの長さ(ブロック数 - 1)、完了すると0xFF
+    //   転送中にBit7 = 0を書き込むと中止 (HDMA5のBit7 = 1になる)
+    fn cgb_dma_start(&mut self, val: u8) {
+        if self.cgb.hdma_active && val & _BIT_7 == 0 {
+            self.cgb.hdma_active = false;
+            self.cgb.hdma5 |= _BIT_7;
+            return;
+        }
+
+        self.cgb.hdma5 = val & 0x7F;
+        // 転送元・転送先の下位4bitは無視される
+        self.cgb.hdma_src = ((self.cgb.hdma1 as u16) << 8 | self.cgb.hdma2 as u16) & 0xFFF0;
+        self.cgb.hdma_dst = ((self.cgb.hdma3 as u16) << 8 | self.cgb.hdma4 as u16) & 0x1FF0;
 
-        if( self.cgb.hdma5 & _BIT_7) != _CGB_GP_DMA {
-            // H-Blank DMAは0x10(16)Byte転送
-            _dma_len = 0x10;
-        }else{
-            // 汎用DMAは0x10~0x800(16~2048)Byte転送
-            _dma_len = self.cgb.get_dma_len();
+        if val & _BIT_7 != 0 {
+            self.cgb.hdma_active = true;
+        } else {
+            let blocks = self.cgb.get_dma_len() / 0x10;
+            for _ in 0..blocks {
+                self.hdma_block();
+            }
         }
+    }
 
-        for i in 0.._dma_len {
-            let tmp = self.read(src_addr | i);
-            self.write(dst_addr | i, tmp);
+    // Copies one 0x10 byte block and updates HDMA5.
+    fn hdma_block(&mut self) {
+        for i in 0..0x10 {
+            let tmp = self.read(self.cgb.hdma_src.wrapping_add(i));
+            self.write(0x8000 | (self.cgb.hdma_dst.wrapping_add(i) & 0x1FFF), tmp);
         }
+        self.cgb.hdma_src = self.cgb.hdma_src.wrapping_add(0x10);
+        self.cgb.hdma_dst = self.cgb.hdma_dst.wrapping_add(0x10) & 0x1FF0;
+
+        // 転送中はCPUが止まる (倍速モードでもかかる時間は同じ = CPUクロックは2倍)
+        self.cpu_stall += if self.cgb.double_speed { HDMA_BLOCK_TICKS * 2 } else { HDMA_BLOCK_TICKS };
 
         // 転送完了はレジスタを0xFFにする
-        self.cgb.hdma5 = 0xFF;
+        if self.cgb.hdma5 & 0x7F == 0 {
+            self.cgb.hdma_active = false;
+            self.cgb.hdma5 = 0xFF;
+        } else {
+            self.cgb.hdma5 -= 1;
+        }
     }
 
     fn oam_dma_start(&mut self, val: u8) {
         // TODO OAM DMA転送バグの実装
         // https://gbdev.io/pandocs/OAM_Corruption_Bug.html
-        let src_base = (val as u16) << 8;
+        // $E000以降はWRAMのミラーから転送する
+        let src_base = if val < 0xE0 { (val as u16) << 8 } else { (val as u16 - 0x20) << 8 };
         let dst_base = 0xFE00;
 
-        if val < 0x80 || 0xDF < val {
-            panic!("[ERR] Invalid DMA Src Addr ${:#04X}", src_base);
-        }
-
         for i in 0..0xA0 {
             let tmp = self.read(src_base | i);
             self.write(dst_base | i, tmp);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cartridge::tests::cartridge;
+
+    fn cgb_mmu() -> MMU {
+        let mut mmu = MMU::new(BIOS::none(), cartridge(0x00, 0, 0, &[]));
+        mmu.model = Model::CGB;
+        mmu.cgb.cgb_unlock(0xC0);
+        mmu.post_boot();
+        mmu
+    }
+
+    // WRAM($C000~)に転送元のデータを置いて、転送元・転送先を設定する
+    fn setup_dma(mmu: &mut MMU, dst: u16) {
+        for i in 0..0x40 {
+            mmu.write(0xC000 + i, i as u8 + 1);
+        }
+        mmu.write(0xFF51, 0xC0);
+        mmu.write(0xFF52, 0x00);
+        mmu.write(0xFF53, (dst >> 8) as u8);
+        mmu.write(0xFF54, dst as u8);
+    }
+
+    // HDMA5が変わるまで進める
+    fn run_until_hdma5_changes(mmu: &mut MMU) {
+        let hdma5 = mmu.read(0xFF55);
+        for _ in 0..10000 {
+            mmu.update(4);
+            if mmu.read(0xFF55) != hdma5 {
+                return;
+            }
+        }
+        panic!("HDMA5 did not change");
+    }
+
+    #[test]
+    fn general_dma_copies_everything() {
+        let mut mmu = cgb_mmu();
+        mmu.write(0xFF40, 0x00);
+        setup_dma(&mut mmu, 0x0000);
+
+        mmu.write(0xFF55, 0x01);
+        assert_eq!(mmu.read(0xFF55), 0xFF);
+        for i in 0..0x20 {
+            assert_eq!(mmu.read(0x8000 + i), i as u8 + 1);
+        }
+        assert_eq!(mmu.read(0x8020), 0x00);
+        assert_eq!(mmu.cpu_stall, HDMA_BLOCK_TICKS * 2);
+    }
+
+    #[test]
+    fn hblank_dma_copies_one_block_per_hblank() {
+        let mut mmu = cgb_mmu();
+        setup_dma(&mut mmu, 0x0000);
+
+        // 3ブロック
+        mmu.write(0xFF55, 0x82);
+        assert_eq!(mmu.read(0xFF55), 0x02);
+
+        run_until_hdma5_changes(&mut mmu);
+        assert_eq!(mmu.read(0xFF55), 0x01);
+        run_until_hdma5_changes(&mut mmu);
+        assert_eq!(mmu.read(0xFF55), 0x00);
+
+        // LCD OFFの間は転送しない
+        mmu.write(0xFF40, 0x00);
+        for _ in 0..1000 {
+            mmu.update(4);
+        }
+        assert_eq!(mmu.read(0xFF55), 0x00);
+        for i in 0..0x20 {
+            assert_eq!(mmu.read(0x8000 + i), i as u8 + 1);
+        }
+        assert_eq!(mmu.read(0x8020), 0x00);
+
+        mmu.write(0xFF40, 0x91);
+        run_until_hdma5_changes(&mut mmu);
+        assert_eq!(mmu.read(0xFF55), 0xFF);
+    }
+
+    #[test]
+    fn hblank_dma_can_be_cancelled() {
+        let mut mmu = cgb_mmu();
+        setup_dma(&mut mmu, 0x0000);
+
+        mmu.write(0xFF55, 0x83);
+        run_until_hdma5_changes(&mut mmu);
+        assert_eq!(mmu.read(0xFF55), 0x02);
+
+        // 中止すると Bit7 = 1、残りの長さはそのまま
+        mmu.write(0xFF55, 0x00);
+        assert_eq!(mmu.read(0xFF55), 0x82);
+        for _ in 0..20000 {
+            mmu.update(4);
+        }
+        assert_eq!(mmu.read(0xFF55), 0x82);
+
+        mmu.write(0xFF40, 0x00);
+        assert_eq!(mmu.read(0x800F), 0x10);
+        assert_eq!(mmu.read(0x8010), 0x00);
+    }
+
+    #[test]
+    fn dma_destination_wraps_in_vram() {
+        let mut mmu = cgb_mmu();
+        mmu.write(0xFF40, 0x00);
+        setup_dma(&mut mmu, 0x1FF0);
+
+        mmu.write(0xFF55, 0x01);
+        assert_eq!(mmu.read(0x9FF0), 0x01);
+        assert_eq!(mmu.read(0x8000), 0x11);
+    }
+}
@@ -0,0 +1,431 @@
+use std::convert::TryFrom;
+use std::fs;
+use std::path::Path;
+use archive;
+use error;
+use error::LoadError;
+
+// [ソフトパッチ (IPS/UPS/BPS)]
+// ROMファイルは書き換えず、読み込んだデータにメモリ上で当てる
+// ROMと同じ名前の .ips/.ups/.bps があれば自動で当てる
+//
+// IPS: "PATCH" | (オフセット 3Byte, サイズ 2Byte, データ) × n | "EOF" | (切り詰めるサイズ 3Byte)
+//      サイズが0ならRLE (サイズ 2Byte, 値 1Byte)
+// UPS: "UPS1" | 元のサイズ | 後のサイズ | (相対位置, XORするデータ, $00) × n | CRC32 × 3
+// BPS: "BPS1" | 元のサイズ | 後のサイズ | メタデータ | コマンド × n | CRC32 × 3
+// UPS/BPSの最後のCRC32は 元のROM, パッチを当てたROM, パッチ自身(最後の4Byte以外)
+pub const PATCH_EXTENSIONS: [&str; 3] = ["bps", "ups", "ips"];
+
+const IPS_EOF: usize = 0x454F46;
+
+// Returns the patch file next to the ROM with the same name, if any.
+pub fn find_patch(rom_path: &str) -> Option<String> {
+    PATCH_EXTENSIONS
+        .iter()
+        .map(|ext| archive::rom_file_path(rom_path, ext))
+        .find(|path| Path::new(path).is_file())
+}
+
+// Reads a patch file and applies it to the ROM data.
+// In lenient mode, checksum mismatches are logged as warnings.
+pub fn apply_patch_file(path: &str, rom: Vec<u8>, lenient: bool) -> Result<Vec<u8>, LoadError> {
+    let data = fs::read(path).map_err(|err| LoadError::Io { path: path.to_string(), err })?;
+    info!("Applying patch {}", path);
+    apply_patch(path, &data, rom, lenient)
+}
+
+fn apply_patch(path: &str, data: &[u8], rom: Vec<u8>, lenient: bool) -> Result<Vec<u8>, LoadError> {
+    let patcher = Patcher { path, lenient, data };
+    if data.starts_with(b"PATCH") {
+        patcher.ips(rom)
+    } else if data.starts_with(b"UPS1") {
+        patcher.ups(rom)
+    } else if data.starts_with(b"BPS1") {
+        patcher.bps(rom)
+    } else {
+        Err(patcher.invalid("unknown patch format (IPS, UPS or BPS)"))
+    }
+}
+
+struct Patcher<'a> {
+    path: &'a str,
+    lenient: bool,
+    data: &'a [u8],
+}
+
+impl<'a> Patcher<'a> {
+    fn invalid(&self, reason: &str) -> LoadError {
+        LoadError::Patch { path: self.path.to_string(), reason: reason.to_string() }
+    }
+
+    fn truncated(&self) -> LoadError {
+        self.invalid("truncated patch")
+    }
+
+    fn broken_number(&self) -> LoadError {
+        self.invalid("broken number")
+    }
+
+    fn bytes(&self, pos: &mut usize, len: usize) -> Result<&'a [u8], LoadError> {
+        let end = pos.checked_add(len).ok_or_else(|| self.truncated())?;
+        let bytes = self.data.get(*pos..end).ok_or_else(|| self.truncated())?;
+        *pos = end;
+        Ok(bytes)
+    }
+
+    // ビッグエンディアンの整数 (IPS)
+    fn be(&self, pos: &mut usize, len: usize) -> Result<usize, LoadError> {
+        Ok(self.bytes(pos, len)?.iter().fold(0, |acc, &b| acc << 8 | b as usize))
+    }
+
+    // 可変長整数 (UPS/BPS)
+    fn varint(&self, pos: &mut usize) -> Result<usize, LoadError> {
+        let mut val: usize = 0;
+        let mut shift: usize = 1;
+        loop {
+            let b = self.bytes(pos, 1)?[0];
+            val = ((b & 0x7F) as usize)
+                .checked_mul(shift)
+                .and_then(|x| val.checked_add(x))
+                .ok_or_else(|| self.broken_number())?;
+            if b & 0x80 != 0 {
+                return Ok(val);
+            }
+            shift = shift.checked_mul(0x80).ok_or_else(|| self.broken_number())?;
+            val = val.checked_add(shift).ok_or_else(|| self.broken_number())?;
+        }
+    }
+
+    fn ips(&self, mut rom: Vec<u8>) -> Result<Vec<u8>, LoadError> {
+        let mut pos = 5;
+
+        loop {
+            let offset = self.be(&mut pos, 3)?;
+            if offset == IPS_EOF {
+                break;
+            }
+
+            let size = self.be(&mut pos, 2)?;
+            let (size, rle) = if size == 0 {
+                (self.be(&mut pos, 2)?, Some(self.bytes(&mut pos, 1)?[0]))
+            } else {
+                (size, None)
+            };
+
+            let end = offset.checked_add(size).ok_or_else(|| self.broken_number())?;
+            self.check_size(end)?;
+            if rom.len() < end {
+                rom.resize(end, 0);
+            }
+            match rle {
+                Some(val) => rom[offset..end].iter_mut().for_each(|b| *b = val),
+                None => rom[offset..end].copy_from_slice(self.bytes(&mut pos, size)?),
+            }
+        }
+
+        // EOFの後に3Byteあれば切り詰める
+        if let Ok(len) = self.be(&mut pos, 3) {
+            rom.truncate(len);
+        }
+
+        Ok(rom)
+    }
+
+    fn ups(&self, rom: Vec<u8>) -> Result<Vec<u8>, LoadError> {
+        let end = self.footer()?;
+        let mut pos = 4;
+        let source_size = self.varint(&mut pos)?;
+        let target_size = self.varint(&mut pos)?;
+        self.check_size(target_size)?;
+
+        self.check_source(&rom, source_size)?;
+
+        let mut target = rom;
+        target.resize(target_size, 0);
+
+        let mut out: usize = 0;
+        while pos < end {
+            out = out.checked_add(self.varint(&mut pos)?).ok_or_else(|| self.broken_number())?;
+            loop {
+                let x = self.bytes(&mut pos, 1)?[0];
+                if let Some(b) = target.get_mut(out) {
+                    *b ^= x;
+                }
+                out = out.saturating_add(1);
+                if x == 0 {
+                    break;
+                }
+            }
+        }
+
+        self.check_target(&target)?;
+        Ok(target)
+    }
+
+    fn bps(&self, rom: Vec<u8>) -> Result<Vec<u8>, LoadError> {
+        let end = self.footer()?;
+        let mut pos = 4;
+        let source_size = self.varint(&mut pos)?;
+        let target_size = self.varint(&mut pos)?;
+        self.check_size(target_size)?;
+        let metadata_size = self.varint(&mut pos)?;
+        self.bytes(&mut pos, metadata_size)?;
+
+        self.check_source(&rom, source_size)?;
+
+        let mut target = Vec::with_capacity(target_size);
+        let mut source_offset: isize = 0;
+        let mut target_offset: isize = 0;
+        let out_of_range = || self.invalid("copy out of range");
+
+        while pos < end {
+            let cmd = self.varint(&mut pos)?;
+            let len = (cmd >> 2) + 1;
+            // 宣言されたサイズを超えて書き出さない
+            if len > target_size - target.len() {
+                return Err(self.invalid("patched size does not match"));
+            }
+
+            match cmd & 0x03 {
+                // SourceRead: 元のROMの同じ位置からコピー
+                0 => {
+                    let start = target.len();
+                    let src = rom.get(start..start + len).ok_or_else(out_of_range)?;
+                    target.extend_from_slice(src);
+                }
+                // TargetRead: パッチからコピー
+                1 => target.extend_from_slice(self.bytes(&mut pos, len)?),
+                // SourceCopy: 元のROMの任意の位置からコピー
+                2 => {
+                    source_offset = self.offset(source_offset, self.relative(&mut pos)?)?;
+                    let start = usize::try_from(source_offset).map_err(|_| out_of_range())?;
+                    let src = start
+                        .checked_add(len)
+                        .and_then(|end| rom.get(start..end))
+                        .ok_or_else(out_of_range)?;
+                    target.extend_from_slice(src);
+                    source_offset = self.offset(source_offset, len as isize)?;
+                }
+                // TargetCopy: 出力済みのデータからコピー (重なってもいいので1Byteずつ)
+                _ => {
+                    target_offset = self.offset(target_offset, self.relative(&mut pos)?)?;
+                    for _ in 0..len {
+                        let i = usize::try_from(target_offset).map_err(|_| out_of_range())?;
+                        let b = *target.get(i).ok_or_else(out_of_range)?;
+                        target.push(b);
+                        target_offset = self.offset(target_offset, 1)?;
+                    }
+                }
+            }
+        }
+
+        if target.len() != target_size {
+            return Err(self.invalid("patched size does not match"));
+        }
+
+        self.check_target(&target)?;
+        Ok(target)
+    }
+
+    // 確保する前にパッチ後のサイズを確かめる (壊れたパッチで巨大な領域を確保しないように)
+    fn check_size(&self, size: usize) -> Result<(), LoadError> {
+        if size as u64 > archive::MAX_ROM_SIZE {
+            return Err(self.invalid("patched ROM is too large"));
+        }
+        Ok(())
+    }
+
+    // BPSの相対オフセット (Bit0 = 符号)
+    fn relative(&self, pos: &mut usize) -> Result<isize, LoadError> {
+        let val = self.varint(pos)?;
+        let offset = (val >> 1) as isize;
+        Ok(if val & 0x01 != 0 { -offset } else { offset })
+    }
+
+    fn offset(&self, offset: isize, delta: isize) -> Result<isize, LoadError> {
+        offset.checked_add(delta).ok_or_else(|| self.broken_number())
+    }
+
+    // パッチ自身のCRC32を確かめて、コマンドの終わりの位置を返す
+    fn footer(&self) -> Result<usize, LoadError> {
+        if self.data.len() < 4 + 12 {
+            return Err(self.truncated());
+        }
+
+        let end = self.data.len() - 12;
+        self.check_crc("patch file", self.crc_at(end + 8), crc32(&self.data[..end + 8]))?;
+        Ok(end)
+    }
+
+    fn check_source(&self, rom: &[u8], size: usize) -> Result<(), LoadError> {
+        if rom.len() != size {
+            error::check(
+                self.lenient,
+                LoadError::PatchSizeMismatch { path: self.path.to_string(), expected: size, actual: rom.len() },
+            )?;
+        }
+        let end = self.data.len() - 12;
+        self.check_crc("source ROM", self.crc_at(end), crc32(rom))
+    }
+
+    fn check_target(&self, rom: &[u8]) -> Result<(), LoadError> {
+        let end = self.data.len() - 12;
+        self.check_crc("patched ROM", self.crc_at(end + 4), crc32(rom))
+    }
+
+    fn crc_at(&self, pos: usize) -> u32 {
+        let mut buf = [0u8; 4];
+        buf.copy_from_slice(&self.data[pos..pos + 4]);
+        u32::from_le_bytes(buf)
+    }
+
+    fn check_crc(&self, what: &'static str, expected: u32, actual: u32) -> Result<(), LoadError> {
+        if expected == actual {
+            return Ok(());
+        }
+        error::check(
+            self.lenient,
+            LoadError::PatchChecksum { path: self.path.to_string(), what, expected, actual },
+        )
+    }
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(data);
+    hasher.finalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vint(mut val: usize) -> Vec<u8> {
+        let mut out = vec![];
+        loop {
+            let x = (val & 0x7F) as u8;
+            val >>= 7;
+            if val == 0 {
+                out.push(x | 0x80);
+                return out;
+            }
+            out.push(x);
+            val -= 1;
+        }
+    }
+
+    // source/target/patchのCRC32を付ける
+    fn footer(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+        patch.extend_from_slice(&crc32(source).to_le_bytes());
+        patch.extend_from_slice(&crc32(target).to_le_bytes());
+        let crc = crc32(&patch);
+        patch.extend_from_slice(&crc.to_le_bytes());
+        patch
+    }
+
+    fn reason(result: Result<Vec<u8>, LoadError>) -> String {
+        match result {
+            Err(LoadError::Patch { reason, .. }) => reason,
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    fn checksum(result: Result<Vec<u8>, LoadError>) -> &'static str {
+        match result {
+            Err(LoadError::PatchChecksum { what, .. }) => what,
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn ips_record_rle_and_truncate() {
+        let mut patch = b"PATCH".to_vec();
+        patch.extend_from_slice(&[0x00, 0x00, 0x01, 0x00, 0x02, 0xAA, 0xBB]);
+        patch.extend_from_slice(&[0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x04, 0x11]);
+        patch.extend_from_slice(b"EOF");
+        patch.extend_from_slice(&[0x00, 0x00, 0x09]);
+
+        let rom = apply_patch("test.ips", &patch, vec![0; 8], false).unwrap();
+        assert_eq!(rom, [0x00, 0xAA, 0xBB, 0x00, 0x00, 0x00, 0x11, 0x11, 0x11]);
+    }
+
+    #[test]
+    fn ips_truncated_record() {
+        let patch = b"PATCH\x00\x00\x01\x00\x02\xAA";
+        assert_eq!(reason(apply_patch("test.ips", patch, vec![0; 8], false)), "truncated patch");
+    }
+
+    #[test]
+    fn ips_too_large() {
+        let patch = b"PATCH\x7F\xFF\xFF\x00\x00\xFF\xFF\x00EOF";
+        assert_eq!(reason(apply_patch("test.ips", patch, vec![], false)), "patched ROM is too large");
+    }
+
+    #[test]
+    fn ups_round_trip() {
+        let source = b"hello world".to_vec();
+        let target = b"hellO world!".to_vec();
+
+        let mut patch = b"UPS1".to_vec();
+        patch.extend(vint(source.len()));
+        patch.extend(vint(target.len()));
+        patch.extend(vint(4));
+        patch.extend_from_slice(&[b'o' ^ b'O', 0x00]);
+        patch.extend(vint(5));
+        patch.extend_from_slice(&[b'!', 0x00]);
+        let patch = footer(patch, &source, &target);
+
+        assert_eq!(apply_patch("test.ups", &patch, source, false).unwrap(), target);
+        assert_eq!(checksum(apply_patch("test.ups", &patch, b"hello_world".to_vec(), false)), "source ROM");
+        assert!(apply_patch("test.ups", &patch, b"hello_world".to_vec(), true).is_ok());
+    }
+
+    #[test]
+    fn bps_round_trip() {
+        let source = b"abcdefgh".to_vec();
+        let target = b"abcdXYghhhh".to_vec();
+        let cmd = |action: usize, len: usize| vint((len - 1) << 2 | action);
+
+        let mut patch = b"BPS1".to_vec();
+        patch.extend(vint(source.len()));
+        patch.extend(vint(target.len()));
+        patch.extend(vint(0));
+        // SourceRead "abcd"
+        patch.extend(cmd(0, 4));
+        // TargetRead "XY"
+        patch.extend(cmd(1, 2));
+        patch.extend_from_slice(b"XY");
+        // SourceCopy "gh" (+6)
+        patch.extend(cmd(2, 2));
+        patch.extend(vint(6 << 1));
+        // TargetCopy "hhh" (+7, 重なりあり)
+        patch.extend(cmd(3, 3));
+        patch.extend(vint(7 << 1));
+        let patch = footer(patch, &source, &target);
+
+        assert_eq!(apply_patch("test.bps", &patch, source.clone(), false).unwrap(), target);
+
+        let mut broken = patch.clone();
+        broken[10] ^= 0xFF;
+        assert_eq!(checksum(apply_patch("test.bps", &broken, source, false)), "patch file");
+    }
+
+    #[test]
+    fn truncated_header() {
+        assert_eq!(reason(apply_patch("test.ups", b"UPS1\x85", vec![], false)), "truncated patch");
+
+        // 終端のない可変長整数
+        let patch = footer([&b"BPS1"[..], &[0x7F; 16]].concat(), &[], &[]);
+        assert_eq!(reason(apply_patch("test.bps", &patch, vec![], false)), "broken number");
+    }
+
+    #[test]
+    fn oversized_header() {
+        let mut patch = b"BPS1".to_vec();
+        patch.extend(vint(0));
+        patch.extend(vint(usize::MAX >> 8));
+        patch.extend(vint(0));
+        let patch = footer(patch, &[], &[]);
+        assert_eq!(reason(apply_patch("test.bps", &patch, vec![], false)), "patched ROM is too large");
+    }
+}
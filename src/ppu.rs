@@ -1,6 +1,8 @@
 use common::*;
-// use  cgb::*;
+use cgb::CGB_MODE_NON_CGB;
 use bitvec::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_big_array::BigArray;
 
 // const VRAM_SIZE: usize = 8 * 1024;  // DMG
 const VRAM_SIZE: usize = 32 * 1024; // CGB (8KB * 2バンク)
@@ -13,6 +15,29 @@ const COLOR_DARK_GRAY: u8 = 0x55;
 const COLOR_BLACK: u8 = 0x00;
 const COLOR_WHITE: u8 = 0xFF;
 
+// [カラーパレット (CGB Only)]
+// リファレンスを翻訳＆図解すると
+// https://gbdev.io/pandocs/Palettes.html#palettes
+// BG/OBJの各カラーパレットは[8パレット×4色/パレット×2バイト/色= 64Byte]
+//
+// [カラーパレットの色番号]
+// |    0x00    |    0x01    |    0x02    |    0x03    |
+// |BGP0 色番号0|BGP0 色番号1|BGP0 色番号2|BGP0 色番号3|
+// ↓ =
+// [カラーパレットのデータ]
+// |    0x00    |    0x01    |    0x02    |    0x03    |
+// |BGP0 0の上位|BGP0 0の下位|BGP0 1の上位|BGP0 1の下位|
+//
+// 例）データ0x03はBGP0の色番号1の上位バイト(青と緑)
+//
+// [RGB555]
+// Bit 0-4   Red Intensity   ($00-1F)
+// Bit 5-9   Green Intensity ($00-1F)
+// Bit 10-14 Blue Intensity  ($00-1F)
+// |   Bit[15:12]   |  Bit[11:8]   |    Bit[7:4]    |    Bit[3:0]    |
+// | N/A |     青色 5bit     |     緑色 5bit     |     赤色 5bit     |
+const COLOR_PALETTE_SIZE: usize = 64;
+
 // [VRAM Mem Map]
 // **********************************
 // Bank 0
@@ -28,10 +53,11 @@ const COLOR_WHITE: u8 = 0xFF;
 // 0x8000-0x87FF: (調査中)
 // 0x8800-0x8FFF: (調査中)
 // 0x9000-0x97FF: (調査中)
-// 0x9800-0x9BFF: BG Map Attributes (※注)
-// 0x9C00-0x9FFF: (調査中)
+// 0x8000-0x97FF: Tile Set (BG Map Attributes Bit3 / OAM Bit3で選択)
+// 0x9800-0x9BFF: BG Map Attributes (Tile Map #1に対応) (※注)
+// 0x9C00-0x9FFF: BG Map Attributes (Tile Map #2に対応)
 // **********************************
-// (※注)は@get_bg_plt_num()に記載
+// (※注)は@fetch_bg_attr()に記載
 
 pub const _BG_MAP_ATTRIBUTE_SIZE: usize = 32 * 32;
 
@@ -43,15 +69,29 @@ pub struct RGB24Color {
     pub b: u8,
 }
 
-#[derive(Copy, Clone, PartialEq)]
+#[derive(Copy, Clone, PartialEq, Serialize, Deserialize)]
 enum BGPriority {
     Color0,
     Color123,
+    Color123Priority,   // (CGB Only) BG Map Attributes Bit7 = 1
+}
+
+// BG Map Attributes (CGB Only)
+#[derive(Copy, Clone, Default)]
+struct BGAttr {
+    plt_num: u8,        // BGパレット番号 (BGP0-7)
+    bank: u8,           // Tile VRAMバンク
+    flip_x: bool,
+    flip_y: bool,
+    priority: bool,     // BG-to-OAM Priority
 }
 
 #[allow(dead_code)]
+#[derive(Serialize, Deserialize)]
 pub struct PPU {
+    #[serde(with = "BigArray")]
     vram: [u8; VRAM_SIZE],            // VRAM
+    #[serde(with = "BigArray")]
     oam: [u8; OAM_SIZE],              // OAM
     lcdc: u8,                         // LCD Control
     stat: u8,                         // Status
@@ -67,27 +107,47 @@ pub struct PPU {
     wx: u8,                           // Window X Position minus 7
     pub irq_vblank: bool,             // V-Blank interrupt request
     pub irq_lcdc: bool,               // LCDC interrupt request
+    pub hblank_start: bool,           // Entered H-Blank (H-Blank DMA)
     cnt: u16,                         // Elapsed clocks in current mode
+    #[serde(with = "BigArray")]
     bg_prio: [BGPriority; SCREEN_W as usize],  // Background priority
 
 
     // DMG
+    #[serde(with = "BigArray")]
     frame_buffer: [u8; SCREEN_WH],    // Frame buffer
+    #[serde(with = "BigArray")]
     scanline: [u8; SCREEN_W as usize], // Current scanline
 
     // TODO CGBの時だけインスタンス生成
     // CGB
+    #[serde(with = "BigArray")]
     frame_buffer_col: [u16; SCREEN_WH],  // CGB対応　カラーフレームバッファ(u16 ... RGB555)
+    #[serde(with = "BigArray")]
     scanline_col: [u16; SCREEN_W as usize], // CGB対応　カラースキャンライン(u16 ... RGB555)
+    frame_buffer_rgb: Vec<u8>,  // 表示用フレームバッファ(RGB24、DMGはグレー) ※サイズが大きいのでヒープに置く
     pub cgb_mode: u8,                  // CGB動作モード (CGB Only)
     pub cgb_unlock_flg: bool,          // CGB動作フラグ (CGB Only)
+    pub opri: u8,                      // OBJ優先モード (CGB Only, Bit0: 0 = OAM順, 1 = X座標順)
     pub vram_bank: u8,                 // VRAM バンク (CGB Only)
-    pub p_bg_col_plt: *const u8,       // BGカラーパレットポインタ(CGB Only)
-    pub p_obj_col_plt: *const u8,      // OBJカラーパレットポインタ(CGB Only)
+    bgpi: u8,                          // (Addr $FF68 R/W) BGパレット オートインクリメント
+    bcps: u8,                          // (Addr $FF68 R/W) BGパレット インデックス
+    #[serde(with = "BigArray")]
+    bg_col_plt: [u8; COLOR_PALETTE_SIZE],  // (Addr $FF69 R/W) BCPD/BGPD
+    obpi: u8,                          // (Addr $FF6A R/W) OBJパレット オートインクリメント
+    ocps: u8,                          // (Addr $FF6A R/W) OBJパレット インデックス
+    #[serde(with = "BigArray")]
+    obj_col_plt: [u8; COLOR_PALETTE_SIZE], // (Addr $FF6B R/W) OCPD/OBPD
+}
+
+impl Default for PPU {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl PPU {
-    pub fn new(p_bg_col_plt: *const u8, p_obj_col_plt: *const u8) -> Self {
+    pub fn new() -> Self {
         PPU {
             vram: [0; VRAM_SIZE ],
             oam: [0; OAM_SIZE],
@@ -105,6 +165,7 @@ impl PPU {
             wx: 0,
             irq_vblank: false,
             irq_lcdc: false,
+            hblank_start: false,
             cnt: 0,
             bg_prio: [BGPriority::Color0; SCREEN_W as usize],
 
@@ -115,30 +176,59 @@ impl PPU {
             // CGB
             scanline_col: [0; SCREEN_W as usize],
             frame_buffer_col: [0; SCREEN_WH],
+            frame_buffer_rgb: vec![0; SCREEN_WH * 3],
             cgb_mode: 0,
             cgb_unlock_flg: false,
+            opri: 0,
             vram_bank: 0,
-            p_bg_col_plt: p_bg_col_plt,
-            p_obj_col_plt: p_obj_col_plt,
+            bgpi: 0,
+            bcps: 0,
+            bg_col_plt: [0; COLOR_PALETTE_SIZE],
+            obpi: 0,
+            ocps: 0,
+            obj_col_plt: [0; COLOR_PALETTE_SIZE],
         }
     }
 
     // (DMG, 非CGBモード専用): モノクロパレットの色変換
     // https://gbdev.io/pandocs/Palettes.html#ff47--bgp-non-cgb-mode-only-bg-palette-data
     fn map_color(&self, color_num: u8, palette: u8) -> u8 {
-        match (palette >> (color_num << 1)) & 0x03 {
+        match Self::map_shade(color_num, palette) {
             0 => COLOR_WHITE,        // 白
             1 => COLOR_LIGHT_GRAY,   // ライトグレー
             2 => COLOR_DARK_GRAY,    // ダークグレー
-            3 | _ => COLOR_BLACK,    // 黒
+            _ => COLOR_BLACK,        // 黒
         }
     }
 
+    // Returns the DMG palette color number (0~3) of the color number.
+    // (CGBのDMG互換モードでは、この番号でカラーパレット0/1の色を選ぶ)
+    fn map_shade(color_num: u8, palette: u8) -> u8 {
+        (palette >> (color_num << 1)) & 0x03
+    }
+
+    // Returns true if rendering in CGB mode (CGB functions unlocked with a CGB cartridge).
+    fn is_cgb_mode(&self) -> bool {
+        self.cgb_unlock_flg && self.cgb_mode != CGB_MODE_NON_CGB
+    }
+
+    // Returns true if palette RAM is accessible. (Mode 3の間はアクセスできない)
+    fn is_palette_accessible(&self) -> bool {
+        self.stat & 0x03 != 3
+    }
+
+    // (CGB Only): カラーパレットの色番号をRGB555に変換
+    // パレットデータはリトルエンディアン (下位バイトが先)
+    fn map_color_cgb(col_plt: &[u8], plt_num: u8, color_num: u8) -> u16 {
+        let idx = ((plt_num as usize) << 3) | ((color_num as usize) << 1);
+        (col_plt[idx + 1] as u16) << 8 | col_plt[idx] as u16
+    }
+
     // (※注)
     // BG Map Attributesについて理解したので翻訳メモ（https://gbdev.io/pandocs/Tile_Maps.html）
     //
     // [メモ] BG/OBJカラーパレット0~7のどのパレットを指定する方法は？
-    // PRG-ROMに仕込まれたBG Map AttributesがVRAM Bank1 $9C00~$9FFFにロードさせる
+    // PRG-ROMに仕込まれたBG Map AttributesがVRAM Bank1 $9800~$9FFFにロードさせる
     // サイズは32x32Byte(1024KB)で、下記のような構成でBit0がパレットの指定になる
     // ★VRAM Bank0 @ $9800~$9BFFは、VRAM Bank1 @ $9800~$9BFFが対応して色付けしてる、ここ重要！
     // [BG Map Attributesの構成(これが32x32ある)]
@@ -150,18 +240,23 @@ impl PPU {
     // Bit 3    Tile VRAM Bank number      (0=Bank 0, 1=Bank 1)
     // Bit 2-0  Background Palette number  (BGP0-7)
     // ================================================================================
-    #[allow(dead_code)]
-    fn get_bg_plt_num(&mut self, addr: u16) -> u8
-    {
-        // TODO BG Map AttributeからBGパレット番号の取得処理
-        let offset = VRAM_BANK_SIZE as usize * self.vram_bank as usize;
-        let bg_plt_num: u8 = self.vram[addr as usize + offset] & 0x07; // 0x07 = Bit 2-0
-        bg_plt_num
+    fn fetch_bg_attr(&self, tile_map_addr: u16) -> BGAttr {
+        if !self.is_cgb_mode() {
+            return BGAttr::default();
+        }
+
+        let attr = self.vram[tile_map_addr as usize + VRAM_BANK_SIZE as usize];
+        BGAttr {
+            plt_num: attr & 0x07,
+            bank: (attr & _BIT_3) >> 3,
+            flip_x: attr & _BIT_5 != 0,
+            flip_y: attr & _BIT_6 != 0,
+            priority: attr & _BIT_7 != 0,
+        }
     }
 
     // (GB/GBC共通、GBC専用) カラーパレット(RGB555)のRGB24変換
-    #[allow(dead_code)]
-    fn rgb555_to_rgb24(&mut self, rgb555: u16) -> RGB24Color {
+    fn rgb555_to_rgb24(&self, rgb555: u16) -> RGB24Color {
         let tmp = rgb555.view_bits::<Lsb0>();
         let r = tmp[0..=4].load::<u8>();
         let g = tmp[5..=9].load::<u8>();
@@ -174,55 +269,45 @@ impl PPU {
     }
 
     // Fetches tile data from VRAM.
-    fn fetch_tile(&self, tile_no: u8, offset_y: u8, tile_data_sel: bool) -> (u8, u8) {
+    fn fetch_tile(&self, tile_no: u8, offset_y: u8, tile_data_sel: bool, bank: u8) -> (u8, u8) {
         // Fetch tile data from tile set
         let tile_data_addr = if tile_data_sel {
             // Use tile set #1 (0x0000-0x07ff) and #2 (0x0800-0x0fff)
             (tile_no as u16) << 4
         } else {
             // Use tile set #2 (0x0800-0x0fff) and #3 (0x1000-0x17ff)
-            (0x1000 as u16).wrapping_add(((tile_no as i8 as i16) << 4) as u16)
+            0x1000_u16.wrapping_add(((tile_no as i8 as i16) << 4) as u16)
         };
         let row_addr = tile_data_addr + (offset_y << 1) as u16;
 
-        let mut _tile0: u8 = 0;
-        let mut _tile1: u8 = 0;
-        if self.cgb_unlock_flg != false {
-            let offset = VRAM_BANK_SIZE as usize * self.vram_bank as usize;
-            _tile0 = self.vram[row_addr as usize + offset];
-            _tile1 = self.vram[(row_addr + 1) as usize + offset];
-        }else{
-            _tile0 = self.vram[row_addr as usize];
-            _tile1 = self.vram[(row_addr + 1) as usize];
-        }
+        let offset = VRAM_BANK_SIZE as usize * bank as usize;
+        let tile0 = self.vram[row_addr as usize + offset];
+        let tile1 = self.vram[(row_addr + 1) as usize + offset];
 
-        (_tile0, _tile1)
+        (tile0, tile1)
     }
 
-    // Fetches BG or Window tile data from VRAM.
+    // Fetches BG or Window tile data and attributes from VRAM.
     fn fetch_bg_window_tile(
         &self,
         tile_x: u8,
         tile_y: u8,
         offset_y: u8,
         tile_map_base: u16,
-    ) -> (u8, u8) {
-        // Fetch tile index from tile map
+    ) -> ((u8, u8), BGAttr) {
+        // Fetch tile index from tile map (Tile MapはBank0、AttributesはBank1)
         let tile_map_addr = tile_map_base | ((tile_x & 0x1F) as u16 + ((tile_y as u16) << 5));
+        let tile_no = self.vram[tile_map_addr as usize];
+        let attr = self.fetch_bg_attr(tile_map_addr);
 
-        let mut _tile_no: u8 = 0;
-        if self.cgb_unlock_flg != false {
-            let offset = VRAM_BANK_SIZE as usize * self.vram_bank as usize;
-            _tile_no = self.vram[tile_map_addr as usize + offset];
-        }else{
-            _tile_no = self.vram[tile_map_addr as usize];
-        }
+        let offset_y = if attr.flip_y { 7 - offset_y } else { offset_y };
+        let tile = self.fetch_tile(tile_no, offset_y, self.lcdc & 0x10 > 0, attr.bank);
 
-        self.fetch_tile(_tile_no, offset_y, self.lcdc & 0x10 > 0)
+        (tile, attr)
     }
 
     // Fetches BG tile data from VRAM.
-    fn fetch_bg_tile(&self, tile_x: u8, tile_y: u8, offset_y: u8) -> (u8, u8) {
+    fn fetch_bg_tile(&self, tile_x: u8, tile_y: u8, offset_y: u8) -> ((u8, u8), BGAttr) {
         // Fetch tile index from tile map
         let tile_map_base = if self.lcdc & 0x8 > 0 { 0x1C00 } else { 0x1800 };
 
@@ -230,7 +315,7 @@ impl PPU {
     }
 
     // Fetches Window tile data from VRAM.
-    fn fetch_window_tile(&self, tile_x: u8, tile_y: u8, offset_y: u8) -> (u8, u8) {
+    fn fetch_window_tile(&self, tile_x: u8, tile_y: u8, offset_y: u8) -> ((u8, u8), BGAttr) {
         // Fetch tile index from tile map
         let tile_map_base = if self.lcdc & 0x40 > 0 { 0x1C00 } else { 0x1800 };
 
@@ -247,6 +332,18 @@ impl PPU {
 
     // Renders BG.
     fn render_bg(&mut self) {
+        let cgb_mode = self.is_cgb_mode();
+
+        // (DMG) LCDC.0 = 0 ... BG/Windowは白
+        if !cgb_mode && self.lcdc & 0x1 == 0 {
+            for x in 0..SCREEN_W as usize {
+                self.scanline[x] = COLOR_WHITE;
+                self.scanline_col[x] = 0x7FFF;
+                self.bg_prio[x] = BGPriority::Color0;
+            }
+            return;
+        }
+
         // Tile coordinate
         let mut tile_x = self.scx >> 3;
         let mut tile_y = self.scy.wrapping_add(self.ly) >> 3;
@@ -255,33 +352,48 @@ impl PPU {
         let mut offset_x = self.scx & 0x7;
         let mut offset_y = self.scy.wrapping_add(self.ly) & 0x7;
 
-        let mut tile = self.fetch_bg_tile(tile_x, tile_y, offset_y);
+        let (mut tile, mut attr) = self.fetch_bg_tile(tile_x, tile_y, offset_y);
 
         let mut window = false;
 
         for x in 0..SCREEN_W {
             // Check if window is enabled
-            if self.lcdc & 0x20 > 0 {
-                if self.wy <= self.ly && self.wx == x + 7 {
-                    tile_x = 0;
-                    tile_y = (self.ly - self.wy) >> 3;
-                    offset_x = 0;
-                    offset_y = (self.ly - self.wy) & 0x7;
-                    tile = self.fetch_window_tile(tile_x, tile_y, offset_y);
-                    window = true;
-                }
+            if self.lcdc & 0x20 > 0 && self.wy <= self.ly && self.wx == x + 7 {
+                tile_x = 0;
+                tile_y = (self.ly - self.wy) >> 3;
+                offset_x = 0;
+                offset_y = (self.ly - self.wy) & 0x7;
+                let (t, a) = self.fetch_window_tile(tile_x, tile_y, offset_y);
+                tile = t;
+                attr = a;
+                window = true;
             }
 
-            let color_num = self.get_color_num(tile, 7 - offset_x);
-            let color = self.map_color(color_num, self.bgp);
+            let bitpos = if attr.flip_x { offset_x } else { 7 - offset_x };
+            let color_num = self.get_color_num(tile, bitpos);
+
+            self.scanline[x as usize] = self.map_color(color_num, self.bgp);
+            if cgb_mode {
+                self.scanline_col[x as usize] =
+                    Self::map_color_cgb(&self.bg_col_plt, attr.plt_num, color_num);
+            } else if self.cgb_unlock_flg {
+                // (CGB DMG互換モード) BGPで選んだ色をBGパレット0で表示する
+                self.scanline_col[x as usize] =
+                    Self::map_color_cgb(&self.bg_col_plt, 0, Self::map_shade(color_num, self.bgp));
+            }
 
             self.bg_prio[x as usize] = if color_num == 0 {
                 BGPriority::Color0
+            } else if attr.priority {
+                BGPriority::Color123Priority
             } else {
                 BGPriority::Color123
             };
 
-            self.scanline[x as usize] = color;
+            // (CGB) LCDC.0 = 0 ... BG/Windowはマスタープライオリティを失う (OBJが常に手前)
+            if cgb_mode && self.lcdc & 0x1 == 0 {
+                self.bg_prio[x as usize] = BGPriority::Color0;
+            }
 
             offset_x += 1;
 
@@ -290,23 +402,43 @@ impl PPU {
                 offset_x = 0;
                 tile_x += 1;
 
-                if window {
-                    tile = self.fetch_window_tile(tile_x, tile_y, offset_y);
+                let (t, a) = if window {
+                    self.fetch_window_tile(tile_x, tile_y, offset_y)
                 } else {
-                    tile = self.fetch_bg_tile(tile_x, tile_y, offset_y);
-                }
+                    self.fetch_bg_tile(tile_x, tile_y, offset_y)
+                };
+                tile = t;
+                attr = a;
             }
         }
     }
 
     // Renders sprites.
     fn render_sprites(&mut self) {
-        let mut n_sprites = 0;
+        let cgb_mode = self.is_cgb_mode();
         let height = if self.lcdc & 0x4 > 0 { 16 } else { 8 };
 
-        for i in 0..40 {
+        // Up to 10 sprites can be rendered on one scanline (OAM順に選択)
+        let mut sprites: Vec<usize> = (0..40)
+            .map(|i| i << 2)
+            .filter(|&entry_addr| {
+                let sprite_y = self.oam[entry_addr];
+                sprite_y > self.ly + 16 - height && sprite_y <= self.ly + 16
+            })
+            .take(10)
+            .collect();
+
+        // 描画優先順位 CGB: OAM順、DMG: X座標が小さい順 (同じならOAM順)
+        // CGBでもOPRI Bit0 = 1 (DMG互換モード) ならX座標順
+        if !cgb_mode || self.opri & 0x01 != 0 {
+            sprites.sort_by_key(|&entry_addr| self.oam[entry_addr + 1]);
+        }
+
+        // 優先順位の高いOBJが不透明ピクセルを確保する
+        let mut drawn = [false; SCREEN_W as usize];
+
+        for entry_addr in sprites {
             // Parse OAM entry
-            let entry_addr = i << 2;
             let sprite_y = self.oam[entry_addr];
             let sprite_x = self.oam[entry_addr + 1];
             let flags = self.oam[entry_addr + 3];
@@ -319,17 +451,12 @@ impl PPU {
             } else {
                 self.obp0
             };
-
-            // Check if sprite is visible on this scanline
-            if sprite_y <= self.ly + 16 - height || sprite_y > self.ly + 16 {
-                continue;
-            }
-
-            // Up to 10 sprites can be rendered on one scanline
-            n_sprites += 1;
-            if n_sprites > 10 {
-                break;
-            }
+            // (CGB Only) Bit3: Tile VRAM Bank, Bit2-0: OBJパレット番号(OBP0-7)
+            let (bank, plt_num) = if cgb_mode {
+                ((flags & _BIT_3) >> 3, flags & 0x07)
+            } else {
+                (0, 0)
+            };
 
             // Check if sprite is within the screen
             if sprite_x == 0 || sprite_x > SCREEN_W + 8 - 1 {
@@ -357,7 +484,7 @@ impl PPU {
             };
 
             // Fetch tile data
-            let tile = self.fetch_tile(tile_no, offset_y, true);
+            let tile = self.fetch_tile(tile_no, offset_y, true, bank);
 
             for offset_x in 0..8 {
                 if offset_x + sprite_x < 8 {
@@ -372,39 +499,71 @@ impl PPU {
 
                 let bitpos = if flip_x { offset_x } else { 7 - offset_x };
                 let color_num = self.get_color_num(tile, bitpos);
-                if color_num == 0 {
+                if color_num == 0 || drawn[x as usize] {
                     continue;
                 }
-                if self.bg_prio[x as usize] == BGPriority::Color123 && obj_prio {
+                drawn[x as usize] = true;
+
+                let bg_wins = match self.bg_prio[x as usize] {
+                    BGPriority::Color0 => false,
+                    BGPriority::Color123 => obj_prio,
+                    BGPriority::Color123Priority => true,
+                };
+                if bg_wins {
                     continue;
                 }
-                let color = self.map_color(color_num, palette);
 
-                self.scanline[x as usize] = color;
+                self.scanline[x as usize] = self.map_color(color_num, palette);
+                if cgb_mode {
+                    self.scanline_col[x as usize] =
+                        Self::map_color_cgb(&self.obj_col_plt, plt_num, color_num);
+                } else if self.cgb_unlock_flg {
+                    // (CGB DMG互換モード) OBP0/OBP1で選んだ色をOBJパレット0/1で表示する
+                    let dmg_plt = (flags & 0x10) >> 4;
+                    self.scanline_col[x as usize] =
+                        Self::map_color_cgb(&self.obj_col_plt, dmg_plt, Self::map_shade(color_num, palette));
+                }
             }
         }
     }
 
     // Renders a scanline.
     fn render_scanline(&mut self) {
-        if self.lcdc & 0x1 > 0 {
-            self.render_bg();
-        }
+        self.render_bg();
         if self.lcdc & 0x2 > 0 {
             self.render_sprites();
         }
 
+        // CGBはDMG互換モードでもカラーパレットで表示する
+        let color = self.cgb_unlock_flg;
+
         for x in 0..SCREEN_W {
             let ix = (x as usize) + (self.ly as usize) * (SCREEN_W as usize);
             self.frame_buffer[ix] = self.scanline[x as usize];
+
+            let rgb = if color {
+                self.frame_buffer_col[ix] = self.scanline_col[x as usize];
+                self.rgb555_to_rgb24(self.scanline_col[x as usize])
+            } else {
+                let color = self.scanline[x as usize];
+                RGB24Color { r: color, g: color, b: color }
+            };
+            self.frame_buffer_rgb[ix * 3] = rgb.r;
+            self.frame_buffer_rgb[ix * 3 + 1] = rgb.g;
+            self.frame_buffer_rgb[ix * 3 + 2] = rgb.b;
         }
     }
 
-    // Returns the current contents of the frame buffer.
+    // Returns the current contents of the frame buffer. (Grayscale, 1Byte/pixel)
     pub fn frame_buffer(&self) -> &[u8] {
         &self.frame_buffer
     }
 
+    // Returns the current contents of the color frame buffer. (RGB24, 3Byte/pixel)
+    pub fn frame_buffer_rgb(&self) -> &[u8] {
+        &self.frame_buffer_rgb
+    }
+
     // Checks LYC interrupt.
     fn update_lyc_interrupt(&mut self) {
         // LYC=LY coincidence interrupt
@@ -441,7 +600,7 @@ impl IO for PPU {
             0x8000..=0x9FFF => {
                 // VRAM is inaccessible during pixel transfer
                 if self.stat & 0x03 != 3 {
-                    if self.cgb_unlock_flg != false {
+                    if self.cgb_unlock_flg {
                         let offset = VRAM_BANK_SIZE as usize * self.vram_bank as usize;
                         self.vram[(addr & 0x1FFF) as usize + offset] = val
                     }else{
@@ -481,12 +640,43 @@ impl IO for PPU {
                     self.update_lyc_interrupt();
                 }
             }
+            0xFF46 => self.dma = val,
             0xFF47 => self.bgp = val,
             0xFF48 => self.obp0 = val,
             0xFF49 => self.obp1 = val,
             0xFF4A => self.wy = val,
             0xFF4B => self.wx = val,
 
+            // (CGB Only) Color palette
+            0xFF68 => {
+                // BGPI(Bit7) = オートインクリメント、BCPS(Bit[5:0]) = パレットのインデックス
+                self.bgpi = (val & _BIT_7) >> 7;
+                self.bcps = val & 0x3F;
+            }
+            0xFF69 => {
+                // BCPD/BGPD (Mode 3中の書き込みは無視されるが、インクリメントはされる)
+                if self.is_palette_accessible() {
+                    self.bg_col_plt[self.bcps as usize] = val;
+                }
+                if self.bgpi != 0 {
+                    self.bcps = (self.bcps + 1) & 0x3F;
+                }
+            }
+            0xFF6A => {
+                // OBPI(Bit7) = オートインクリメント、OCPS(Bit[5:0]) = パレットのインデックス
+                self.obpi = (val & _BIT_7) >> 7;
+                self.ocps = val & 0x3F;
+            }
+            0xFF6B => {
+                // OCPD/OBPD (Mode 3中の書き込みは無視されるが、インクリメントはされる)
+                if self.is_palette_accessible() {
+                    self.obj_col_plt[self.ocps as usize] = val;
+                }
+                if self.obpi != 0 {
+                    self.ocps = (self.ocps + 1) & 0x3F;
+                }
+            }
+
             _ => unreachable!("Unexpected address: 0x{:04X}", addr),
         }
     }
@@ -497,7 +687,7 @@ impl IO for PPU {
             0x8000..=0x9FFF => {
                 // VRAM is inaccessible during pixel transfer
                 if self.stat & 0x03 != 3 {
-                    if self.cgb_unlock_flg != false {
+                    if self.cgb_unlock_flg {
                         let offset = VRAM_BANK_SIZE as usize * self.vram_bank as usize;
                         self.vram[(addr & 0x1FFF) as usize + offset]
                     }else{
@@ -532,6 +722,24 @@ impl IO for PPU {
             0xFF4A => self.wy,
             0xFF4B => self.wx,
 
+            // (CGB Only) Color palette (Bit6は未使用で常に1)
+            0xFF68 => self.bgpi << 7 | 0x40 | self.bcps,
+            0xFF69 => {
+                if self.is_palette_accessible() {
+                    self.bg_col_plt[self.bcps as usize]
+                } else {
+                    0xFF
+                }
+            }
+            0xFF6A => self.obpi << 7 | 0x40 | self.ocps,
+            0xFF6B => {
+                if self.is_palette_accessible() {
+                    self.obj_col_plt[self.ocps as usize]
+                } else {
+                    0xFF
+                }
+            }
+
             _ => unreachable!("Unexpected address: 0x{:04X}", addr),
         }
     }
@@ -558,7 +766,8 @@ impl IO for PPU {
                 if self.cnt >= 172 {
                     self.cnt -= 172;
                     // Transition to H-Blank mode
-                    self.stat = self.stat & 0xF8;
+                    self.stat &= 0xF8;
+                    self.hblank_start = true;
                     self.update_mode_interrupt();
                 }
             }
@@ -582,7 +791,7 @@ impl IO for PPU {
                 }
             }
             // V-Blank (4560 clocks or 10 lines)
-            1 | _ => {
+            _ => {
                 if self.cnt >= 456 {
                     self.cnt -= 456;
                     self.ly += 1;
@@ -0,0 +1,190 @@
+use std::f64::consts::PI;
+
+// [バンド制限リサンプラー]
+// APUの出力(1,048,576Hz、L/Rインターリーブ)をオーディオデバイスのサンプルレート(44.1kHz/48kHzなど)に変換する
+//
+// 入力をそのまま窓関数(Blackman)付きsincで補間する (カットオフは出力のナイキスト周波数 × CUTOFF)
+// 係数はポリフェーズテーブル(PHASES分割)に前計算しておく
+// (平均による間引きは100kHz以上の成分を折り返してしまうので使わない)
+const ZERO_CROSSINGS: f64 = 12.0;   // sincの片側のゼロ交差数
+const PHASES: usize = 256;          // ポリフェーズの分割数
+const CUTOFF: f64 = 0.9;
+
+pub struct Resampler {
+    step: f64,              // 出力1サンプルあたりの入力サンプル数
+    adjust: f64,            // 速度調整 (1.0 = 等速)
+    pos: f64,               // 次の出力サンプルの位置 (history内)
+    half: usize,            // フィルタの片側のタップ数
+    table: Vec<f32>,        // (PHASES + 1) × (half × 2)
+    history: Vec<[f32; 2]>, // 入力サンプル
+}
+
+impl Resampler {
+    pub fn new(in_rate: u32, out_rate: u32) -> Self {
+        let step = in_rate as f64 / out_rate as f64;
+
+        // 正規化カットオフ (入力のサンプルレート基準、ダウンサンプリングの時だけ下げる)
+        let fc = CUTOFF / step.max(1.0);
+        let half = (ZERO_CROSSINGS / fc).ceil() as usize;
+        let taps = half * 2;
+
+        let mut table = Vec::with_capacity((PHASES + 1) * taps);
+        for phase in 0..=PHASES {
+            let frac = phase as f64 / PHASES as f64;
+            let row: Vec<f64> = (0..taps)
+                .map(|k| {
+                    // 出力位置からタップkまでの距離
+                    let x = frac + (half - 1) as f64 - k as f64;
+                    sinc(fc * x) * blackman(x / half as f64)
+                })
+                .collect();
+
+            // DCゲインを1にする
+            let sum: f64 = row.iter().sum();
+            table.extend(row.iter().map(|&c| (c / sum) as f32));
+        }
+
+        Resampler {
+            step,
+            adjust: 1.0,
+            pos: (half - 1) as f64,
+            half,
+            table,
+            history: vec![[0.0; 2]; taps],
+        }
+    }
+
+    // Sets the rate adjustment. Values above 1.0 produce more output samples.
+    pub fn set_adjust(&mut self, adjust: f64) {
+        self.adjust = adjust;
+    }
+
+    // Resamples interleaved L/R samples and appends them to `out`.
+    pub fn process(&mut self, input: &[f32], out: &mut Vec<f32>) {
+        self.history.extend(input.chunks_exact(2).map(|s| [s[0], s[1]]));
+
+        let taps = self.half * 2;
+        let step = self.step / self.adjust;
+
+        // 窓の右端 (pos + half) までサンプルが揃っている間だけ出力する
+        while self.pos as usize + self.half < self.history.len() {
+            let i = self.pos as usize;
+            let phase = ((self.pos - i as f64) * PHASES as f64).round() as usize;
+            let coeffs = &self.table[phase * taps..(phase + 1) * taps];
+            let window = &self.history[i + 1 - self.half..i + 1 + self.half];
+
+            let mut left = 0.0;
+            let mut right = 0.0;
+            for (c, s) in coeffs.iter().zip(window) {
+                left += c * s[0];
+                right += c * s[1];
+            }
+            out.push(left);
+            out.push(right);
+
+            self.pos += step;
+        }
+
+        // 使い終わったサンプルを捨てる
+        let used = (self.pos as usize + 1).saturating_sub(self.half);
+        self.history.drain(..used);
+        self.pos -= used as f64;
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+// Blackman window (-1.0 <= x <= 1.0)
+fn blackman(x: f64) -> f64 {
+    0.42 + 0.5 * (PI * x).cos() + 0.08 * (2.0 * PI * x).cos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use apu::SAMPLE_RATE;
+
+    const OUT_RATE: u32 = 48000;
+
+    // L/Rインターリーブの正弦波 (1秒)
+    fn tone(freq: f64, amp: f32) -> Vec<f32> {
+        (0..SAMPLE_RATE as usize)
+            .flat_map(|i| {
+                let s = amp * (2.0 * PI * freq * i as f64 / SAMPLE_RATE as f64).sin() as f32;
+                vec![s, s]
+            })
+            .collect()
+    }
+
+    fn resample(input: &[f32]) -> Vec<f32> {
+        let mut resampler = Resampler::new(SAMPLE_RATE, OUT_RATE);
+        let mut out = Vec::new();
+        resampler.process(input, &mut out);
+        out
+    }
+
+    // 立ち上がりを除いた左チャンネルの最大振幅
+    fn peak(out: &[f32]) -> f32 {
+        out.iter().step_by(2).skip(1000).fold(0.0, |max, s| s.abs().max(max))
+    }
+
+    #[test]
+    fn output_rate() {
+        let out = resample(&vec![0.0; SAMPLE_RATE as usize * 2]);
+        let frames = out.len() / 2;
+        assert!((47900..=48100).contains(&frames), "{}", frames);
+    }
+
+    #[test]
+    fn adjust_changes_rate() {
+        let mut resampler = Resampler::new(SAMPLE_RATE, OUT_RATE);
+        resampler.set_adjust(1.01);
+        let mut out = Vec::new();
+        resampler.process(&vec![0.0; SAMPLE_RATE as usize * 2], &mut out);
+        let frames = out.len() / 2;
+        assert!((48380..=48580).contains(&frames), "{}", frames);
+    }
+
+    #[test]
+    fn dc_gain_is_one() {
+        let out = resample(&vec![0.5; SAMPLE_RATE as usize * 2]);
+        assert!(out.iter().skip(2000).all(|s| (s - 0.5).abs() < 1e-3));
+    }
+
+    #[test]
+    fn passes_audible_tone() {
+        let out = resample(&tone(1000.0, 0.5));
+        assert!((peak(&out) - 0.5).abs() < 0.01, "{}", peak(&out));
+    }
+
+    #[test]
+    fn removes_tone_above_nyquist() {
+        let out = resample(&tone(30000.0, 0.5));
+        assert!(peak(&out) < 0.01, "{}", peak(&out));
+
+        // 超音波の成分が折り返して可聴域に落ちてこないこと
+        for &freq in &[150000.0, 250000.0] {
+            let out = resample(&tone(freq, 0.5));
+            assert!(peak(&out) < 0.01, "{}Hz: {}", freq, peak(&out));
+        }
+    }
+
+    #[test]
+    fn chunked_input_gives_same_output() {
+        let input = tone(440.0, 0.25);
+        let whole = resample(&input);
+
+        let mut resampler = Resampler::new(SAMPLE_RATE, OUT_RATE);
+        let mut chunked = Vec::new();
+        for chunk in input.chunks(2 * 17477) {
+            resampler.process(chunk, &mut chunked);
+        }
+        assert_eq!(whole, chunked);
+    }
+}
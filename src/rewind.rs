@@ -0,0 +1,292 @@
+use std::collections::VecDeque;
+
+use emulator::Emulator;
+
+// [巻き戻し用リングバッファ]
+// 最新のスナップショットだけを完全な状態で持ち、それより古いスナップショットは
+// 「1つ新しいスナップショットとの差分(XOR + ゼロ連長圧縮)」として保持する
+//
+// | delta[0] (最古) | ... | delta[n-1] | latest (完全な状態) |
+//
+// 巻き戻す時は latest を返し、latest XOR delta[n-1] を新しい latest にする
+pub const REWIND_INTERVAL: u32 = 2;     // スナップショット間隔 (フレーム)
+pub const REWIND_CAPACITY: usize = 600; // 保持するスナップショット数 (2フレーム × 600 = 約20秒)
+
+pub struct Rewind {
+    interval: u32,
+    capacity: usize,
+    frame_cnt: u32,
+    rewind_cnt: u32,
+    latest: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,
+}
+
+impl Default for Rewind {
+    fn default() -> Self {
+        Self::new(REWIND_INTERVAL, REWIND_CAPACITY)
+    }
+}
+
+impl Rewind {
+    pub fn new(interval: u32, capacity: usize) -> Self {
+        Rewind {
+            interval: interval.max(1),
+            capacity: capacity.max(1),
+            frame_cnt: 0,
+            rewind_cnt: 0,
+            latest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    // Called once per emulated frame. Takes a snapshot every `interval` frames.
+    pub fn record(&mut self, emu: &Emulator) {
+        self.rewind_cnt = 0;
+        self.frame_cnt += 1;
+        if self.frame_cnt < self.interval {
+            return;
+        }
+        self.frame_cnt = 0;
+
+        match emu.save_state() {
+            Ok(state) => self.push(state),
+            Err(e) => warn!("Rewind snapshot failed: {}", e),
+        }
+    }
+
+    // Called once per displayed frame while rewinding.
+    // Steps back to the previous snapshot every `interval` frames. Returns false if there is no snapshot.
+    pub fn rewind(&mut self, emu: &mut Emulator) -> bool {
+        // 記録と同じ間隔で戻す (等速で巻き戻す)
+        if self.rewind_cnt > 0 {
+            self.rewind_cnt -= 1;
+            return true;
+        }
+
+        let state = match self.pop() {
+            Some(state) => state,
+            None => return false,
+        };
+        self.frame_cnt = 0;
+        self.rewind_cnt = self.interval - 1;
+
+        match emu.load_state(&state) {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("Rewind failed: {}", e);
+                false
+            }
+        }
+    }
+
+    // Pushes a new snapshot (serialized machine state).
+    pub fn push(&mut self, state: Vec<u8>) {
+        if let Some(prev) = self.latest.take() {
+            self.deltas.push_back(delta_encode(&state, &prev));
+            if self.deltas.len() >= self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+        self.latest = Some(state);
+    }
+
+    // Pops the newest snapshot.
+    // The oldest snapshot is kept so that holding the rewind key stays there.
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let delta = match self.deltas.pop_back() {
+            Some(delta) => delta,
+            None => return self.latest.clone(),
+        };
+
+        let latest = self.latest.take()?;
+        self.latest = Some(delta_decode(&latest, &delta));
+
+        Some(latest)
+    }
+
+    // Returns the number of snapshots.
+    pub fn len(&self) -> usize {
+        match self.latest {
+            Some(_) => self.deltas.len() + 1,
+            None => 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.frame_cnt = 0;
+        self.rewind_cnt = 0;
+        self.latest = None;
+        self.deltas.clear();
+    }
+}
+
+// [差分データの構成]
+// | target長 (u32 LE) | (一致バイト数 varint, 不一致バイト数 varint, XOR値 × 不一致バイト数) の繰り返し |
+fn delta_encode(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&(target.len() as u32).to_le_bytes());
+
+    let xor = |i: usize| target[i] ^ base.get(i).copied().unwrap_or(0);
+
+    let mut i = 0;
+    while i < target.len() {
+        let start = i;
+        while i < target.len() && xor(i) == 0 {
+            i += 1;
+        }
+        let skip = i - start;
+
+        let start = i;
+        while i < target.len() && xor(i) != 0 {
+            i += 1;
+        }
+
+        write_varint(&mut out, skip);
+        write_varint(&mut out, i - start);
+        out.extend((start..i).map(xor));
+    }
+
+    out
+}
+
+fn delta_decode(base: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut len = [0u8; 4];
+    len.copy_from_slice(&delta[0..4]);
+    let len = u32::from_le_bytes(len) as usize;
+
+    let mut out = base.to_vec();
+    out.resize(len, 0);
+
+    let mut pos = 0;
+    let mut i = 4;
+    while i < delta.len() {
+        pos += read_varint(delta, &mut i);
+        let n = read_varint(delta, &mut i);
+        for val in &delta[i..i + n] {
+            out[pos] ^= val;
+            pos += 1;
+        }
+        i += n;
+    }
+
+    out
+}
+
+fn write_varint(out: &mut Vec<u8>, mut val: usize) {
+    while val >= 0x80 {
+        out.push((val as u8) | 0x80);
+        val >>= 7;
+    }
+    out.push(val as u8);
+}
+
+fn read_varint(buf: &[u8], i: &mut usize) -> usize {
+    let mut val = 0;
+    let mut shift = 0;
+    loop {
+        let b = buf[*i];
+        *i += 1;
+        val |= ((b & 0x7F) as usize) << shift;
+        if b & 0x80 == 0 {
+            return val;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bios::BIOS;
+    use cartridge::tests::cartridge;
+    use emulator::tests::with_large_stack;
+
+    fn round_trip(base: &[u8], target: &[u8]) {
+        assert_eq!(delta_decode(base, &delta_encode(base, target)), target);
+    }
+
+    #[test]
+    fn delta_round_trip() {
+        let base: Vec<u8> = (0..1000).map(|i| (i * 7) as u8).collect();
+        let mut target = base.clone();
+        target[0] ^= 0x01;
+        target[500..700].iter_mut().for_each(|b| *b = 0xAA);
+        target[999] = 0x00;
+
+        round_trip(&base, &base);
+        round_trip(&base, &target);
+        // 長さが変わる時
+        round_trip(&base, &target[..300]);
+        round_trip(&target[..300], &base);
+        round_trip(&[], &base);
+        round_trip(&base, &[]);
+    }
+
+    #[test]
+    fn delta_of_same_data_is_small() {
+        let state = vec![0x55; 100_000];
+        assert!(delta_encode(&state, &state).len() < 16);
+    }
+
+    #[test]
+    fn pop_returns_newest_first() {
+        let mut rewind = Rewind::new(1, 10);
+        assert!(rewind.is_empty());
+        assert_eq!(rewind.pop(), None);
+
+        for i in 0..5u8 {
+            rewind.push(vec![i; 64 + i as usize]);
+        }
+        assert_eq!(rewind.len(), 5);
+
+        for i in (1..5u8).rev() {
+            assert_eq!(rewind.pop(), Some(vec![i; 64 + i as usize]));
+        }
+        // 最古のスナップショットは残る
+        assert_eq!(rewind.pop(), Some(vec![0; 64]));
+        assert_eq!(rewind.pop(), Some(vec![0; 64]));
+        assert_eq!(rewind.len(), 1);
+    }
+
+    #[test]
+    fn capacity_drops_oldest() {
+        let mut rewind = Rewind::new(1, 3);
+        for i in 0..10u8 {
+            rewind.push(vec![i; 8]);
+        }
+        assert_eq!(rewind.len(), 3);
+
+        assert_eq!(rewind.pop(), Some(vec![9; 8]));
+        assert_eq!(rewind.pop(), Some(vec![8; 8]));
+        assert_eq!(rewind.pop(), Some(vec![7; 8]));
+        assert_eq!(rewind.pop(), Some(vec![7; 8]));
+
+        rewind.clear();
+        assert!(rewind.is_empty());
+    }
+
+    #[test]
+    fn rewinds_at_recording_pace() {
+        with_large_stack(|| {
+            let mut emu = Emulator::from_parts(BIOS::none(), cartridge(0x00, 0, 0, &[0x18, 0xFE]), None);
+            let mut rewind = Rewind::new(2, 10);
+            for _ in 0..6 {
+                emu.run_frame();
+                rewind.record(&emu);
+            }
+            assert_eq!(rewind.len(), 3);
+
+            // 2フレームごとに1つ戻る
+            let mut lens = Vec::new();
+            for _ in 0..4 {
+                assert!(rewind.rewind(&mut emu));
+                lens.push(rewind.len());
+            }
+            assert_eq!(lens, [2, 2, 1, 1]);
+        });
+    }
+}
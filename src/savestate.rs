@@ -0,0 +1,177 @@
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::{Read, Write};
+
+use bincode;
+use bincode::Options;
+use serde::{Deserialize, Serialize};
+
+use cartridge::Cartridge;
+use cpu::CPU;
+
+// [セーブステートのファイル構成]
+// |    ヘッダー (StateHeader)    |    マシン全体の状態 (CPU + MMU + 各ペリフェラル)    |
+// 両方ともbincodeでシリアライズする
+// ※ ROMデータはセーブステートに含めない（ロード時に現在のカートリッジから引き継ぐ）
+pub const STATE_MAGIC: [u8; 8] = *b"RSGBSTAT";
+// 状態を持つ構造体のフィールドを変更したらインクリメントすること
+pub const STATE_VERSION: u32 = 1;
+
+#[derive(Debug)]
+pub enum StateError {
+    Io(io::Error),
+    Format(bincode::Error),
+    BadMagic,
+    VersionMismatch { found: u32, expected: u32 },
+    RomMismatch { found: String, expected: String },
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::Io(e) => write!(f, "I/O error: {}", e),
+            StateError::Format(e) => write!(f, "Broken save state: {}", e),
+            StateError::BadMagic => write!(f, "Not a save state file"),
+            StateError::VersionMismatch { found, expected } => write!(
+                f,
+                "Save state version mismatch (found: {}, expected: {})",
+                found, expected
+            ),
+            StateError::RomMismatch { found, expected } => write!(
+                f,
+                "Save state is for another ROM (found: {}, expected: {})",
+                found, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+impl From<io::Error> for StateError {
+    fn from(e: io::Error) -> Self {
+        StateError::Io(e)
+    }
+}
+
+impl From<bincode::Error> for StateError {
+    fn from(e: bincode::Error) -> Self {
+        StateError::Format(e)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StateHeader {
+    pub magic: [u8; 8],
+    pub version: u32,
+    pub title: String,          // ROMタイトル ($0134-$0143)
+    pub header_chksum: u8,      // ヘッダーチェックサム ($014D)
+    pub global_chksum: u16,     // グローバルチェックサム ($014E-$014F)
+}
+
+impl StateHeader {
+    pub fn new(cartridge: &Cartridge) -> Self {
+        StateHeader {
+            magic: STATE_MAGIC,
+            version: STATE_VERSION,
+            title: cartridge.title(),
+            header_chksum: cartridge.header_checksum(),
+            global_chksum: cartridge.global_checksum(),
+        }
+    }
+
+    // Checks that the save state is compatible with the current ROM.
+    fn verify(&self, cartridge: &Cartridge) -> Result<(), StateError> {
+        if self.magic != STATE_MAGIC {
+            return Err(StateError::BadMagic);
+        }
+
+        if self.version != STATE_VERSION {
+            return Err(StateError::VersionMismatch {
+                found: self.version,
+                expected: STATE_VERSION,
+            });
+        }
+
+        let expected = StateHeader::new(cartridge);
+        if self.title != expected.title
+            || self.header_chksum != expected.header_chksum
+            || self.global_chksum != expected.global_chksum
+        {
+            return Err(StateError::RomMismatch {
+                found: format!("{} ({:#06X})", self.title, self.global_chksum),
+                expected: format!("{} ({:#06X})", expected.title, expected.global_chksum),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+// Serializes the whole machine.
+pub fn save_state(cpu: &CPU) -> Result<Vec<u8>, StateError> {
+    let mut buf = bincode::serialize(&StateHeader::new(&cpu.mmu.cartridge))?;
+    bincode::serialize_into(&mut buf, cpu)?;
+
+    Ok(buf)
+}
+
+// Checks the magic and version from the raw bytes before decoding anything.
+// (バージョンの違うデータや壊れたデータをbincodeに渡さないように)
+fn check_prefix(data: &[u8]) -> Result<(), StateError> {
+    if data.len() < 12 || data[..8] != STATE_MAGIC {
+        return Err(StateError::BadMagic);
+    }
+
+    let mut version = [0u8; 4];
+    version.copy_from_slice(&data[8..12]);
+    let version = u32::from_le_bytes(version);
+    if version != STATE_VERSION {
+        return Err(StateError::VersionMismatch { found: version, expected: STATE_VERSION });
+    }
+
+    Ok(())
+}
+
+// Restores the whole machine from serialized data.
+pub fn load_state(cpu: &mut CPU, data: &[u8]) -> Result<(), StateError> {
+    check_prefix(data)?;
+
+    // bincode::serialize と同じ形式 (固定長整数) で、データの長さを超える確保はしない
+    let options = bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .with_limit(data.len() as u64);
+    let mut reader = data;
+
+    let header: StateHeader = options.deserialize_from(&mut reader)?;
+    header.verify(&cpu.mmu.cartridge)?;
+
+    let mut state: CPU = options.deserialize_from(&mut reader)?;
+    state.mmu.restore_from(&mut cpu.mmu);
+    *cpu = state;
+
+    Ok(())
+}
+
+// Writes a save state file.
+pub fn save_state_file(cpu: &CPU, fname: &str) -> Result<(), StateError> {
+    info!("Writing save state to: {}", fname);
+
+    let buf = save_state(cpu)?;
+    let mut file = File::create(fname)?;
+    file.write_all(&buf)?;
+
+    Ok(())
+}
+
+// Reads a save state file.
+pub fn load_state_file(cpu: &mut CPU, fname: &str) -> Result<(), StateError> {
+    info!("Reading save state from: {}", fname);
+
+    let mut buf = Vec::new();
+    let mut file = File::open(fname)?;
+    file.read_to_end(&mut buf)?;
+
+    load_state(cpu, &buf)
+}
@@ -1,18 +1,27 @@
+use serde::{Deserialize, Serialize};
 use common::*;
 
 const _CLOCK_SPEED_NORMAL: u8 = 0;
 const _CLOCK_SPEED_FAST: u8 = 1;
 
-const _SHIFT_CLOCK_INTERNAL: u8 = 0;
-const _SHIFT_CLOCK_EXTERNAL: u8 = 1;
+const _SHIFT_CLOCK_EXTERNAL: u8 = 0;
+const _SHIFT_CLOCK_INTERNAL: u8 = 1;
+
+// 1Bit転送するクロック数 (内部クロック 8192Hz, CGB高速 262144Hz)
+// ※ CPUのクロックで数えるので、倍速モードでは転送も倍速になる
+const BIT_TICKS_NORMAL: u16 = 512;
+const BIT_TICKS_FAST: u16 = 16;
 
 // TODO SPIの実装(Master, Slave)
 // https://gbdev.io/pandocs/Serial_Data_Transfer_(Link_Cable).html#serial-data-transfer-link-cable
+// 通信相手はいないので、受信データは常に1 (8Bit転送後のSBは$FF)
 #[allow(dead_code)]
+#[derive(Serialize, Deserialize)]
 pub struct Serial {
     sb: u8,               // SB(Serial転送データレジスタ)
     sc: u8,               // SC(Serial転送制御レジスタ)
-    tick: u8,             // SPI クロック
+    tick: u16,            // SPI クロック
+    bits: u8,             // 残りの転送Bit数
     pub irq: bool,        // IRQ
 
     // SC Bit
@@ -21,6 +30,12 @@ pub struct Serial {
     shift_clock: u8,     // SC Bit0: Shift Clock (0 = 外部クロック, 1 = 内部クロック)
 }
 
+impl Default for Serial {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[allow(dead_code)]
 impl Serial {
     pub fn new() -> Self {
@@ -28,48 +43,69 @@ impl Serial {
             sb: 0,
             sc: 0,
             tick: 0,
+            bits: 0,
             irq: false,
 
             is_start_req: false,
             clock_speed: _CLOCK_SPEED_NORMAL,
-            shift_clock: _SHIFT_CLOCK_INTERNAL,
+            shift_clock: _SHIFT_CLOCK_EXTERNAL,
         }
     }
 
-    fn clock_speed_change(&mut self) {
-        // TODO :SPI IRQ
-        self.irq = true;
+    fn bit_ticks(&self) -> u16 {
+        if self.clock_speed == _CLOCK_SPEED_FAST {
+            BIT_TICKS_FAST
+        } else {
+            BIT_TICKS_NORMAL
+        }
     }
 
     fn spi_tx(&mut self, _val: u8) {
         // TODO :SPI TX
     }
 
-    fn spi_rx(&self) -> u8{
-        // TODO :SPI RX
-        0xFF
+    fn spi_rx(&self) -> u8 {
+        // TODO :SPI RX (通信相手がいなければHigh)
+        0x01
     }
 
+    // SPI 割込み (8Bit転送完了)
+    // https://gbdev.io/pandocs/Interrupt_Sources.html#int-58--serial-interrupt
     fn spi_irq(&mut self) {
-        // TODO :SPI IRQ
+        self.is_start_req = false;
+        self.sc &= !_BIT_7;
         self.irq = true;
     }
 
-    // SPI 割込みハンドラ
-    // https://gbdev.io/pandocs/Interrupt_Sources.html#int-58--serial-interrupt
-    fn spi_irq_handler(&mut self) {
-        self.spi_tx(self.sb);
-        self.irq = false;
-        // TODO $0058にJMPする???
+    // Shifts one bit out of SB (MSB first) and one bit in.
+    fn shift(&mut self) {
+        self.spi_tx(self.sb >> 7);
+        self.sb = (self.sb << 1) | self.spi_rx();
+        self.bits -= 1;
+
+        if self.bits == 0 {
+            self.spi_irq();
+        }
     }
 }
 
 #[allow(dead_code)]
 impl IO for Serial {
-    fn write(&mut self, addr: u16, _val: u8) {
+    fn write(&mut self, addr: u16, val: u8) {
         match addr {
-            0xFF01 => self.sb = _val,
-            0xFF02 => self.sc = _val,
+            0xFF01 => self.sb = val,
+            0xFF02 => {
+                self.sc = val;
+                self.is_start_req = (self.sc & _BIT_7) != 0;
+                self.clock_speed = (self.sc & _BIT_1) >> 1;
+                self.shift_clock = self.sc & _BIT_0;
+
+                // 転送開始
+                if self.is_start_req {
+                    self.bits = 8;
+                    self.tick = 0;
+                }
+            }
             _ => panic!("[ERR] Serial Write Only! (Addr: ${:#04X})", addr),
         }
     }
@@ -77,20 +113,22 @@ impl IO for Serial {
     fn read(&mut self, addr: u16) -> u8 {
         match addr {
             0xFF01 => self.sb,
-            0xFF02 => self.sc,
+            // 未使用Bitは1
+            0xFF02 => self.sc | 0x7C,
             _ => panic!("[ERR] Serial Read Addr ${:#04X}", addr),
         }
     }
 
-    fn update(&mut self, _tick: u8) {
-        // SC(Serial転送制御レジスタ)
-        self.is_start_req = (self.sc & _BIT_7) != 0;
-        self.clock_speed = (self.sc & _BIT_1) >> 1;
-        self.shift_clock = self.sc & _BIT_0;
+    fn update(&mut self, tick: u8) {
+        // 外部クロックは通信相手がいないので進まない
+        if !self.is_start_req || self.shift_clock != _SHIFT_CLOCK_INTERNAL {
+            return;
+        }
 
-        // IRQ
-        if self.is_start_req != false {
-            self.spi_irq();
+        self.tick += tick as u16;
+        while self.is_start_req && self.tick >= self.bit_ticks() {
+            self.tick -= self.bit_ticks();
+            self.shift();
         }
     }
 }
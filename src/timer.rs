@@ -1,3 +1,4 @@
+use serde::{Deserialize, Serialize};
 use common::IO;
 
 const DIV_ADDR: u16 = 0xFF04;
@@ -5,12 +6,21 @@ const TIMA_ADDR: u16 = 0xFF05;
 const TMA_ADDR: u16 = 0xFF06;
 const TAC_ADDR: u16 = 0xFF07;
 
+#[derive(Serialize, Deserialize)]
 pub struct Timer {
     tima: u8,       // TIMA (Timer Counter)
     tma: u8,        // TMA (Timer Modulo)
     tac: u8,        // TAC (Timer control)
     cnt: u16,       // 16bit カウント値
     pub irq: bool,  // IRQ
+    pub div_apu: u8, // DIV-APUイベント数 (DIV Bit4の立ち下がり、APUのフレームシーケンサを進める)
+    pub double_speed: bool, // CGB倍速モード (DIV-APUはDIV Bit5の立ち下がり)
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Timer {
@@ -21,14 +31,36 @@ impl Timer {
             tac: 0,
             cnt: 0,
             irq: false,
+            div_apu: 0,
+            double_speed: false,
         }
     }
+
+    // Sets the internal 16bit counter (upper 8bit = DIV).
+    pub fn set_counter(&mut self, cnt: u16) {
+        self.cnt = cnt;
+    }
+
+    // Returns the bit of the internal counter that clocks DIV-APU.
+    // 倍速モードでもフレームシーケンサが512Hzになるように、1つ上のBitを使う
+    fn div_apu_bit(&self) -> u32 {
+        if self.double_speed { 13 } else { 12 }
+    }
+
+    // Resets the internal counter. (DIVへの書き込み, STOP)
+    pub fn reset_counter(&mut self) {
+        // DIV-APUのBitが立っていればリセットで立ち下がる
+        if self.cnt & (1 << self.div_apu_bit()) != 0 {
+            self.div_apu += 1;
+        }
+        self.cnt = 0;
+    }
 }
 
 impl IO for Timer {
     fn write(&mut self, addr: u16, val: u8) {
         match addr {
-            DIV_ADDR => self.cnt = 0,
+            DIV_ADDR => self.reset_counter(),
             TIMA_ADDR => self.tima = val,
             TMA_ADDR => self.tma = val,
             TAC_ADDR => self.tac = val & 0x7,
@@ -51,12 +83,16 @@ impl IO for Timer {
 
         self.cnt = self.cnt.wrapping_add(tick as u16);
 
+        // Bit12の立ち下がり = 0x2000の境界をまたいだ回数 (倍速モードはBit13、0x4000の境界)
+        let shift = self.div_apu_bit() + 1;
+        self.div_apu += ((self.cnt >> shift).wrapping_sub(counter_prev >> shift) & 0x07) as u8;
+
         if self.tac & 4 > 0 {
             let divider = match self.tac & 3 {
                 0 => 10,
                 1 => 4,
                 2 => 6,
-                3 | _ => 8,
+                _ => 8,
             };
 
             let x = self.cnt >> divider;